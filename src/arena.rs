@@ -0,0 +1,123 @@
+use std::ops::Range;
+
+// hands out ranges of `0..capacity`, taking the first free range that's big
+// enough. freed ranges are merged with the free ranges on either side, so
+// a buffer split up by meshes that come and go can still fit a big one once
+// its neighbours are gone.
+#[derive(Debug)]
+pub struct RangeAllocator {
+    // sorted, never empty and never touching each other
+    free: Vec<Range<usize>>,
+}
+
+impl RangeAllocator {
+    pub fn new(capacity: usize) -> RangeAllocator {
+        RangeAllocator {
+            free: if capacity > 0 {
+                vec![0..capacity]
+            } else {
+                Vec::new()
+            },
+        }
+    }
+
+    pub fn alloc(&mut self, len: usize) -> Option<Range<usize>> {
+        let i = match self
+            .free
+            .iter()
+            .position(|free| free.end - free.start >= len)
+        {
+            Some(i) => i,
+            None => return None,
+        };
+        let start = self.free[i].start;
+        self.free[i].start += len;
+        if self.free[i].start == self.free[i].end {
+            self.free.remove(i);
+        }
+        Some(start..start + len)
+    }
+
+    pub fn free(&mut self, range: Range<usize>) {
+        if range.start == range.end {
+            return;
+        }
+        let i = match self
+            .free
+            .binary_search_by_key(&range.start, |free| free.start)
+        {
+            Ok(i) | Err(i) => i,
+        };
+        debug_assert!(i == 0 || self.free[i - 1].end <= range.start);
+        debug_assert!(i == self.free.len() || range.end <= self.free[i].start);
+
+        let joins_previous = i > 0 && self.free[i - 1].end == range.start;
+        let joins_next = i < self.free.len() && self.free[i].start == range.end;
+        match (joins_previous, joins_next) {
+            (true, true) => {
+                self.free[i - 1].end = self.free[i].end;
+                self.free.remove(i);
+            }
+            (true, false) => self.free[i - 1].end = range.end,
+            (false, true) => self.free[i].start = range.start,
+            (false, false) => self.free.insert(i, range),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_come_from_the_front() {
+        let mut ranges = RangeAllocator::new(10);
+        assert_eq!(ranges.alloc(4), Some(0..4));
+        assert_eq!(ranges.alloc(4), Some(4..8));
+        assert_eq!(ranges.alloc(3), None);
+        assert_eq!(ranges.alloc(2), Some(8..10));
+        assert_eq!(ranges.alloc(1), None);
+        assert!(ranges.free.is_empty());
+
+        assert_eq!(RangeAllocator::new(0).alloc(1), None);
+    }
+
+    #[test]
+    fn freed_ranges_are_reused() {
+        let mut ranges = RangeAllocator::new(10);
+        let a = ranges.alloc(3).unwrap();
+        let b = ranges.alloc(3).unwrap();
+        let c = ranges.alloc(3).unwrap();
+
+        ranges.free(b);
+        assert_eq!(ranges.free, vec![3..6, 9..10]);
+        // too big for the hole, goes nowhere
+        assert_eq!(ranges.alloc(4), None);
+        assert_eq!(ranges.alloc(2), Some(3..5));
+        assert_eq!(ranges.alloc(2), None);
+
+        ranges.free(a);
+        ranges.free(c);
+        assert_eq!(ranges.free, vec![0..3, 5..10]);
+        assert_eq!(ranges.alloc(5), Some(5..10));
+    }
+
+    #[test]
+    fn neighbours_merge() {
+        let mut ranges = RangeAllocator::new(12);
+        let parts = (0..4).map(|_| ranges.alloc(3).unwrap()).collect::<Vec<_>>();
+
+        // on its own, then joining the one after it, the one before it and
+        // finally both
+        ranges.free(parts[1].clone());
+        assert_eq!(ranges.free, vec![3..6]);
+        ranges.free(parts[0].clone());
+        assert_eq!(ranges.free, vec![0..6]);
+        ranges.free(parts[3].clone());
+        assert_eq!(ranges.free, vec![0..6, 9..12]);
+        ranges.free(parts[2].clone());
+        assert_eq!(ranges.free, vec![0..12]);
+
+        assert_eq!(ranges.alloc(12), Some(0..12));
+    }
+}
@@ -0,0 +1,350 @@
+use image;
+use image::FilterType;
+use image::ImageResult;
+use image::RgbaImage;
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// every texture gets scaled to this size, keeping it a power of two means the
+// tiles never mix with each other in the mipmaps
+pub const TILE_SIZE: u32 = 16;
+
+// atlas space rectangle as [u, v, width, height]
+pub type TileRect = [f32; 4];
+
+// rgba8 pixels of every mip level, largest first
+pub struct AtlasImage {
+    pub width: u32,
+    pub height: u32,
+    pub levels: Vec<Vec<u8>>,
+}
+
+pub struct Atlas {
+    rects: HashMap<String, TileRect>,
+}
+
+impl Atlas {
+    // an atlas without any textures, every lookup ends up on the white tile
+    pub fn new() -> Atlas {
+        Atlas {
+            rects: HashMap::new(),
+        }
+    }
+
+    // builds the atlas from every image in `dir`, each is named after its file
+    // stem, so `assets/blocks/stone.png` becomes "stone"
+    pub fn load<P: AsRef<Path>>(dir: P) -> ImageResult<(Atlas, AtlasImage)> {
+        let mut entries = fs::read_dir(dir)?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        // sorted so the layout doesn't depend on the directory order
+        entries.sort();
+
+        let mut images = Vec::new();
+        for path in entries {
+            let name = match path.file_stem().and_then(|stem| stem.to_str()) {
+                Some(name) if path.is_file() => name.to_string(),
+                _ => continue,
+            };
+            let image = image::open(&path)?.to_rgba();
+            images.push((name, image));
+        }
+
+        Ok(Atlas::from_images(images))
+    }
+
+    // lays the tiles out on a square grid, the first tile is solid white and
+    // sits at the origin so meshes without texture coordinates sample white
+    pub fn from_images(images: Vec<(String, RgbaImage)>) -> (Atlas, AtlasImage) {
+        let white = RgbaImage::from_pixel(TILE_SIZE, TILE_SIZE, image::Rgba([255; 4]));
+        let mut missing = RgbaImage::new(TILE_SIZE, TILE_SIZE);
+        for (x, y, pixel) in missing.enumerate_pixels_mut() {
+            let checker = (x / (TILE_SIZE / 2) + y / (TILE_SIZE / 2)) % 2 == 0;
+            *pixel = if checker {
+                image::Rgba([255, 0, 255, 255])
+            } else {
+                image::Rgba([0, 0, 0, 255])
+            };
+        }
+
+        let mut tiles = vec![
+            ("white".to_string(), white),
+            ("missing".to_string(), missing),
+        ];
+        tiles.extend(images);
+
+        let mut columns = 1;
+        while columns * columns < tiles.len() as u32 {
+            columns *= 2;
+        }
+        let size = columns * TILE_SIZE;
+
+        let mut atlas_image = RgbaImage::new(size, size);
+        let mut rects = HashMap::new();
+        for (i, (name, tile)) in tiles.into_iter().enumerate() {
+            let tile = if tile.dimensions() != (TILE_SIZE, TILE_SIZE) {
+                image::imageops::resize(&tile, TILE_SIZE, TILE_SIZE, FilterType::Nearest)
+            } else {
+                tile
+            };
+
+            let column = i as u32 % columns;
+            let row = i as u32 / columns;
+            image::imageops::overlay(&mut atlas_image, &tile, column * TILE_SIZE, row * TILE_SIZE);
+
+            let tile_size = 1.0 / columns as f32;
+            rects.insert(
+                name,
+                [
+                    column as f32 * tile_size,
+                    row as f32 * tile_size,
+                    tile_size,
+                    tile_size,
+                ],
+            );
+        }
+
+        let atlas = Atlas { rects: rects };
+        let image = AtlasImage {
+            width: size,
+            height: size,
+            levels: mip_levels(atlas_image.into_raw(), size),
+        };
+        (atlas, image)
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.rects.contains_key(name)
+    }
+
+    pub fn rect(&self, name: &str) -> TileRect {
+        match self.rects.get(name).or_else(|| self.rects.get("missing")) {
+            Some(&rect) => rect,
+            None => [0.0; 4],
+        }
+    }
+}
+
+impl Default for Atlas {
+    fn default() -> Atlas {
+        Atlas::new()
+    }
+}
+
+// box filtered mip chain down to one pixel per tile, going further would
+// start blending neighbouring tiles together. the texture is sampled as
+// sRGB, so colours are averaged in linear space, and weighted by alpha so
+// that the colour of transparent texels doesn't bleed into the rest.
+fn mip_levels(base: Vec<u8>, size: u32) -> Vec<Vec<u8>> {
+    let linear = (0..256)
+        .map(|value| srgb_to_linear(value as u8))
+        .collect::<Vec<_>>();
+    let mut levels = vec![base];
+    let mut size = size;
+    let mut tile = TILE_SIZE;
+    while tile > 1 {
+        let half = size / 2;
+        let next = {
+            let previous = levels.last().unwrap();
+            let mut next = vec![0u8; (half * half * 4) as usize];
+            for y in 0..half {
+                for x in 0..half {
+                    let texels = [(0, 0), (1, 0), (0, 1), (1, 1)]
+                        .iter()
+                        .map(|&(dx, dy)| {
+                            let i = (((y * 2 + dy) * size + x * 2 + dx) * 4) as usize;
+                            &previous[i..i + 4]
+                        })
+                        .collect::<Vec<_>>();
+                    let alpha = texels.iter().map(|texel| texel[3] as u32).sum::<u32>();
+                    let out = ((y * half + x) * 4) as usize;
+                    for c in 0..3 {
+                        // fully transparent texels still get a plain average
+                        // for whatever samples them
+                        let colour = if alpha > 0 {
+                            texels
+                                .iter()
+                                .map(|texel| linear[texel[c] as usize] * texel[3] as f32)
+                                .sum::<f32>()
+                                / alpha as f32
+                        } else {
+                            texels
+                                .iter()
+                                .map(|texel| linear[texel[c] as usize])
+                                .sum::<f32>()
+                                / 4.0
+                        };
+                        next[out + c] = linear_to_srgb(colour);
+                    }
+                    next[out + 3] = ((alpha + 2) / 4) as u8;
+                }
+            }
+            next
+        };
+        levels.push(next);
+        size = half;
+        tile /= 2;
+    }
+    levels
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let value = value as f32 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let value = if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    };
+    (value * 255.0).round().max(0.0).min(255.0) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use image::Rgba;
+
+    fn solid(colour: [u8; 4]) -> RgbaImage {
+        RgbaImage::from_pixel(TILE_SIZE, TILE_SIZE, Rgba(colour))
+    }
+
+    // the colour of the texel at (x, y) of a mip level that's `size` wide
+    fn texel(level: &[u8], size: u32, x: u32, y: u32) -> [u8; 4] {
+        let i = ((y * size + x) * 4) as usize;
+        [level[i], level[i + 1], level[i + 2], level[i + 3]]
+    }
+
+    #[test]
+    fn tiles_go_on_a_square_grid() {
+        let (atlas, image) = Atlas::from_images(vec![
+            ("red".to_string(), solid([255, 0, 0, 255])),
+            // scaled to the tile size
+            (
+                "blue".to_string(),
+                RgbaImage::from_pixel(4, 4, Rgba([0, 0, 255, 255])),
+            ),
+        ]);
+        assert_eq!((image.width, image.height), (2 * TILE_SIZE, 2 * TILE_SIZE));
+        assert_eq!(atlas.rect("white"), [0.0, 0.0, 0.5, 0.5]);
+        assert_eq!(atlas.rect("missing"), [0.5, 0.0, 0.5, 0.5]);
+        assert_eq!(atlas.rect("red"), [0.0, 0.5, 0.5, 0.5]);
+        assert_eq!(atlas.rect("blue"), [0.5, 0.5, 0.5, 0.5]);
+        assert!(atlas.contains("red"));
+        assert!(!atlas.contains("green"));
+        // unknown names get the checkerboard
+        assert_eq!(atlas.rect("green"), atlas.rect("missing"));
+
+        let base = &image.levels[0];
+        let size = image.width;
+        assert_eq!(texel(base, size, 0, 0), [255; 4]);
+        assert_eq!(texel(base, size, TILE_SIZE - 1, TILE_SIZE - 1), [255; 4]);
+        assert_eq!(texel(base, size, TILE_SIZE, 0), [255, 0, 255, 255]);
+        assert_eq!(
+            texel(base, size, TILE_SIZE + TILE_SIZE / 2, 0),
+            [0, 0, 0, 255]
+        );
+        assert_eq!(texel(base, size, 0, TILE_SIZE), [255, 0, 0, 255]);
+        assert_eq!(
+            texel(base, size, 2 * TILE_SIZE - 1, 2 * TILE_SIZE - 1),
+            [0, 0, 255, 255]
+        );
+
+        // five tiles need the next power of two of columns
+        let images = (0..3)
+            .map(|i| (i.to_string(), solid([0, 0, 0, 255])))
+            .collect();
+        let (atlas, image) = Atlas::from_images(images);
+        assert_eq!(image.width, 4 * TILE_SIZE);
+        assert_eq!(atlas.rect("2"), [0.0, 0.25, 0.25, 0.25]);
+    }
+
+    #[test]
+    fn mips_stop_at_a_texel_per_tile_without_bleeding() {
+        let colours = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 128]];
+        let images = colours
+            .iter()
+            .enumerate()
+            .map(|(i, &colour)| (i.to_string(), solid(colour)))
+            .collect();
+        let (atlas, image) = Atlas::from_images(images);
+
+        let mut levels = 1;
+        let mut tile = TILE_SIZE;
+        while tile > 1 {
+            tile /= 2;
+            levels += 1;
+        }
+        assert_eq!(image.levels.len(), levels);
+
+        // solid tiles stay exactly their colour all the way down
+        for (level, pixels) in image.levels.iter().enumerate() {
+            let size = image.width >> level;
+            let tile = TILE_SIZE >> level;
+            assert_eq!(pixels.len(), (size * size * 4) as usize);
+            for (i, &colour) in colours.iter().enumerate() {
+                let rect = atlas.rect(&i.to_string());
+                let x0 = (rect[0] * size as f32) as u32;
+                let y0 = (rect[1] * size as f32) as u32;
+                for y in y0..y0 + tile {
+                    for x in x0..x0 + tile {
+                        assert_eq!(texel(pixels, size, x, y), colour, "level {}", level);
+                    }
+                }
+            }
+        }
+        let columns = image.width / TILE_SIZE;
+        assert_eq!(
+            image.levels.last().unwrap().len(),
+            (columns * columns * 4) as usize
+        );
+    }
+
+    #[test]
+    fn mips_average_in_linear_space() {
+        // black and white in a checkerboard is half as bright, which in sRGB
+        // is a lot more than 128
+        let mut checker = RgbaImage::new(TILE_SIZE, TILE_SIZE);
+        for (x, y, pixel) in checker.enumerate_pixels_mut() {
+            let value = if (x + y) % 2 == 0 { 255 } else { 0 };
+            *pixel = Rgba([value, value, value, 255]);
+        }
+        let levels = mip_levels(checker.into_raw(), TILE_SIZE);
+        assert_eq!(levels.len(), 5);
+        assert_eq!(texel(&levels[1], TILE_SIZE / 2, 0, 0), [188, 188, 188, 255]);
+        assert_eq!(texel(levels.last().unwrap(), 1, 0, 0), [188, 188, 188, 255]);
+
+        for value in 0..256 {
+            assert_eq!(linear_to_srgb(srgb_to_linear(value as u8)), value as u8);
+        }
+    }
+
+    #[test]
+    fn transparent_texels_dont_darken_mips() {
+        // opaque red next to transparent black, like the edge of a cutout
+        let mut edge = RgbaImage::new(TILE_SIZE, TILE_SIZE);
+        for (x, _, pixel) in edge.enumerate_pixels_mut() {
+            *pixel = if x % 2 == 0 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            };
+        }
+        let levels = mip_levels(edge.into_raw(), TILE_SIZE);
+        assert_eq!(texel(&levels[1], TILE_SIZE / 2, 0, 0), [255, 0, 0, 128]);
+
+        // nothing to weigh by, the colour is kept as it is
+        let clear = RgbaImage::from_pixel(TILE_SIZE, TILE_SIZE, Rgba([0, 255, 0, 0]));
+        let levels = mip_levels(clear.into_raw(), TILE_SIZE);
+        assert_eq!(texel(levels.last().unwrap(), 1, 0, 0), [0, 255, 0, 0]);
+    }
+}
@@ -0,0 +1,625 @@
+use toml;
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use light::MAX_LIGHT;
+
+// what the block types are is up to assets/blocks.toml, blocks themselves are
+// only ids into the registry loaded from it
+pub const DEFAULT_PATH: &'static str = "assets/blocks.toml";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Block(pub u16);
+
+pub const AIR: Block = Block(0);
+
+// ids of some of the blocks in assets/blocks.toml, for tests that use
+// `test_registry`. everything else looks blocks up by name.
+#[cfg(test)]
+pub const STONE: Block = Block(1);
+#[cfg(test)]
+pub const DIRT: Block = Block(2);
+#[cfg(test)]
+pub const GRASS: Block = Block(3);
+#[cfg(test)]
+pub const SAND: Block = Block(4);
+#[cfg(test)]
+pub const WATER: Block = Block(5);
+#[cfg(test)]
+pub const TORCH: Block = Block(6);
+#[cfg(test)]
+pub const SLAB: Block = Block(7);
+
+impl Block {
+    pub fn is_air(self) -> bool {
+        self == AIR
+    }
+
+    // whether there's anything in the cell at all, what it is and how it
+    // behaves is up to the registry
+    pub fn is_present(self) -> bool {
+        !self.is_air()
+    }
+}
+
+impl Default for Block {
+    fn default() -> Block {
+        AIR
+    }
+}
+
+// which pipeline draws the block's faces. cutout textures are either fully
+// opaque or fully transparent per texel, translucent ones are blended over
+// whatever is behind them.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum RenderLayer {
+    Opaque,
+    Cutout,
+    Translucent,
+}
+
+impl Default for RenderLayer {
+    fn default() -> RenderLayer {
+        RenderLayer::Opaque
+    }
+}
+
+// faces are indexed like mesher::FACES (+x, -x, +y, -y, +z, -z)
+const FACE_NAMES: [&'static str; 6] = ["east", "west", "top", "bottom", "south", "north"];
+
+#[derive(Clone, Debug)]
+pub struct BlockType {
+    pub name: String,
+    // whether players bump into the block or can move through it
+    pub solid: bool,
+    // whether light passes through the block
+    pub transparent: bool,
+    // block light level the block gives off, 0 to light::MAX_LIGHT
+    pub light: u8,
+    // rays from the crosshair go through liquids, they can't be broken
+    pub liquid: bool,
+    // seconds it takes to break the block
+    pub hardness: f32,
+    // height of the block's box from the bottom of its cell, slabs fill the
+    // lower half
+    pub height: f32,
+    pub render: RenderLayer,
+    // atlas texture for each face
+    pub textures: [String; 6],
+    pub drop: Option<Block>,
+}
+
+impl BlockType {
+    fn air() -> BlockType {
+        BlockType {
+            name: "air".to_string(),
+            solid: false,
+            transparent: true,
+            light: 0,
+            liquid: false,
+            hardness: 0.0,
+            height: 0.0,
+            render: RenderLayer::Opaque,
+            textures: faces("missing"),
+            drop: None,
+        }
+    }
+
+    // stands in for ids that aren't in the registry, e.g. from chunks saved
+    // with a block that has been removed since
+    fn unknown() -> BlockType {
+        BlockType {
+            name: "unknown".to_string(),
+            solid: true,
+            transparent: false,
+            light: 0,
+            liquid: false,
+            hardness: 1.0,
+            height: 1.0,
+            render: RenderLayer::Opaque,
+            textures: faces("missing"),
+            drop: None,
+        }
+    }
+}
+
+fn faces(texture: &str) -> [String; 6] {
+    [
+        texture.to_string(),
+        texture.to_string(),
+        texture.to_string(),
+        texture.to_string(),
+        texture.to_string(),
+        texture.to_string(),
+    ]
+}
+
+// one [[block]] entry as it's written in the file
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlockDef {
+    name: String,
+    id: u16,
+    #[serde(default = "default_true")]
+    solid: bool,
+    #[serde(default)]
+    transparent: bool,
+    #[serde(default)]
+    light: u8,
+    #[serde(default)]
+    liquid: bool,
+    #[serde(default = "default_one")]
+    hardness: f32,
+    #[serde(default = "default_one")]
+    height: f32,
+    #[serde(default)]
+    render: RenderLayer,
+    #[serde(default)]
+    drop: Option<String>,
+    #[serde(default)]
+    textures: FaceTextures,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_one() -> f32 {
+    1.0
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct FaceTextures {
+    all: Option<String>,
+    side: Option<String>,
+    top: Option<String>,
+    bottom: Option<String>,
+    east: Option<String>,
+    west: Option<String>,
+    south: Option<String>,
+    north: Option<String>,
+}
+
+impl FaceTextures {
+    // the most specific key that covers the face
+    fn face(&self, face: usize) -> Option<&String> {
+        let (single, group) = match face {
+            0 => (&self.east, &self.side),
+            1 => (&self.west, &self.side),
+            2 => (&self.top, &None),
+            3 => (&self.bottom, &None),
+            4 => (&self.south, &self.side),
+            _ => (&self.north, &self.side),
+        };
+        single.as_ref().or(group.as_ref()).or(self.all.as_ref())
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlockFile {
+    #[serde(default)]
+    block: Vec<BlockDef>,
+}
+
+#[derive(Debug)]
+pub enum RegistryError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Invalid(PathBuf, String),
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RegistryError::Io(ref path, ref err) => {
+                write!(f, "failed to read {}: {}", path.display(), err)
+            }
+            RegistryError::Parse(ref path, ref err) => write!(f, "{}: {}", path.display(), err),
+            RegistryError::Invalid(ref path, ref message) => {
+                write!(f, "{}: {}", path.display(), message)
+            }
+        }
+    }
+}
+
+impl error::Error for RegistryError {
+    fn description(&self) -> &str {
+        match *self {
+            RegistryError::Io(_, ref err) => err.description(),
+            RegistryError::Parse(..) => "failed to parse block types",
+            RegistryError::Invalid(_, ref message) => message,
+        }
+    }
+}
+
+// everything there is to know about each kind of block. the mesher, lighting
+// and physics all look blocks up in here, which is shared with the worker
+// threads.
+pub struct BlockRegistry {
+    // indexed by id, None for ids that aren't used
+    types: Vec<Option<BlockType>>,
+    names: HashMap<String, Block>,
+    unknown: BlockType,
+}
+
+impl BlockRegistry {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<BlockRegistry, RegistryError> {
+        let path = path.as_ref();
+        let text =
+            fs::read_to_string(path).map_err(|err| RegistryError::Io(path.to_path_buf(), err))?;
+        BlockRegistry::parse(&text, path)
+    }
+
+    // `path` is only used in errors
+    pub fn parse(text: &str, path: &Path) -> Result<BlockRegistry, RegistryError> {
+        let file: BlockFile =
+            toml::from_str(text).map_err(|err| RegistryError::Parse(path.to_path_buf(), err))?;
+        BlockRegistry::from_defs(file.block)
+            .map_err(|message| RegistryError::Invalid(path.to_path_buf(), message))
+    }
+
+    fn from_defs(defs: Vec<BlockDef>) -> Result<BlockRegistry, String> {
+        let mut names = HashMap::new();
+        names.insert("air".to_string(), AIR);
+        for def in &defs {
+            if def.id == AIR.0 {
+                return Err(format!(
+                    "'{}' has id 0, which is reserved for air",
+                    def.name
+                ));
+            }
+            if def.name.is_empty() {
+                return Err(format!("block {} has no name", def.id));
+            }
+            if let Some(other) = names.insert(def.name.clone(), Block(def.id)) {
+                return Err(if other == AIR {
+                    "'air' is built in and can't be redefined".to_string()
+                } else {
+                    format!("there are two blocks named '{}'", def.name)
+                });
+            }
+        }
+
+        let max_id = defs.iter().map(|def| def.id).max().unwrap_or(0);
+        let mut types = vec![None; max_id as usize + 1];
+        types[0] = Some(BlockType::air());
+        for def in defs {
+            let id = def.id as usize;
+            if let Some(ref other) = types[id] {
+                return Err(format!(
+                    "'{}' and '{}' both have id {}",
+                    other.name, def.name, id
+                ));
+            }
+            types[id] = Some(validate(def, &names)?);
+        }
+
+        Ok(BlockRegistry {
+            types: types,
+            names: names,
+            unknown: BlockType::unknown(),
+        })
+    }
+
+    pub fn get(&self, block: Block) -> &BlockType {
+        match self.types.get(block.0 as usize) {
+            Some(&Some(ref ty)) => ty,
+            _ => &self.unknown,
+        }
+    }
+
+    // every block type apart from air, by id
+    pub fn types(&self) -> Vec<&BlockType> {
+        self.types
+            .iter()
+            .skip(1)
+            .filter_map(|ty| ty.as_ref())
+            .collect()
+    }
+
+    pub fn by_name(&self, name: &str) -> Option<Block> {
+        self.names.get(name).cloned()
+    }
+
+    // solid blocks that fill their whole cell, these hide the faces of the
+    // blocks next to them
+    pub fn is_full_cube(&self, block: Block) -> bool {
+        block.is_present() && self.get(block).height >= 1.0
+    }
+
+    // whether the block stops light
+    pub fn is_opaque(&self, block: Block) -> bool {
+        self.is_full_cube(block) && !self.get(block).transparent
+    }
+
+    // whether `neighbour` covers up the face of `block` that touches it. see
+    // through blocks only hide faces between blocks of the same kind, like
+    // the inside of a body of water.
+    pub fn hides_face(&self, neighbour: Block, block: Block) -> bool {
+        self.is_full_cube(neighbour)
+            && (self.get(neighbour).render == RenderLayer::Opaque || neighbour == block)
+    }
+
+    pub fn layer(&self, block: Block) -> RenderLayer {
+        self.get(block).render
+    }
+
+    pub fn is_collidable(&self, block: Block) -> bool {
+        self.get(block).solid
+    }
+
+    // whether rays from the crosshair stop at the block, so that it can be
+    // broken or built against
+    pub fn is_targetable(&self, block: Block) -> bool {
+        block.is_present() && !self.get(block).liquid
+    }
+
+    pub fn height(&self, block: Block) -> f32 {
+        self.get(block).height
+    }
+
+    pub fn light_emission(&self, block: Block) -> u8 {
+        self.get(block).light
+    }
+
+    pub fn texture(&self, block: Block, face: usize) -> &str {
+        &self.get(block).textures[face]
+    }
+}
+
+fn validate(def: BlockDef, names: &HashMap<String, Block>) -> Result<BlockType, String> {
+    if def.light > MAX_LIGHT {
+        return Err(format!(
+            "'{}' gives off light {}, the most is {}",
+            def.name, def.light, MAX_LIGHT
+        ));
+    }
+    if !(def.height > 0.0 && def.height <= 1.0) {
+        return Err(format!(
+            "'{}' has height {}, it has to be more than 0 and at most 1",
+            def.name, def.height
+        ));
+    }
+    if !(def.hardness >= 0.0 && def.hardness.is_finite()) {
+        return Err(format!(
+            "'{}' has hardness {}, it can't be negative",
+            def.name, def.hardness
+        ));
+    }
+    let drop = match def.drop {
+        Some(ref drop) => match names.get(drop) {
+            Some(&block) => Some(block),
+            None => {
+                return Err(format!(
+                    "'{}' drops '{}', which isn't a block",
+                    def.name, drop
+                ))
+            }
+        },
+        None => None,
+    };
+
+    let mut textures = faces("");
+    for face in 0..6 {
+        textures[face] = match def.textures.face(face) {
+            Some(texture) => texture.clone(),
+            None => {
+                return Err(format!(
+                    "'{}' has no texture for its {} face",
+                    def.name, FACE_NAMES[face]
+                ))
+            }
+        };
+    }
+
+    Ok(BlockType {
+        name: def.name,
+        solid: def.solid,
+        transparent: def.transparent,
+        light: def.light,
+        liquid: def.liquid,
+        hardness: def.hardness,
+        height: def.height,
+        render: def.render,
+        textures: textures,
+        drop: drop,
+    })
+}
+
+// the block types shipped in assets/, for tests that need real blocks
+#[cfg(test)]
+pub fn test_registry() -> BlockRegistry {
+    BlockRegistry::parse(
+        include_str!("../assets/blocks.toml"),
+        Path::new(DEFAULT_PATH),
+    ).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SHIPPED_TOML: &'static str = include_str!("../assets/blocks.toml");
+
+    fn parse(extra: &str) -> Result<BlockRegistry, RegistryError> {
+        BlockRegistry::parse(
+            &format!("{}\n{}", SHIPPED_TOML, extra),
+            Path::new("blocks.toml"),
+        )
+    }
+
+    fn invalid(extra: &str) -> String {
+        match parse(extra) {
+            Err(RegistryError::Invalid(_, message)) => message,
+            Err(err) => panic!("expected a validation error, got {}", err),
+            Ok(_) => panic!("expected a validation error"),
+        }
+    }
+
+    #[test]
+    fn shipped_blocks() {
+        let blocks = test_registry();
+        assert_eq!(blocks.by_name("grass"), Some(GRASS));
+        assert_eq!(blocks.texture(GRASS, 2), "grass_top");
+        assert_eq!(blocks.texture(GRASS, 3), "dirt");
+        assert_eq!(blocks.texture(GRASS, 0), "grass_side");
+        assert_eq!(blocks.get(GRASS).drop, Some(DIRT));
+        assert_eq!(blocks.light_emission(TORCH), 14);
+
+        assert!(blocks.is_opaque(STONE));
+        assert!(!blocks.is_opaque(WATER) && blocks.is_full_cube(WATER));
+        assert!(!blocks.is_collidable(WATER));
+        assert!(blocks.is_collidable(SLAB) && !blocks.is_full_cube(SLAB));
+        assert_eq!(blocks.height(SLAB), 0.5);
+        assert!(!blocks.is_collidable(AIR) && !blocks.is_opaque(AIR));
+
+        // torches are small, let light through and can be walked through
+        assert!(!blocks.is_full_cube(TORCH) && !blocks.is_opaque(TORCH));
+        assert!(!blocks.is_collidable(TORCH));
+        assert_eq!(blocks.layer(TORCH), RenderLayer::Cutout);
+
+        // the crosshair looks through water and air, at anything else
+        assert!(!blocks.is_targetable(WATER) && !blocks.is_targetable(AIR));
+        assert!(blocks.is_targetable(TORCH) && blocks.is_targetable(SLAB));
+    }
+
+    #[test]
+    fn new_blocks_come_from_the_file() {
+        let blocks = parse(
+            "[[block]]
+            name = \"glowstone\"
+            id = 300
+            light = 15
+            textures = { all = \"stone\", top = \"sand\", north = \"dirt\" }",
+        ).unwrap();
+        let glowstone = blocks.by_name("glowstone").unwrap();
+        assert_eq!(glowstone, Block(300));
+        assert_eq!(blocks.light_emission(glowstone), 15);
+        assert!(blocks.is_collidable(glowstone));
+        assert_eq!(blocks.get(glowstone).drop, None);
+        assert_eq!(blocks.texture(glowstone, 2), "sand");
+        assert_eq!(blocks.texture(glowstone, 5), "dirt");
+        assert_eq!(blocks.texture(glowstone, 0), "stone");
+    }
+
+    #[test]
+    fn render_layers() {
+        let blocks = test_registry();
+        let glass = blocks.by_name("glass").unwrap();
+        let leaves = blocks.by_name("leaves").unwrap();
+        assert_eq!(blocks.layer(STONE), RenderLayer::Opaque);
+        assert_eq!(blocks.layer(WATER), RenderLayer::Translucent);
+        assert_eq!(blocks.layer(glass), RenderLayer::Translucent);
+        assert_eq!(blocks.layer(leaves), RenderLayer::Cutout);
+
+        // see through blocks don't hide what's behind them, except more of
+        // the same block
+        assert!(blocks.hides_face(STONE, WATER));
+        assert!(!blocks.hides_face(WATER, STONE));
+        assert!(!blocks.hides_face(glass, WATER));
+        assert!(!blocks.hides_face(leaves, STONE));
+        assert!(blocks.hides_face(WATER, WATER));
+        assert!(blocks.hides_face(leaves, leaves));
+        assert!(!blocks.hides_face(SLAB, STONE));
+        assert!(!blocks.hides_face(AIR, STONE));
+
+        match parse("[[block]]\nname = \"new\"\nid = 20\nrender = \"glowing\"") {
+            Err(RegistryError::Parse(..)) => {}
+            _ => panic!("expected a parse error"),
+        }
+    }
+
+    #[test]
+    fn unknown_ids_are_solid() {
+        let blocks = test_registry();
+        assert_eq!(blocks.get(Block(200)).name, "unknown");
+        assert!(blocks.is_opaque(Block(200)));
+        assert_eq!(blocks.texture(Block(200), 0), "missing");
+    }
+
+    #[test]
+    fn validation_errors() {
+        let block = |fields: &str| format!("[[block]]\nname = \"new\"\n{}", fields);
+        assert_eq!(
+            invalid(&block("id = 0\ntextures = { all = \"stone\" }")),
+            "'new' has id 0, which is reserved for air"
+        );
+        assert_eq!(
+            invalid(&block("id = 1\ntextures = { all = \"stone\" }")),
+            "'stone' and 'new' both have id 1"
+        );
+        assert_eq!(
+            invalid("[[block]]\nname = \"dirt\"\nid = 20\ntextures = { all = \"dirt\" }"),
+            "there are two blocks named 'dirt'"
+        );
+        assert_eq!(
+            invalid(&block(
+                "id = 20\nlight = 16\ntextures = { all = \"stone\" }"
+            )),
+            "'new' gives off light 16, the most is 15"
+        );
+        assert_eq!(
+            invalid(&block(
+                "id = 20\nheight = 0.0\ntextures = { all = \"stone\" }"
+            )),
+            "'new' has height 0, it has to be more than 0 and at most 1"
+        );
+        assert_eq!(
+            invalid(&block(
+                "id = 20\ndrop = \"gold\"\ntextures = { all = \"stone\" }"
+            )),
+            "'new' drops 'gold', which isn't a block"
+        );
+        assert_eq!(
+            invalid(&block(
+                "id = 20\ntextures = { side = \"stone\", top = \"dirt\" }"
+            )),
+            "'new' has no texture for its bottom face"
+        );
+    }
+
+    #[test]
+    fn ids_are_up_to_the_file() {
+        // nothing needs a block under a particular name or id
+        let renamed = SHIPPED_TOML
+            .replace("\"slab\"", "\"half\"")
+            .replace("id = 1\n", "id = 100\n");
+        let blocks = BlockRegistry::parse(&renamed, Path::new("blocks.toml")).unwrap();
+        assert_eq!(blocks.by_name("half"), Some(SLAB));
+        assert_eq!(blocks.by_name("slab"), None);
+        assert_eq!(blocks.by_name("stone"), Some(Block(100)));
+
+        // the ids the tests use are still the ones in the shipped file
+        let blocks = test_registry();
+        let names = [
+            (STONE, "stone"),
+            (DIRT, "dirt"),
+            (GRASS, "grass"),
+            (SAND, "sand"),
+            (WATER, "water"),
+            (TORCH, "torch"),
+            (SLAB, "slab"),
+        ];
+        for &(block, name) in names.iter() {
+            assert_eq!(blocks.by_name(name), Some(block));
+        }
+    }
+
+    #[test]
+    fn parse_errors_name_the_file() {
+        let err = match parse("[[block]]\nname = \"new\"\nid = 20\ncolour = 3") {
+            Err(err) => err,
+            Ok(_) => panic!("expected a parse error"),
+        };
+        assert!(err.to_string().starts_with("blocks.toml: "), "{}", err);
+    }
+}
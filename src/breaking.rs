@@ -0,0 +1,93 @@
+use block::Block;
+use block::BlockRegistry;
+
+// how far along breaking the block under the crosshair is. a block breaks
+// once it has been worked on for its hardness in seconds, looking at another
+// block or letting go of the button starts over.
+#[derive(Debug, Default)]
+pub struct Breaking {
+    target: Option<([i32; 3], Block)>,
+    elapsed: f32,
+}
+
+impl Breaking {
+    pub fn stop(&mut self) {
+        self.target = None;
+        self.elapsed = 0.0;
+    }
+
+    // keeps working on `block` at `position` for another `dt` seconds,
+    // returns what it drops once it breaks. that's `Some(None)` for blocks
+    // that don't drop anything.
+    pub fn update(
+        &mut self,
+        blocks: &BlockRegistry,
+        position: [i32; 3],
+        block: Block,
+        dt: f32,
+    ) -> Option<Option<Block>> {
+        if self.target != Some((position, block)) {
+            self.target = Some((position, block));
+            self.elapsed = 0.0;
+        }
+        self.elapsed += dt;
+
+        let ty = blocks.get(block);
+        if self.elapsed >= ty.hardness {
+            self.stop();
+            Some(ty.drop)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use block;
+
+    #[test]
+    fn blocks_break_after_their_hardness() {
+        let blocks = block::test_registry();
+        let mut breaking = Breaking::default();
+        // stone takes 1.5 seconds and drops itself
+        for _ in 0..14 {
+            assert_eq!(breaking.update(&blocks, [1, 2, 3], block::STONE, 0.1), None);
+        }
+        assert_eq!(
+            breaking.update(&blocks, [1, 2, 3], block::STONE, 0.2),
+            Some(Some(block::STONE))
+        );
+        // and then starts over
+        assert_eq!(breaking.update(&blocks, [1, 2, 3], block::STONE, 1.0), None);
+
+        assert_eq!(
+            breaking.update(&blocks, [0, 0, 0], block::GRASS, 1.0),
+            Some(Some(block::DIRT))
+        );
+        assert_eq!(
+            breaking.update(&blocks, [0, 0, 0], block::TORCH, 0.0),
+            Some(Some(block::TORCH))
+        );
+        let glass = blocks.by_name("glass").unwrap();
+        assert_eq!(breaking.update(&blocks, [0, 0, 0], glass, 1.0), Some(None));
+    }
+
+    #[test]
+    fn progress_is_lost_when_looking_away() {
+        let blocks = block::test_registry();
+        let mut breaking = Breaking::default();
+        assert_eq!(breaking.update(&blocks, [0, 0, 0], block::DIRT, 0.4), None);
+        assert_eq!(breaking.update(&blocks, [0, 1, 0], block::DIRT, 0.4), None);
+        assert_eq!(breaking.update(&blocks, [0, 1, 0], block::SAND, 0.4), None);
+
+        breaking.stop();
+        assert_eq!(breaking.update(&blocks, [0, 1, 0], block::SAND, 0.4), None);
+        assert_eq!(
+            breaking.update(&blocks, [0, 1, 0], block::SAND, 0.4),
+            Some(Some(block::SAND))
+        );
+    }
+}
@@ -0,0 +1,181 @@
+use cgmath::InnerSpace;
+use cgmath::Matrix4;
+use cgmath::Point3;
+use cgmath::Vector3;
+use winit::VirtualKeyCode;
+
+use std::f32::consts::FRAC_PI_2;
+
+use settings::Keys;
+use settings::SettingsError;
+
+// the names keys can be bound by in the settings, the same as the variant
+macro_rules! key_names {
+    ($($key:ident),*) => {
+        pub fn key_code(name: &str) -> Option<VirtualKeyCode> {
+            match name {
+                $(stringify!($key) => Some(VirtualKeyCode::$key),)*
+                _ => None,
+            }
+        }
+    };
+}
+
+key_names!(
+    A, B, C, D, E, F, G, H, I, J, K, L, M, N, O, P, Q, R, S, T, U, V, W, X, Y, Z, Key0, Key1, Key2,
+    Key3, Key4, Key5, Key6, Key7, Key8, Key9, F1, F2, F3, F4, F5, F6, F7, F8, F9, F10, F11, F12,
+    Escape, Space, Tab, Return, Back, Insert, Delete, Home, End, PageUp, PageDown, Left, Up, Right,
+    Down, LShift, RShift, LControl, RControl, LAlt, RAlt, Numpad0, Numpad1, Numpad2, Numpad3,
+    Numpad4, Numpad5, Numpad6, Numpad7, Numpad8, Numpad9, Comma, Period, Slash, Semicolon,
+    Apostrophe, LBracket, RBracket, Minus, Equals, Grave
+);
+
+pub struct KeyBindings {
+    pub forward: VirtualKeyCode,
+    pub back: VirtualKeyCode,
+    pub left: VirtualKeyCode,
+    pub right: VirtualKeyCode,
+    pub up: VirtualKeyCode,
+    pub down: VirtualKeyCode,
+    pub walk: VirtualKeyCode,
+    pub quit: VirtualKeyCode,
+}
+
+impl KeyBindings {
+    pub fn new(keys: &Keys) -> Result<KeyBindings, SettingsError> {
+        let code = |action: &str, name: &str| {
+            key_code(name).ok_or_else(|| {
+                SettingsError::Invalid(format!("{} is bound to unknown key {:?}", action, name))
+            })
+        };
+        Ok(KeyBindings {
+            forward: code("forward", &keys.forward)?,
+            back: code("back", &keys.back)?,
+            left: code("left", &keys.left)?,
+            right: code("right", &keys.right)?,
+            up: code("up", &keys.up)?,
+            down: code("down", &keys.down)?,
+            walk: code("walk", &keys.walk)?,
+            quit: code("quit", &keys.quit)?,
+        })
+    }
+}
+
+// which movement keys are currently held down
+#[derive(Default)]
+pub struct Movement {
+    forward: bool,
+    back: bool,
+    left: bool,
+    right: bool,
+    up: bool,
+    down: bool,
+}
+
+impl Movement {
+    // returns false for keys that don't move the camera
+    pub fn key(&mut self, bindings: &KeyBindings, key: VirtualKeyCode, pressed: bool) -> bool {
+        if key == bindings.forward {
+            self.forward = pressed;
+        } else if key == bindings.back {
+            self.back = pressed;
+        } else if key == bindings.left {
+            self.left = pressed;
+        } else if key == bindings.right {
+            self.right = pressed;
+        } else if key == bindings.up {
+            self.up = pressed;
+        } else if key == bindings.down {
+            self.down = pressed;
+        } else {
+            return false;
+        }
+        true
+    }
+
+    pub fn jumping(&self) -> bool {
+        self.up
+    }
+}
+
+fn axis(positive: bool, negative: bool) -> f32 {
+    match (positive, negative) {
+        (true, false) => 1.0,
+        (false, true) => -1.0,
+        _ => 0.0,
+    }
+}
+
+// y is up, a yaw of zero looks down -z and positive yaw turns right
+pub struct Camera {
+    pub position: Point3<f32>,
+    pub yaw: f32,
+    pub pitch: f32,
+    // blocks per second
+    pub speed: f32,
+    // radians per pixel of mouse movement
+    pub sensitivity: f32,
+}
+
+impl Camera {
+    pub fn new(position: Point3<f32>, yaw: f32, pitch: f32) -> Camera {
+        Camera {
+            position: position,
+            yaw: yaw,
+            pitch: pitch,
+            speed: 10.0,
+            sensitivity: 0.002,
+        }
+    }
+
+    pub fn forward(&self) -> Vector3<f32> {
+        Vector3::new(
+            self.yaw.sin() * self.pitch.cos(),
+            self.pitch.sin(),
+            -self.yaw.cos() * self.pitch.cos(),
+        )
+    }
+
+    pub fn right(&self) -> Vector3<f32> {
+        Vector3::new(self.yaw.cos(), 0.0, self.yaw.sin())
+    }
+
+    pub fn view(&self) -> Matrix4<f32> {
+        Matrix4::look_at_dir(self.position, self.forward(), Vector3::unit_y())
+    }
+
+    pub fn rotate(&mut self, dx: f64, dy: f64) {
+        // stop just short of straight up or down where look_at_dir breaks
+        let limit = FRAC_PI_2 - 0.01;
+        self.yaw += dx as f32 * self.sensitivity;
+        self.pitch -= dy as f32 * self.sensitivity;
+        self.pitch = self.pitch.max(-limit).min(limit);
+    }
+
+    // horizontal direction the movement keys point in as (x, z), either unit
+    // length or zero, for walking where up and down come from physics
+    pub fn walk_direction(&self, movement: &Movement) -> [f32; 2] {
+        let flat_forward = Vector3::new(self.yaw.sin(), 0.0, -self.yaw.cos());
+        let direction = flat_forward * axis(movement.forward, movement.back)
+            + self.right() * axis(movement.right, movement.left);
+
+        if direction.magnitude2() > 0.0 {
+            let direction = direction.normalize();
+            [direction.x, direction.z]
+        } else {
+            [0.0, 0.0]
+        }
+    }
+
+    // `dt` is in seconds, so the speed doesn't depend on the frame rate
+    pub fn update(&mut self, movement: &Movement, dt: f32) {
+        let flat_forward = Vector3::new(self.yaw.sin(), 0.0, -self.yaw.cos());
+        let direction = flat_forward * axis(movement.forward, movement.back)
+            + self.right() * axis(movement.right, movement.left)
+            + Vector3::unit_y() * axis(movement.up, movement.down);
+
+        if direction.magnitude2() > 0.0 {
+            self.position += direction.normalize() * self.speed * dt;
+        }
+    }
+}
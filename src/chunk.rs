@@ -0,0 +1,408 @@
+use block;
+use block::Block;
+
+pub const CHUNK_BITS: i32 = 5;
+pub const CHUNK_SIZE: usize = 1 << CHUNK_BITS;
+pub const CHUNK_VOLUME: usize = CHUNK_SIZE * CHUNK_SIZE * CHUNK_SIZE;
+
+// chunk coordinates, i.e. world block coordinates divided by CHUNK_SIZE
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ChunkPos {
+    pub x: i32,
+    pub y: i32,
+    pub z: i32,
+}
+
+impl ChunkPos {
+    pub fn new(x: i32, y: i32, z: i32) -> ChunkPos {
+        ChunkPos { x: x, y: y, z: z }
+    }
+
+    // the arithmetic shift rounds towards negative infinity, so -1 ends up
+    // in chunk -1 rather than chunk 0
+    pub fn containing(x: i32, y: i32, z: i32) -> ChunkPos {
+        ChunkPos::new(x >> CHUNK_BITS, y >> CHUNK_BITS, z >> CHUNK_BITS)
+    }
+
+    // world coordinates of the block at local (0, 0, 0)
+    pub fn origin(&self) -> [i32; 3] {
+        [
+            self.x << CHUNK_BITS,
+            self.y << CHUNK_BITS,
+            self.z << CHUNK_BITS,
+        ]
+    }
+
+    pub fn offset(&self, dx: i32, dy: i32, dz: i32) -> ChunkPos {
+        ChunkPos::new(self.x + dx, self.y + dy, self.z + dz)
+    }
+}
+
+// position of a world coordinate inside its chunk
+pub fn local(v: i32) -> usize {
+    (v & (CHUNK_SIZE as i32 - 1)) as usize
+}
+
+// every chunk holding the block at x, y, z or one of the 26 blocks around it,
+// i.e. the chunks whose meshes can change when that block does
+pub fn chunks_touching(x: i32, y: i32, z: i32) -> Vec<ChunkPos> {
+    let mut chunks = Vec::new();
+    for &dy in [-1, 0, 1].iter() {
+        for &dz in [-1, 0, 1].iter() {
+            for &dx in [-1, 0, 1].iter() {
+                let pos = ChunkPos::containing(x + dx, y + dy, z + dz);
+                if !chunks.contains(&pos) {
+                    chunks.push(pos);
+                }
+            }
+        }
+    }
+    chunks
+}
+
+fn index(x: usize, y: usize, z: usize) -> usize {
+    debug_assert!(x < CHUNK_SIZE && y < CHUNK_SIZE && z < CHUNK_SIZE);
+    (y * CHUNK_SIZE + z) * CHUNK_SIZE + x
+}
+
+// number of bits needed to address `len` palette entries
+fn bits_for(len: usize) -> u32 {
+    let mut bits = 0;
+    while (1 << bits) < len {
+        bits += 1;
+    }
+    bits
+}
+
+// block storage as a palette of distinct blocks plus a packed array of
+// palette indices. a chunk of a single block type takes no index storage at
+// all, entries never straddle two words to keep the lookups cheap.
+#[derive(Clone)]
+struct PalettedStorage {
+    palette: Vec<Block>,
+    // how many cells use each palette entry, entries at zero get reused
+    counts: Vec<u32>,
+    bits: u32,
+    words: Vec<u64>,
+}
+
+impl PalettedStorage {
+    fn new(fill: Block) -> PalettedStorage {
+        PalettedStorage {
+            palette: vec![fill],
+            counts: vec![CHUNK_VOLUME as u32],
+            bits: 0,
+            words: Vec::new(),
+        }
+    }
+
+    fn index_at(&self, i: usize) -> usize {
+        if self.bits == 0 {
+            return 0;
+        }
+        let per_word = 64 / self.bits as usize;
+        let word = self.words[i / per_word];
+        let shift = (i % per_word) * self.bits as usize;
+        ((word >> shift) & ((1 << self.bits) - 1)) as usize
+    }
+
+    fn set_index(&mut self, i: usize, value: usize) {
+        let per_word = 64 / self.bits as usize;
+        let shift = (i % per_word) * self.bits as usize;
+        let mask = ((1u64 << self.bits) - 1) << shift;
+        let word = &mut self.words[i / per_word];
+        *word = (*word & !mask) | ((value as u64) << shift);
+    }
+
+    fn get(&self, i: usize) -> Block {
+        self.palette[self.index_at(i)]
+    }
+
+    fn set(&mut self, i: usize, block: Block) -> Block {
+        let old_index = self.index_at(i);
+        let old = self.palette[old_index];
+        if old == block {
+            return old;
+        }
+
+        let new_index = match self.palette.iter().position(|&b| b == block) {
+            Some(index) => index,
+            None => self.add_to_palette(block),
+        };
+
+        self.counts[old_index] -= 1;
+        self.counts[new_index] += 1;
+        self.set_index(i, new_index);
+        old
+    }
+
+    fn add_to_palette(&mut self, block: Block) -> usize {
+        if let Some(free) = self.counts.iter().position(|&count| count == 0) {
+            self.palette[free] = block;
+            return free;
+        }
+
+        self.palette.push(block);
+        self.counts.push(0);
+        let bits = bits_for(self.palette.len());
+        if bits > self.bits {
+            self.repack(bits);
+        }
+        self.palette.len() - 1
+    }
+
+    fn repack(&mut self, bits: u32) {
+        let indices = (0..CHUNK_VOLUME)
+            .map(|i| self.index_at(i))
+            .collect::<Vec<_>>();
+
+        let per_word = 64 / bits as usize;
+        self.bits = bits;
+        self.words = vec![0; (CHUNK_VOLUME + per_word - 1) / per_word];
+        for (i, index) in indices.into_iter().enumerate() {
+            self.set_index(i, index);
+        }
+    }
+
+    fn contains_only(&self, block: Block) -> bool {
+        self.palette
+            .iter()
+            .zip(self.counts.iter())
+            .all(|(&b, &count)| count == 0 || b == block)
+    }
+}
+
+#[derive(Clone)]
+pub struct Chunk {
+    blocks: PalettedStorage,
+}
+
+impl Chunk {
+    pub fn new() -> Chunk {
+        Chunk::filled(block::AIR)
+    }
+
+    pub fn filled(block: Block) -> Chunk {
+        Chunk {
+            blocks: PalettedStorage::new(block),
+        }
+    }
+
+    pub fn get(&self, x: usize, y: usize, z: usize) -> Block {
+        self.blocks.get(index(x, y, z))
+    }
+
+    // returns the block that was there before
+    pub fn set(&mut self, x: usize, y: usize, z: usize, block: Block) -> Block {
+        self.blocks.set(index(x, y, z), block)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.blocks.contains_only(block::AIR)
+    }
+
+    // FNV-1a over the block ids in storage order, stable across runs and
+    // platforms unlike the std hashers
+    pub fn content_hash(&self) -> u64 {
+        let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+        for i in 0..CHUNK_VOLUME {
+            let id = self.blocks.get(i).0;
+            for &byte in [id as u8, (id >> 8) as u8].iter() {
+                hash ^= byte as u64;
+                hash = hash.wrapping_mul(0x100_0000_01b3);
+            }
+        }
+        hash
+    }
+
+    // bits used per block, mostly interesting for memory statistics
+    pub fn bits_per_block(&self) -> u32 {
+        self.blocks.bits
+    }
+
+    // the palette followed by the packed index words, all little endian. the
+    // bit width follows from the palette length so it isn't stored.
+    pub fn encode(&self) -> Vec<u8> {
+        let storage = &self.blocks;
+        let mut bytes = Vec::with_capacity(2 + storage.palette.len() * 2 + storage.words.len() * 8);
+        let len = storage.palette.len() as u16;
+        bytes.push(len as u8);
+        bytes.push((len >> 8) as u8);
+        for block in &storage.palette {
+            bytes.push(block.0 as u8);
+            bytes.push((block.0 >> 8) as u8);
+        }
+        for word in &storage.words {
+            for i in 0..8 {
+                bytes.push((word >> (i * 8)) as u8);
+            }
+        }
+        bytes
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<Chunk, String> {
+        let u16_at = |i: usize| bytes[i] as u16 | (bytes[i + 1] as u16) << 8;
+
+        if bytes.len() < 2 {
+            return Err("truncated palette".to_string());
+        }
+        let len = u16_at(0) as usize;
+        if len == 0 {
+            return Err("empty palette".to_string());
+        }
+        let bits = bits_for(len);
+        let word_count = if bits == 0 {
+            0
+        } else {
+            let per_word = 64 / bits as usize;
+            (CHUNK_VOLUME + per_word - 1) / per_word
+        };
+        let expected = 2 + len * 2 + word_count * 8;
+        if bytes.len() != expected {
+            return Err(format!(
+                "expected {} bytes for a palette of {}, got {}",
+                expected,
+                len,
+                bytes.len()
+            ));
+        }
+
+        let palette = (0..len)
+            .map(|i| Block(u16_at(2 + i * 2)))
+            .collect::<Vec<_>>();
+        let words = (0..word_count)
+            .map(|i| {
+                let start = 2 + len * 2 + i * 8;
+                (0..8).fold(0u64, |word, b| word | (bytes[start + b] as u64) << (b * 8))
+            })
+            .collect::<Vec<_>>();
+
+        let mut storage = PalettedStorage {
+            palette: palette,
+            counts: vec![0; len],
+            bits: bits,
+            words: words,
+        };
+        for i in 0..CHUNK_VOLUME {
+            let index = storage.index_at(i);
+            if index >= len {
+                return Err(format!("palette index {} out of range", index));
+            }
+            storage.counts[index] += 1;
+        }
+
+        Ok(Chunk { blocks: storage })
+    }
+}
+
+impl Default for Chunk {
+    fn default() -> Chunk {
+        Chunk::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_read_back_what_was_set() {
+        let mut chunk = Chunk::new();
+        assert_eq!(chunk.set(1, 2, 3, block::STONE), block::AIR);
+        assert_eq!(chunk.set(31, 31, 31, block::DIRT), block::AIR);
+        assert_eq!(chunk.get(1, 2, 3), block::STONE);
+        assert_eq!(chunk.get(31, 31, 31), block::DIRT);
+        assert_eq!(chunk.get(3, 2, 1), block::AIR);
+        assert_eq!(chunk.set(1, 2, 3, block::GRASS), block::STONE);
+        assert_eq!(chunk.get(1, 2, 3), block::GRASS);
+    }
+
+    #[test]
+    fn palette_slots_are_reused() {
+        let mut chunk = Chunk::new();
+        chunk.set(0, 0, 0, block::STONE);
+        chunk.set(1, 0, 0, block::DIRT);
+        assert_eq!(chunk.blocks.palette.len(), 3);
+
+        // the last stone is gone, grass takes its slot
+        chunk.set(0, 0, 0, block::AIR);
+        chunk.set(2, 0, 0, block::GRASS);
+        assert_eq!(chunk.blocks.palette.len(), 3);
+        assert_eq!(chunk.blocks.palette[1], block::GRASS);
+        assert_eq!(chunk.get(2, 0, 0), block::GRASS);
+        assert_eq!(chunk.get(1, 0, 0), block::DIRT);
+        assert_eq!(chunk.get(0, 0, 0), block::AIR);
+    }
+
+    #[test]
+    fn indices_widen_as_the_palette_grows() {
+        let mut chunk = Chunk::filled(block::STONE);
+        assert_eq!(chunk.bits_per_block(), 0);
+
+        let mut widths = Vec::new();
+        for id in 1..200u16 {
+            let i = id as usize;
+            chunk.set(i % CHUNK_SIZE, i / CHUNK_SIZE, 7, Block(100 + id));
+            if widths.last() != Some(&chunk.bits_per_block()) {
+                widths.push(chunk.bits_per_block());
+            }
+        }
+        assert_eq!(widths, vec![1, 2, 3, 4, 5, 6, 7, 8]);
+
+        // nothing got lost along the way
+        for id in 1..200u16 {
+            let i = id as usize;
+            assert_eq!(
+                chunk.get(i % CHUNK_SIZE, i / CHUNK_SIZE, 7),
+                Block(100 + id)
+            );
+        }
+        assert_eq!(chunk.get(0, 0, 7), block::STONE);
+        assert_eq!(chunk.get(5, 5, 5), block::STONE);
+    }
+
+    #[test]
+    fn empty_means_only_air() {
+        let mut chunk = Chunk::new();
+        assert!(chunk.is_empty());
+        chunk.set(4, 4, 4, block::STONE);
+        assert!(!chunk.is_empty());
+        // the palette still has stone in it, but nothing uses it
+        chunk.set(4, 4, 4, block::AIR);
+        assert!(chunk.is_empty());
+        assert!(!Chunk::filled(block::STONE).is_empty());
+        assert!(Chunk::filled(block::STONE)
+            .blocks
+            .contains_only(block::STONE));
+    }
+
+    #[test]
+    fn encoding_keeps_every_block() {
+        let mut chunk = Chunk::filled(block::DIRT);
+        chunk.set(0, 0, 0, block::STONE);
+        chunk.set(31, 0, 9, block::SAND);
+        let decoded = Chunk::decode(&chunk.encode()).unwrap();
+        assert_eq!(decoded.get(0, 0, 0), block::STONE);
+        assert_eq!(decoded.get(31, 0, 9), block::SAND);
+        assert_eq!(decoded.get(1, 1, 1), block::DIRT);
+        assert_eq!(decoded.content_hash(), chunk.content_hash());
+    }
+
+    #[test]
+    fn positions_round_towards_negative_infinity() {
+        let size = CHUNK_SIZE as i32;
+        assert_eq!(ChunkPos::containing(0, 0, 0), ChunkPos::new(0, 0, 0));
+        assert_eq!(
+            ChunkPos::containing(size - 1, size, -1),
+            ChunkPos::new(0, 1, -1)
+        );
+        assert_eq!(
+            ChunkPos::containing(-size, -size - 1, 2 * size),
+            ChunkPos::new(-1, -2, 2)
+        );
+        assert_eq!(ChunkPos::new(-1, 0, 2).origin(), [-size, 0, 2 * size]);
+        assert_eq!(local(-1), CHUNK_SIZE - 1);
+        assert_eq!(local(-size), 0);
+        assert_eq!(local(size + 3), 3);
+    }
+}
@@ -0,0 +1,161 @@
+use log::Level;
+use vulkano::device::Device;
+use vulkano::device::DeviceExtensions;
+use vulkano::device::DeviceOwned;
+use vulkano::image::sys::UnsafeImage;
+use vulkano::instance;
+use vulkano::instance::debug::DebugCallback;
+use vulkano::instance::debug::Message;
+use vulkano::instance::debug::MessageTypes;
+use vulkano::instance::Instance;
+use vulkano::instance::InstanceCreationError;
+use vulkano::instance::InstanceExtensions;
+use vulkano::instance::PhysicalDevice;
+use vulkano::OomError;
+use vulkano::VulkanHandle;
+use vulkano::VulkanObject;
+
+use std::ffi::CStr;
+use std::ffi::CString;
+use std::sync::Arc;
+
+// newer SDKs ship the Khronos layer, older ones only the LunarG meta layer
+// that bundles the same checks
+const VALIDATION_LAYERS: [&'static str; 2] = [
+    "VK_LAYER_KHRONOS_validation",
+    "VK_LAYER_LUNARG_standard_validation",
+];
+
+// messages from the validation layer are logged with this target
+pub const TARGET: &'static str = "vulkan";
+
+// with `validation` the instance loads a validation layer and everything it
+// reports is logged, as long as the layer and ext_debug_report are installed.
+// the callback has to be kept alive for as long as messages should arrive.
+pub fn create_instance(
+    extensions: InstanceExtensions,
+    validation: bool,
+) -> Result<(Arc<Instance>, Option<DebugCallback>), InstanceCreationError> {
+    if !validation {
+        return Ok((Instance::new(None, &extensions, None)?, None));
+    }
+
+    let layer = validation_layer();
+    match layer {
+        Some(layer) => info!("enabling {}", layer),
+        None => warn!("no validation layer is installed, is the Vulkan SDK set up?"),
+    }
+
+    let supported = InstanceExtensions::supported_by_core().unwrap_or(InstanceExtensions::none());
+    if !supported.ext_debug_report {
+        warn!("VK_EXT_debug_report isn't supported, validation messages won't be logged");
+    }
+    let extensions = InstanceExtensions {
+        ext_debug_report: supported.ext_debug_report,
+        ..extensions
+    };
+
+    let instance = Instance::new(None, &extensions, layer.iter())?;
+
+    let callback = if extensions.ext_debug_report {
+        let messages = MessageTypes {
+            error: true,
+            warning: true,
+            performance_warning: true,
+            information: true,
+            debug: true,
+        };
+        DebugCallback::new(&instance, messages, log_message)
+            .map_err(|err| warn!("failed to set up the debug callback: {}", err))
+            .ok()
+    } else {
+        None
+    };
+    Ok((instance, callback))
+}
+
+fn validation_layer() -> Option<&'static str> {
+    let available: Vec<_> = match instance::layers_list() {
+        Ok(layers) => layers.map(|layer| layer.name().to_string()).collect(),
+        Err(err) => {
+            warn!("couldn't list the instance layers: {}", err);
+            return None;
+        }
+    };
+    VALIDATION_LAYERS
+        .iter()
+        .cloned()
+        .find(|&wanted| available.iter().any(|layer| layer == wanted))
+}
+
+// the loader and the layers are chatty at the information level, so that
+// only shows up with RSCRAFT_LOG=debug
+fn log_message(message: &Message) {
+    let level = if message.ty.error {
+        Level::Error
+    } else if message.ty.warning || message.ty.performance_warning {
+        Level::Warn
+    } else if message.ty.information {
+        Level::Debug
+    } else {
+        Level::Trace
+    };
+    log!(
+        target: TARGET,
+        level,
+        "{}: {}",
+        message.layer_prefix,
+        message.description
+    );
+}
+
+// with `validation` the device also gets ext_debug_marker where it has it, so
+// that `name` can label objects in the validation messages and in tools
+// like RenderDoc
+pub fn device_extensions(physical: PhysicalDevice, validation: bool) -> DeviceExtensions {
+    if !validation {
+        return DeviceExtensions::none();
+    }
+    let supported = DeviceExtensions::supported_by_device(physical);
+    if !supported.ext_debug_marker {
+        info!("VK_EXT_debug_marker isn't supported, Vulkan objects won't be named");
+    }
+    DeviceExtensions {
+        ext_debug_marker: supported.ext_debug_marker,
+        ..DeviceExtensions::none()
+    }
+}
+
+// does nothing unless the device was created with ext_debug_marker
+pub fn name<T>(object: &T, name: &str)
+where
+    T: VulkanObject + DeviceOwned,
+{
+    let device = object.device();
+    set_name(device, name, |name| device.set_object_name(object, name));
+}
+
+// images aren't `DeviceOwned`, so they're named through their raw handle
+pub fn name_image(image: &UnsafeImage, name: &str) {
+    let device = image.device();
+    set_name(device, name, |name| unsafe {
+        // the handle and its type both come from `image`, which `device` owns
+        device.set_object_name_raw(UnsafeImage::TYPE, image.internal_object().value(), name)
+    });
+}
+
+fn set_name<F>(device: &Device, name: &str, set: F)
+where
+    F: FnOnce(&CStr) -> Result<(), OomError>,
+{
+    if !device.loaded_extensions().ext_debug_marker {
+        return;
+    }
+    let name = match CString::new(name) {
+        Ok(name) => name,
+        Err(_) => return,
+    };
+    if let Err(err) = set(&name) {
+        warn!("couldn't name {:?}: {}", name, err);
+    }
+}
@@ -0,0 +1,201 @@
+use cgmath::Matrix4;
+use cgmath::Point3;
+use cgmath::Transform;
+
+// the six planes bounding what a camera can see, as (a, b, c, d) with the
+// normal (a, b, c) pointing inwards, so a point p is inside a plane when
+// a*x + b*y + c*z + d >= 0
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Frustum {
+    pub planes: [[f32; 4]; 6],
+}
+
+impl Frustum {
+    // pulls the planes out of a combined `proj * view` matrix (Gribb &
+    // Hartmann). every row but the last bounds one clip space coordinate
+    // against w, giving a left/right, a bottom/top and a near/far pair.
+    pub fn from_matrix(m: Matrix4<f32>) -> Frustum {
+        // cgmath matrices are column major, m[column][row]
+        let row = |i: usize| [m[0][i], m[1][i], m[2][i], m[3][i]];
+        let (x, y, z, w) = (row(0), row(1), row(2), row(3));
+
+        let mut planes = [[0.0; 4]; 6];
+        for (plane, &(side, sign)) in planes.iter_mut().zip(
+            [
+                (x, 1.0),
+                (x, -1.0),
+                (y, 1.0),
+                (y, -1.0),
+                (z, 1.0),
+                (z, -1.0),
+            ]
+            .iter(),
+        ) {
+            for i in 0..4 {
+                plane[i] = w[i] + side[i] * sign;
+            }
+            // normalized so that the plane equation gives real distances
+            let length = (plane[0] * plane[0] + plane[1] * plane[1] + plane[2] * plane[2]).sqrt();
+            if length > 0.0 {
+                for v in plane.iter_mut() {
+                    *v /= length;
+                }
+            }
+        }
+        Frustum { planes: planes }
+    }
+
+    // false only when the box is entirely outside one of the planes, boxes
+    // near the corners of the frustum can be kept even though they're out
+    // of view, which just costs a draw call
+    pub fn intersects_aabb(&self, min: [f32; 3], max: [f32; 3]) -> bool {
+        self.planes.iter().all(|plane| {
+            // the corner of the box furthest along the plane normal
+            let mut distance = plane[3];
+            for axis in 0..3 {
+                let corner = if plane[axis] >= 0.0 {
+                    max[axis]
+                } else {
+                    min[axis]
+                };
+                distance += plane[axis] * corner;
+            }
+            distance >= 0.0
+        })
+    }
+}
+
+// the axis aligned box around `min`..`max` after transforming it by `m`
+pub fn transform_aabb(m: &Matrix4<f32>, min: [f32; 3], max: [f32; 3]) -> ([f32; 3], [f32; 3]) {
+    let mut new_min = [::std::f32::MAX; 3];
+    let mut new_max = [::std::f32::MIN; 3];
+    for i in 0..8 {
+        let corner = Point3::new(
+            if i & 1 == 0 { min[0] } else { max[0] },
+            if i & 2 == 0 { min[1] } else { max[1] },
+            if i & 4 == 0 { min[2] } else { max[2] },
+        );
+        let p = m.transform_point(corner);
+        let p = [p.x, p.y, p.z];
+        for axis in 0..3 {
+            new_min[axis] = new_min[axis].min(p[axis]);
+            new_max[axis] = new_max[axis].max(p[axis]);
+        }
+    }
+    (new_min, new_max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use cgmath;
+    use cgmath::Vector3;
+
+    fn approx(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4 * b.abs().max(1.0)
+    }
+
+    // 90 degree square frustum looking down -z from the origin
+    fn camera(near: f32, far: f32) -> Frustum {
+        let proj = cgmath::perspective(cgmath::Rad(::std::f32::consts::FRAC_PI_2), 1.0, near, far);
+        Frustum::from_matrix(proj)
+    }
+
+    fn point_inside(frustum: &Frustum, p: [f32; 3]) -> bool {
+        frustum.intersects_aabb(p, p)
+    }
+
+    #[test]
+    fn planes_match_the_projection() {
+        let frustum = camera(1.0, 100.0);
+        let s = 0.5f32.sqrt();
+        let expected = [
+            [s, 0.0, -s, 0.0],
+            [-s, 0.0, -s, 0.0],
+            [0.0, s, -s, 0.0],
+            [0.0, -s, -s, 0.0],
+            [0.0, 0.0, -1.0, -1.0],
+            [0.0, 0.0, 1.0, 100.0],
+        ];
+        for (plane, expected) in frustum.planes.iter().zip(expected.iter()) {
+            for i in 0..4 {
+                assert!(
+                    approx(plane[i], expected[i]),
+                    "{:?} != {:?}",
+                    plane,
+                    expected
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn points_inside_and_outside() {
+        let frustum = camera(1.0, 100.0);
+        assert!(point_inside(&frustum, [0.0, 0.0, -10.0]));
+        assert!(point_inside(&frustum, [9.0, -9.0, -10.0]));
+        assert!(!point_inside(&frustum, [11.0, 0.0, -10.0]));
+        assert!(!point_inside(&frustum, [0.0, 11.0, -10.0]));
+        // behind the camera, closer than the near plane, past the far plane
+        assert!(!point_inside(&frustum, [0.0, 0.0, 10.0]));
+        assert!(!point_inside(&frustum, [0.0, 0.0, -0.5]));
+        assert!(!point_inside(&frustum, [0.0, 0.0, -101.0]));
+    }
+
+    #[test]
+    fn boxes_partly_inside_are_kept() {
+        let frustum = camera(1.0, 100.0);
+        // straddles the right plane
+        assert!(frustum.intersects_aabb([9.0, -1.0, -11.0], [12.0, 1.0, -9.0]));
+        // straddles the far plane
+        assert!(frustum.intersects_aabb([-1.0, -1.0, -110.0], [1.0, 1.0, -90.0]));
+        // surrounds the camera
+        assert!(frustum.intersects_aabb([-5.0, -5.0, -5.0], [5.0, 5.0, 5.0]));
+        // just right of the right plane
+        assert!(!frustum.intersects_aabb([10.5, -1.0, -10.0], [12.0, 1.0, -9.9]));
+        // entirely behind
+        assert!(!frustum.intersects_aabb([-32.0, -32.0, 1.0], [32.0, 32.0, 33.0]));
+    }
+
+    #[test]
+    fn view_and_flip_are_taken_into_account() {
+        // the renderer's projection flips y and the camera looks down +x from
+        // (10, 0, 0)
+        let flip_y = Matrix4::from_nonuniform_scale(1.0, -1.0, 1.0);
+        let proj = flip_y
+            * cgmath::perspective(cgmath::Rad(::std::f32::consts::FRAC_PI_2), 1.0, 0.1, 50.0);
+        let view = Matrix4::look_at_dir(
+            Point3::new(10.0, 0.0, 0.0),
+            Vector3::new(1.0, 0.0, 0.0),
+            Vector3::unit_y(),
+        );
+        let frustum = Frustum::from_matrix(proj * view);
+
+        assert!(point_inside(&frustum, [20.0, 0.0, 0.0]));
+        assert!(point_inside(&frustum, [20.0, 9.0, 0.0]));
+        assert!(point_inside(&frustum, [20.0, -9.0, 0.0]));
+        assert!(!point_inside(&frustum, [20.0, 11.0, 0.0]));
+        assert!(!point_inside(&frustum, [20.0, 0.0, -11.0]));
+        assert!(!point_inside(&frustum, [0.0, 0.0, 0.0]));
+        assert!(!point_inside(&frustum, [61.0, 0.0, 0.0]));
+    }
+
+    #[test]
+    fn transformed_boxes() {
+        let m = Matrix4::from_translation(Vector3::new(32.0, -32.0, 0.0));
+        let (min, max) = transform_aabb(&m, [0.0, 0.0, 0.0], [32.0, 32.0, 32.0]);
+        assert_eq!(min, [32.0, -32.0, 0.0]);
+        assert_eq!(max, [64.0, 0.0, 32.0]);
+
+        // a quarter turn around y swaps x and z
+        let m = Matrix4::from_angle_y(cgmath::Deg(90.0));
+        let (min, max) = transform_aabb(&m, [0.0, 0.0, 0.0], [1.0, 2.0, 3.0]);
+        let expected_min = [0.0, 0.0, -1.0];
+        let expected_max = [3.0, 2.0, 0.0];
+        for axis in 0..3 {
+            assert!(approx(min[axis], expected_min[axis]), "{:?}", min);
+            assert!(approx(max[axis], expected_max[axis]), "{:?}", max);
+        }
+    }
+}
@@ -0,0 +1,212 @@
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DeviceKind {
+    Discrete,
+    Integrated,
+    Virtual,
+    Cpu,
+    Other,
+}
+
+impl DeviceKind {
+    // a discrete gpu always wins over an integrated one however much memory
+    // the integrated one reports
+    fn score(self) -> u64 {
+        match self {
+            DeviceKind::Discrete => 4000,
+            DeviceKind::Integrated => 2000,
+            DeviceKind::Virtual => 1000,
+            DeviceKind::Cpu => 100,
+            DeviceKind::Other => 0,
+        }
+    }
+}
+
+// what choosing a device needs to know about each one
+#[derive(Clone, Debug)]
+pub struct Candidate {
+    pub index: usize,
+    pub name: String,
+    pub kind: DeviceKind,
+    // has a queue family that can draw, and present when there's a window
+    pub can_draw: bool,
+    // supports a color format the renderer can draw into
+    pub has_formats: bool,
+    pub device_local_memory: u64,
+}
+
+// why a device can't be used
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Unsuitable {
+    NoQueue,
+    NoFormat,
+}
+
+impl fmt::Display for Unsuitable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Unsuitable::NoQueue => write!(f, "no queue family can draw to the window"),
+            Unsuitable::NoFormat => write!(f, "no supported color format"),
+        }
+    }
+}
+
+impl Candidate {
+    // higher is better, ties go to the device enumerated first
+    pub fn score(&self) -> Result<u64, Unsuitable> {
+        if !self.can_draw {
+            return Err(Unsuitable::NoQueue);
+        }
+        if !self.has_formats {
+            return Err(Unsuitable::NoFormat);
+        }
+        // a gigabyte of device local memory is worth a point, capped well
+        // below the gap between device kinds
+        let memory = (self.device_local_memory >> 30).min(999);
+        Ok(self.kind.score() + memory)
+    }
+}
+
+// the index of the best usable device
+pub fn choose(candidates: &[Candidate]) -> Option<usize> {
+    let mut best: Option<(u64, usize)> = None;
+    for candidate in candidates {
+        if let Ok(score) = candidate.score() {
+            if best.map_or(true, |(best_score, _)| score > best_score) {
+                best = Some((score, candidate.index));
+            }
+        }
+    }
+    best.map(|(_, index)| index)
+}
+
+// the size the swapchain should be made with, or None while the window is
+// minimized and there's nothing to draw into. the surface's current extent is
+// already in pixels, the window size is used when the surface leaves it up to
+// the swapchain and is scaled by the hidpi factor first. either way the result
+// has to fit within the extents the surface allows.
+pub fn swapchain_extent(
+    current_extent: Option<[u32; 2]>,
+    window_size: Option<(u32, u32)>,
+    hidpi_factor: f32,
+    min_extent: [u32; 2],
+    max_extent: [u32; 2],
+) -> Option<[u32; 2]> {
+    let extent = match current_extent {
+        Some(extent) => extent,
+        None => {
+            let (width, height) = window_size?;
+            [
+                (width as f32 * hidpi_factor).round() as u32,
+                (height as f32 * hidpi_factor).round() as u32,
+            ]
+        }
+    };
+    // some platforms report a zero sized surface, others a zero maximum
+    if extent[0] == 0 || extent[1] == 0 || max_extent[0] == 0 || max_extent[1] == 0 {
+        return None;
+    }
+    Some([
+        extent[0].max(min_extent[0]).min(max_extent[0]),
+        extent[1].max(min_extent[1]).min(max_extent[1]),
+    ])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GIB: u64 = 1 << 30;
+
+    fn candidate(index: usize, kind: DeviceKind, memory: u64) -> Candidate {
+        Candidate {
+            index: index,
+            name: format!("device {}", index),
+            kind: kind,
+            can_draw: true,
+            has_formats: true,
+            device_local_memory: memory,
+        }
+    }
+
+    #[test]
+    fn discrete_beats_integrated() {
+        let candidates = [
+            candidate(0, DeviceKind::Integrated, 64 * GIB),
+            candidate(1, DeviceKind::Cpu, 128 * GIB),
+            candidate(2, DeviceKind::Discrete, 2 * GIB),
+        ];
+        assert_eq!(choose(&candidates), Some(2));
+    }
+
+    #[test]
+    fn more_memory_breaks_ties_between_kinds() {
+        let candidates = [
+            candidate(0, DeviceKind::Discrete, 4 * GIB),
+            candidate(1, DeviceKind::Discrete, 8 * GIB),
+            candidate(2, DeviceKind::Discrete, 8 * GIB),
+        ];
+        assert_eq!(choose(&candidates), Some(1));
+    }
+
+    #[test]
+    fn unusable_devices_are_skipped() {
+        let mut no_queue = candidate(0, DeviceKind::Discrete, 8 * GIB);
+        no_queue.can_draw = false;
+        let mut no_format = candidate(1, DeviceKind::Discrete, 8 * GIB);
+        no_format.has_formats = false;
+        assert_eq!(no_queue.score(), Err(Unsuitable::NoQueue));
+        assert_eq!(no_format.score(), Err(Unsuitable::NoFormat));
+
+        let software = candidate(2, DeviceKind::Cpu, 0);
+        assert_eq!(
+            choose(&[no_queue.clone(), no_format.clone(), software]),
+            Some(2)
+        );
+        assert_eq!(choose(&[no_queue, no_format]), None);
+        assert_eq!(choose(&[]), None);
+    }
+
+    const MIN: [u32; 2] = [1, 1];
+    const MAX: [u32; 2] = [16384, 16384];
+
+    #[test]
+    fn current_extent_wins_over_the_window_size() {
+        assert_eq!(
+            swapchain_extent(Some([1600, 1200]), Some((800, 600)), 2.0, MIN, MAX),
+            Some([1600, 1200])
+        );
+    }
+
+    #[test]
+    fn window_size_is_scaled_to_pixels() {
+        assert_eq!(
+            swapchain_extent(None, Some((800, 600)), 2.0, MIN, MAX),
+            Some([1600, 1200])
+        );
+        assert_eq!(
+            swapchain_extent(None, Some((1001, 501)), 1.5, MIN, MAX),
+            Some([1502, 752])
+        );
+        assert_eq!(swapchain_extent(None, None, 1.0, MIN, MAX), None);
+    }
+
+    #[test]
+    fn extent_is_clamped_to_what_the_surface_allows() {
+        assert_eq!(
+            swapchain_extent(None, Some((5000, 10)), 2.0, [64, 64], [4096, 4096]),
+            Some([4096, 64])
+        );
+    }
+
+    #[test]
+    fn minimized_windows_have_no_extent() {
+        assert_eq!(swapchain_extent(Some([0, 0]), None, 1.0, MIN, MAX), None);
+        assert_eq!(swapchain_extent(None, Some((640, 0)), 1.0, MIN, MAX), None);
+        assert_eq!(
+            swapchain_extent(Some([640, 480]), None, 1.0, [0, 0], [0, 0]),
+            None
+        );
+    }
+}
@@ -0,0 +1,67 @@
+use cgmath;
+
+use std::error::Error;
+use std::path::Path;
+
+use block::RenderLayer;
+use obj;
+use renderer::RenderError;
+use renderer::Renderer;
+use renderer::Scene;
+use renderer::SceneObject;
+
+// renders a single frame into an offscreen image and writes it out as a PNG,
+// no window or surface is involved so this works on software drivers such as
+// lavapipe on machines without a display
+pub fn render_to_png<P: AsRef<Path>, M: AsRef<Path>>(
+    path: P,
+    dimensions: [u32; 2],
+    model: M,
+) -> Result<(), Box<Error>> {
+    let data = obj::load(model.as_ref())
+        .map_err(|err| format!("failed to load {}: {}", model.as_ref().display(), err))?;
+
+    let mut renderer = Renderer::new_headless(dimensions)?;
+
+    let mesh = renderer.upload_mesh(&data)?;
+
+    // scale the model into a unit sphere around the origin so that any model
+    // ends up in view
+    let (min, max) = data.bounds();
+    let center = cgmath::Vector3::new(
+        (min[0] + max[0]) / 2.0,
+        (min[1] + max[1]) / 2.0,
+        (min[2] + max[2]) / 2.0,
+    );
+    let radius = (0..3)
+        .map(|axis| (max[axis] - min[axis]) / 2.0)
+        .fold(0.0, f32::max)
+        .max(::std::f32::EPSILON);
+    let world =
+        cgmath::Matrix4::from_scale(1.0 / radius) * cgmath::Matrix4::from_translation(-center);
+
+    let view = cgmath::Matrix4::look_at(
+        cgmath::Point3::new(0.6, 0.6, 2.0),
+        cgmath::Point3::new(0.0, 0.0, 0.0),
+        cgmath::Vector3::new(0.0, 1.0, 0.0),
+    );
+
+    let scene = Scene {
+        view: view,
+        objects: vec![SceneObject {
+            mesh: &mesh,
+            world: world,
+            layer: RenderLayer::Opaque,
+        }],
+    };
+
+    // offscreen targets have no swapchain that could be out of date, so
+    // there's always a frame
+    let frame = renderer.draw(&scene)?.ok_or_else(|| {
+        RenderError::Unexpected("the offscreen target had no frame to draw into".to_string())
+    })?;
+    renderer.present(frame)?;
+
+    renderer.save_png(path)?;
+    Ok(())
+}
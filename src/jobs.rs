@@ -0,0 +1,683 @@
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::panic;
+use std::panic::AssertUnwindSafe;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::sync::Condvar;
+use std::sync::Mutex;
+use std::thread;
+
+use atlas::Atlas;
+use block::BlockRegistry;
+use chunk::Chunk;
+use chunk::ChunkPos;
+use light::Lighting;
+use mesher;
+use mesher::ChunkMesh;
+use storage::StorageError;
+use storage::WorldStorage;
+use world::World;
+use worldgen::WorldGenerator;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum JobKind {
+    // loading and generation come first so that chunks at the same distance
+    // have their neighbours around by the time they're meshed
+    Load,
+    Generate,
+    Mesh,
+}
+
+pub enum JobResult {
+    Loaded(ChunkPos, Chunk),
+    Generated(ChunkPos, Chunk),
+    LoadFailed(ChunkPos, StorageError),
+    Meshed(ChunkPos, ChunkMesh),
+}
+
+enum Task {
+    // from storage, or generated when it was never saved
+    Load(Arc<WorldStorage>),
+    Generate,
+    // the chunk and its neighbours as they were when the job was submitted
+    Mesh(World, Lighting),
+}
+
+struct Job {
+    version: u64,
+    task: Task,
+}
+
+// closest to the camera first, then by kind and position so the order is
+// the same every time. the version tells whether the entry is still the
+// queued job for its chunk.
+type Priority = Reverse<(i32, JobKind, i32, i32, i32, u64)>;
+
+struct Queue {
+    jobs: HashMap<(ChunkPos, JobKind), Job>,
+    // entries are only ever added, the ones for jobs that were replaced or
+    // cancelled are skipped when they come up and cleared out whenever the
+    // camera moves and the heap is rebuilt anyway
+    order: BinaryHeap<Priority>,
+    // version of the newest job submitted for each chunk that hasn't been
+    // delivered or cancelled yet, anything older is stale
+    latest: HashMap<(ChunkPos, JobKind), u64>,
+    next_version: u64,
+    center: ChunkPos,
+    radius: i32,
+    shutdown: bool,
+}
+
+impl Queue {
+    fn in_range(&self, pos: ChunkPos) -> bool {
+        (pos.x - self.center.x).abs() <= self.radius
+            && (pos.y - self.center.y).abs() <= self.radius
+            && (pos.z - self.center.z).abs() <= self.radius
+    }
+
+    fn priority(&self, pos: ChunkPos, kind: JobKind, version: u64) -> Priority {
+        Reverse((
+            distance2(pos, self.center),
+            kind,
+            pos.x,
+            pos.y,
+            pos.z,
+            version,
+        ))
+    }
+
+    fn push(&mut self, pos: ChunkPos, kind: JobKind, job: Job) {
+        let priority = self.priority(pos, kind, job.version);
+        self.order.push(priority);
+        self.jobs.insert((pos, kind), job);
+    }
+
+    // distances change with the center, so the heap is built again from
+    // the jobs that are still queued
+    fn reorder(&mut self) {
+        let order = self
+            .jobs
+            .iter()
+            .map(|(&(pos, kind), job)| self.priority(pos, kind, job.version))
+            .collect::<Vec<_>>();
+        self.order = BinaryHeap::from(order);
+    }
+
+    // the job closest to the camera
+    fn pop(&mut self) -> Option<(ChunkPos, JobKind, Job)> {
+        while let Some(Reverse((_, kind, x, y, z, version))) = self.order.pop() {
+            let key = (ChunkPos::new(x, y, z), kind);
+            if self.jobs.get(&key).map(|job| job.version) == Some(version) {
+                let job = self.jobs.remove(&key).unwrap();
+                return Some((key.0, kind, job));
+            }
+        }
+        None
+    }
+}
+
+fn distance2(a: ChunkPos, b: ChunkPos) -> i32 {
+    let (dx, dy, dz) = (a.x - b.x, a.y - b.y, a.z - b.z);
+    dx * dx + dy * dy + dz * dz
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    ready: Condvar,
+    generator: Arc<WorldGenerator>,
+    atlas: Arc<Atlas>,
+    blocks: Arc<BlockRegistry>,
+}
+
+// generates and meshes chunks on a pool of worker threads. jobs are picked
+// closest to the camera first, jobs for chunks that leave the view range are
+// dropped, and finished work is collected with `poll` on the thread that owns
+// the world and the renderer.
+pub struct JobPool {
+    shared: Arc<Shared>,
+    results: mpsc::Receiver<(ChunkPos, JobKind, u64, JobResult)>,
+    workers: Vec<thread::JoinHandle<()>>,
+}
+
+impl JobPool {
+    pub fn new(
+        threads: usize,
+        generator: Arc<WorldGenerator>,
+        atlas: Arc<Atlas>,
+        blocks: Arc<BlockRegistry>,
+    ) -> JobPool {
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                jobs: HashMap::new(),
+                order: BinaryHeap::new(),
+                latest: HashMap::new(),
+                next_version: 0,
+                center: ChunkPos::new(0, 0, 0),
+                radius: ::std::i32::MAX,
+                shutdown: false,
+            }),
+            ready: Condvar::new(),
+            generator: generator,
+            atlas: atlas,
+            blocks: blocks,
+        });
+
+        let (sender, results) = mpsc::channel();
+        let workers = (0..threads.max(1))
+            .map(|_| {
+                let shared = shared.clone();
+                let sender = sender.clone();
+                thread::spawn(move || work(&shared, &sender))
+            })
+            .collect();
+
+        JobPool {
+            shared: shared,
+            results: results,
+            workers: workers,
+        }
+    }
+
+    // queues reading the chunk at `pos` from `storage`, chunks that were
+    // never saved are generated instead
+    pub fn load(&self, storage: &Arc<WorldStorage>, pos: ChunkPos) {
+        self.submit(pos, JobKind::Load, Task::Load(storage.clone()));
+    }
+
+    // queues generating the chunk at `pos`
+    pub fn generate(&self, pos: ChunkPos) {
+        self.submit(pos, JobKind::Generate, Task::Generate);
+    }
+
+    // queues meshing the chunk at `pos` as it is right now, a job already
+    // queued for the same chunk is replaced
+    pub fn mesh(&self, world: &World, lighting: &Lighting, pos: ChunkPos) {
+        let task = Task::Mesh(world.region(pos), lighting.region(pos));
+        self.submit(pos, JobKind::Mesh, task);
+    }
+
+    fn submit(&self, pos: ChunkPos, kind: JobKind, task: Task) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        if !queue.in_range(pos) {
+            return;
+        }
+        let version = queue.next_version;
+        queue.next_version += 1;
+        queue.latest.insert((pos, kind), version);
+        queue.push(
+            pos,
+            kind,
+            Job {
+                version: version,
+                task: task,
+            },
+        );
+        self.shared.ready.notify_one();
+    }
+
+    // moves the camera to `center`, which reorders the queue, and drops every
+    // job for a chunk more than `radius` chunks away from it on any axis
+    pub fn set_view(&self, center: ChunkPos, radius: i32) {
+        let mut queue = self.shared.queue.lock().unwrap();
+        queue.center = center;
+        queue.radius = radius;
+
+        let cancelled: Vec<_> = queue
+            .latest
+            .keys()
+            .filter(|&&(pos, _)| !queue.in_range(pos))
+            .cloned()
+            .collect();
+        for key in cancelled {
+            queue.jobs.remove(&key);
+            queue.latest.remove(&key);
+        }
+        queue.reorder();
+    }
+
+    // finished jobs, without waiting for any. results of jobs that were
+    // cancelled or replaced by a newer job in the meantime are left out.
+    pub fn poll(&self) -> Vec<JobResult> {
+        let mut finished = Vec::new();
+        while let Ok(result) = self.results.try_recv() {
+            finished.push(result);
+        }
+        if finished.is_empty() {
+            return Vec::new();
+        }
+
+        let mut queue = self.shared.queue.lock().unwrap();
+        finished
+            .into_iter()
+            .filter_map(|(pos, kind, version, result)| {
+                if queue.latest.get(&(pos, kind)) == Some(&version) {
+                    queue.latest.remove(&(pos, kind));
+                    Some(result)
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    // jobs that are queued, running, or done but not polled yet
+    pub fn pending(&self) -> usize {
+        self.shared.queue.lock().unwrap().latest.len()
+    }
+}
+
+impl Drop for JobPool {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().shutdown = true;
+        self.shared.ready.notify_all();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+fn work(shared: &Shared, results: &mpsc::Sender<(ChunkPos, JobKind, u64, JobResult)>) {
+    loop {
+        let (pos, kind, job) = {
+            let mut queue = shared.queue.lock().unwrap();
+            loop {
+                if queue.shutdown {
+                    return;
+                }
+                if let Some(next) = queue.pop() {
+                    break next;
+                }
+                queue = shared.ready.wait(queue).unwrap();
+            }
+        };
+
+        let version = job.version;
+        let result = panic::catch_unwind(AssertUnwindSafe(|| match job.task {
+            Task::Load(storage) => match storage.load_chunk(pos) {
+                Ok(Some(chunk)) => JobResult::Loaded(pos, chunk),
+                Ok(None) => JobResult::Generated(pos, shared.generator.generate(pos)),
+                Err(err) => JobResult::LoadFailed(pos, err),
+            },
+            Task::Generate => JobResult::Generated(pos, shared.generator.generate(pos)),
+            Task::Mesh(world, lighting) => JobResult::Meshed(
+                pos,
+                mesher::mesh_chunk_greedy(&world, &lighting, pos, &shared.atlas, &shared.blocks),
+            ),
+        }));
+        match result {
+            // the pool is gone when nobody is listening, the next pop will
+            // see the shutdown
+            Ok(result) => {
+                let _ = results.send((pos, kind, version, result));
+            }
+            // nothing gets delivered, but the job mustn't look like it's
+            // still running either
+            Err(_) => {
+                error!("{:?} job for chunk {:?} panicked", kind, pos);
+                let mut queue = shared.queue.lock().unwrap();
+                if queue.latest.get(&(pos, kind)) == Some(&version) {
+                    queue.latest.remove(&(pos, kind));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use block;
+    use std::collections::HashSet;
+    use std::env;
+    use std::fs;
+    use std::process;
+    use std::sync::mpsc::Receiver;
+    use std::sync::mpsc::SyncSender;
+    use std::time::Duration;
+    use std::time::Instant;
+    use worldgen::TerrainGenerator;
+
+    fn wait_for_all(pool: &JobPool) -> Vec<JobResult> {
+        let start = Instant::now();
+        let mut results = Vec::new();
+        while pool.pending() > 0 {
+            assert!(
+                start.elapsed() < Duration::from_secs(60),
+                "jobs never finished"
+            );
+            results.extend(pool.poll());
+            thread::sleep(Duration::from_millis(1));
+        }
+        results
+    }
+
+    fn generated(results: &[JobResult]) -> Vec<ChunkPos> {
+        results
+            .iter()
+            .filter_map(|result| match *result {
+                JobResult::Generated(pos, _) => Some(pos),
+                _ => None,
+            })
+            .collect()
+    }
+
+    // blocks every generate call until the test lets it through, reporting
+    // the order the calls came in
+    struct GatedGenerator {
+        started: Mutex<mpsc::Sender<ChunkPos>>,
+        gate: Mutex<Receiver<()>>,
+    }
+
+    impl WorldGenerator for GatedGenerator {
+        fn generate(&self, pos: ChunkPos) -> Chunk {
+            self.started.lock().unwrap().send(pos).unwrap();
+            self.gate.lock().unwrap().recv().unwrap();
+            Chunk::new()
+        }
+    }
+
+    fn gated_pool() -> (JobPool, Receiver<ChunkPos>, SyncSender<()>) {
+        let (started, started_rx) = mpsc::channel();
+        let (gate, gate_rx) = mpsc::sync_channel(0);
+        let generator = GatedGenerator {
+            started: Mutex::new(started),
+            gate: Mutex::new(gate_rx),
+        };
+        let pool = JobPool::new(
+            1,
+            Arc::new(generator),
+            Arc::new(Atlas::new()),
+            Arc::new(block::test_registry()),
+        );
+        (pool, started_rx, gate)
+    }
+
+    #[test]
+    fn generates_everything_it_is_asked_to() {
+        let generator = Arc::new(TerrainGenerator::new(7, &block::test_registry()).unwrap());
+        let pool = JobPool::new(
+            4,
+            generator.clone(),
+            Arc::new(Atlas::new()),
+            Arc::new(block::test_registry()),
+        );
+
+        let mut expected = HashSet::new();
+        for x in -4..4 {
+            for y in -1..2 {
+                for z in -4..4 {
+                    let pos = ChunkPos::new(x, y, z);
+                    pool.generate(pos);
+                    expected.insert(pos);
+                }
+            }
+        }
+
+        let results = wait_for_all(&pool);
+        assert_eq!(results.len(), expected.len());
+        for result in results {
+            match result {
+                JobResult::Generated(pos, chunk) => {
+                    assert!(expected.remove(&pos));
+                    let reference = generator.generate(pos);
+                    assert_eq!(chunk.content_hash(), reference.content_hash());
+                }
+                _ => panic!("only generating was asked for"),
+            }
+        }
+        assert!(expected.is_empty());
+    }
+
+    #[test]
+    fn meshes_match_meshing_on_one_thread() {
+        let blocks = Arc::new(block::test_registry());
+        let generator = TerrainGenerator::new(3, &block::test_registry()).unwrap();
+        let mut world = World::new();
+        for x in -2..2 {
+            for y in -1..1 {
+                for z in -2..2 {
+                    let pos = ChunkPos::new(x, y, z);
+                    world.insert_chunk(pos, generator.generate(pos));
+                }
+            }
+        }
+        let lighting = Lighting::compute(&world, &blocks);
+        let atlas = Atlas::new();
+
+        let pool = JobPool::new(
+            4,
+            Arc::new(generator),
+            Arc::new(Atlas::new()),
+            blocks.clone(),
+        );
+        for (&pos, _) in world.chunks() {
+            pool.mesh(&world, &lighting, pos);
+        }
+
+        let results = wait_for_all(&pool);
+        assert_eq!(results.len(), world.len());
+        for result in results {
+            match result {
+                JobResult::Meshed(pos, data) => {
+                    let reference =
+                        mesher::mesh_chunk_greedy(&world, &lighting, pos, &atlas, &blocks);
+                    assert!(data == reference, "chunk {:?} meshed differently", pos);
+                }
+                _ => panic!("only meshing was asked for"),
+            }
+        }
+    }
+
+    #[test]
+    fn closest_jobs_run_first() {
+        let (pool, started, gate) = gated_pool();
+        pool.set_view(ChunkPos::new(0, 0, 0), 16);
+
+        // keep the only worker busy while the queue fills up
+        pool.generate(ChunkPos::new(9, 9, 9));
+        assert_eq!(started.recv().unwrap(), ChunkPos::new(9, 9, 9));
+
+        for &x in [5, -1, 8, 0, -3, 2].iter() {
+            pool.generate(ChunkPos::new(x, 0, 0));
+        }
+        // the camera moves before the worker gets to them
+        pool.set_view(ChunkPos::new(6, 0, 0), 16);
+
+        let mut order = Vec::new();
+        for _ in 0..6 {
+            gate.send(()).unwrap();
+            order.push(started.recv().unwrap().x);
+        }
+        gate.send(()).unwrap();
+        assert_eq!(order, vec![5, 8, 2, 0, -1, -3]);
+        wait_for_all(&pool);
+    }
+
+    #[test]
+    fn leaving_the_view_range_cancels_jobs() {
+        let (pool, started, gate) = gated_pool();
+        pool.set_view(ChunkPos::new(0, 0, 0), 4);
+
+        pool.generate(ChunkPos::new(0, 0, 0));
+        assert_eq!(started.recv().unwrap(), ChunkPos::new(0, 0, 0));
+        for x in 1..5 {
+            pool.generate(ChunkPos::new(x, 0, 0));
+        }
+        // already out of range, never queued
+        pool.generate(ChunkPos::new(-5, 0, 0));
+        assert_eq!(pool.pending(), 5);
+
+        // the running job is dropped too, once it finishes
+        pool.set_view(ChunkPos::new(10, 0, 0), 7);
+        assert_eq!(pool.pending(), 2);
+
+        gate.send(()).unwrap();
+        for _ in 0..2 {
+            started.recv().unwrap();
+            gate.send(()).unwrap();
+        }
+        let mut positions = generated(&wait_for_all(&pool));
+        positions.sort_by_key(|pos| pos.x);
+        assert_eq!(
+            positions,
+            vec![ChunkPos::new(3, 0, 0), ChunkPos::new(4, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn only_the_newest_mesh_is_delivered() {
+        let blocks = Arc::new(block::test_registry());
+        let generator = Arc::new(TerrainGenerator::new(0, &block::test_registry()).unwrap());
+        let pool = JobPool::new(4, generator, Arc::new(Atlas::new()), blocks.clone());
+        let atlas = Atlas::new();
+        let pos = ChunkPos::new(0, 0, 0);
+
+        let mut world = World::new();
+        world.insert_chunk(pos, Chunk::new());
+        let lighting = Lighting::new();
+        for i in 0..50 {
+            world.set_block(i % 32, i / 32, 0, block::STONE);
+            pool.mesh(&world, &lighting, pos);
+        }
+
+        let results = wait_for_all(&pool);
+        assert_eq!(results.len(), 1);
+        let reference = mesher::mesh_chunk_greedy(&world, &lighting, pos, &atlas, &blocks);
+        match results[0] {
+            JobResult::Meshed(p, ref data) => {
+                assert_eq!(p, pos);
+                assert!(*data == reference);
+            }
+            _ => panic!("only meshing was asked for"),
+        }
+    }
+
+    struct PanickyGenerator;
+
+    impl WorldGenerator for PanickyGenerator {
+        fn generate(&self, pos: ChunkPos) -> Chunk {
+            if pos.x < 0 {
+                panic!("no chunks west of the origin");
+            }
+            Chunk::new()
+        }
+    }
+
+    #[test]
+    fn panicking_jobs_are_not_pending_forever() {
+        let pool = JobPool::new(
+            1,
+            Arc::new(PanickyGenerator),
+            Arc::new(Atlas::new()),
+            Arc::new(block::test_registry()),
+        );
+        for x in -2..3 {
+            pool.generate(ChunkPos::new(x, 0, 0));
+        }
+
+        // the worker survives the panics and gets to everything else
+        let mut positions = generated(&wait_for_all(&pool));
+        positions.sort_by_key(|pos| pos.x);
+        assert_eq!(
+            positions,
+            vec![
+                ChunkPos::new(0, 0, 0),
+                ChunkPos::new(1, 0, 0),
+                ChunkPos::new(2, 0, 0),
+            ]
+        );
+        pool.generate(ChunkPos::new(3, 0, 0));
+        assert_eq!(
+            generated(&wait_for_all(&pool)),
+            vec![ChunkPos::new(3, 0, 0)]
+        );
+    }
+
+    #[test]
+    fn loads_fall_back_to_generating() {
+        let dir = env::temp_dir().join(format!("rscraft-jobs-load-{}", process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let storage = Arc::new(WorldStorage::open(&dir).unwrap());
+        let saved = ChunkPos::new(0, 0, 0);
+        storage.save_chunk(saved, &Chunk::filled(block::SAND));
+
+        let generator = Arc::new(TerrainGenerator::new(5, &block::test_registry()).unwrap());
+        let pool = JobPool::new(
+            2,
+            generator.clone(),
+            Arc::new(Atlas::new()),
+            Arc::new(block::test_registry()),
+        );
+        let fresh = ChunkPos::new(1, -1, 0);
+        pool.load(&storage, saved);
+        pool.load(&storage, fresh);
+
+        let results = wait_for_all(&pool);
+        assert_eq!(results.len(), 2);
+        for result in results {
+            match result {
+                JobResult::Loaded(pos, chunk) => {
+                    assert_eq!(pos, saved);
+                    assert_eq!(chunk.get(3, 3, 3), block::SAND);
+                }
+                JobResult::Generated(pos, chunk) => {
+                    assert_eq!(pos, fresh);
+                    assert_eq!(chunk.content_hash(), generator.generate(pos).content_hash());
+                }
+                _ => panic!("only loading was asked for"),
+            }
+        }
+
+        drop(pool);
+        drop(storage);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn stress_moving_camera() {
+        // a camera flying along x while chunks are queued around it, every
+        // chunk that is delivered must still be in range, and none twice
+        let generator = Arc::new(TerrainGenerator::new(11, &block::test_registry()).unwrap());
+        let pool = JobPool::new(
+            4,
+            generator,
+            Arc::new(Atlas::new()),
+            Arc::new(block::test_registry()),
+        );
+        let radius = 2;
+        let mut delivered = HashSet::new();
+
+        for step in 0..40 {
+            let center = ChunkPos::new(step, 0, 0);
+            pool.set_view(center, radius);
+            for x in -radius..radius + 1 {
+                for y in -1..1 {
+                    for z in -radius..radius + 1 {
+                        let pos = center.offset(x, y, z);
+                        if !delivered.contains(&pos) {
+                            pool.generate(pos);
+                        }
+                    }
+                }
+            }
+            for pos in generated(&pool.poll()) {
+                assert!((pos.x - center.x).abs() <= radius, "{:?} at {}", pos, step);
+                assert!(delivered.insert(pos), "{:?} delivered twice", pos);
+            }
+        }
+
+        let center = ChunkPos::new(39, 0, 0);
+        for pos in generated(&wait_for_all(&pool)) {
+            assert!((pos.x - center.x).abs() <= radius);
+            assert!(delivered.insert(pos), "{:?} delivered twice", pos);
+        }
+        for x in -radius..radius + 1 {
+            for z in -radius..radius + 1 {
+                assert!(delivered.contains(&center.offset(x, 0, z)));
+            }
+        }
+    }
+}
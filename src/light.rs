@@ -0,0 +1,623 @@
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::collections::VecDeque;
+use std::mem;
+use std::sync::Arc;
+
+use block::BlockRegistry;
+use chunk;
+use chunk::ChunkPos;
+use chunk::CHUNK_SIZE;
+use chunk::CHUNK_VOLUME;
+use world::World;
+
+pub const MAX_LIGHT: u8 = 15;
+
+const DIRECTIONS: [[i32; 3]; 6] = [
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 1, 0],
+    [0, -1, 0],
+    [0, 0, 1],
+    [0, 0, -1],
+];
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Channel {
+    Sky,
+    Block,
+}
+
+// light levels of one chunk, sky light in the high nibble and block light in
+// the low one
+#[derive(Clone)]
+struct LightMap {
+    values: Vec<u8>,
+}
+
+impl LightMap {
+    fn new() -> LightMap {
+        LightMap {
+            values: vec![0; CHUNK_VOLUME],
+        }
+    }
+
+    fn get(&self, channel: Channel, i: usize) -> u8 {
+        match channel {
+            Channel::Sky => self.values[i] >> 4,
+            Channel::Block => self.values[i] & 0xf,
+        }
+    }
+
+    fn set(&mut self, channel: Channel, i: usize, level: u8) {
+        let value = &mut self.values[i];
+        *value = match channel {
+            Channel::Sky => (*value & 0xf) | (level << 4),
+            Channel::Block => (*value & 0xf0) | level,
+        };
+    }
+}
+
+fn index(p: [i32; 3]) -> usize {
+    (chunk::local(p[1]) * CHUNK_SIZE + chunk::local(p[2])) * CHUNK_SIZE + chunk::local(p[0])
+}
+
+fn add(p: [i32; 3], d: [i32; 3]) -> [i32; 3] {
+    [p[0] + d[0], p[1] + d[1], p[2] + d[2]]
+}
+
+// the level light reaches a neighbour with. sky light at full strength
+// travels straight down without getting weaker, everything else loses one
+// level per block.
+fn spread(channel: Channel, level: u8, direction: [i32; 3]) -> u8 {
+    if channel == Channel::Sky && level == MAX_LIGHT && direction[1] == -1 {
+        MAX_LIGHT
+    } else {
+        level.saturating_sub(1)
+    }
+}
+
+// sky and block light for every loaded chunk, flood filled outwards from the
+// light sources. anything above the highest loaded chunk of a column counts
+// as open sky.
+pub struct Lighting {
+    maps: HashMap<ChunkPos, Arc<LightMap>>,
+    // chunks whose meshes saw light change since the last update
+    changed: HashSet<ChunkPos>,
+}
+
+impl Lighting {
+    pub fn new() -> Lighting {
+        Lighting {
+            maps: HashMap::new(),
+            changed: HashSet::new(),
+        }
+    }
+
+    // lights every chunk of the world from scratch
+    pub fn compute(world: &World, blocks: &BlockRegistry) -> Lighting {
+        let mut lighting = Lighting::new();
+        for (&pos, _) in world.chunks() {
+            lighting.maps.insert(pos, Arc::new(LightMap::new()));
+        }
+
+        let mut sky = VecDeque::new();
+        let mut block_light = VecDeque::new();
+        for (&pos, _) in world.chunks() {
+            lighting.seed(world, blocks, pos, &mut sky, &mut block_light);
+        }
+        lighting.propagate(world, blocks, Channel::Sky, sky);
+        lighting.propagate(world, blocks, Channel::Block, block_light);
+        lighting
+    }
+
+    // lights a chunk that was added to the world after the lighting was
+    // computed, light from the chunks around it flows in as well. returns the
+    // chunks whose meshes need to pick up the new light.
+    pub fn add_chunk(
+        &mut self,
+        world: &World,
+        blocks: &BlockRegistry,
+        pos: ChunkPos,
+    ) -> HashSet<ChunkPos> {
+        self.changed.clear();
+        self.light_chunk(world, blocks, pos);
+        mem::replace(&mut self.changed, HashSet::new())
+    }
+
+    fn light_chunk(&mut self, world: &World, blocks: &BlockRegistry, pos: ChunkPos) {
+        if world.chunk(pos).is_none() {
+            return;
+        }
+        self.maps.insert(pos, Arc::new(LightMap::new()));
+
+        let mut sky = VecDeque::new();
+        let mut block_light = VecDeque::new();
+        self.seed(world, blocks, pos, &mut sky, &mut block_light);
+
+        // the faces of the neighbouring chunks that touch this one
+        let origin = pos.origin();
+        let size = CHUNK_SIZE as i32;
+        for &d in DIRECTIONS.iter() {
+            if !self.maps.contains_key(&pos.offset(d[0], d[1], d[2])) {
+                continue;
+            }
+            let axis = d.iter().position(|&n| n != 0).unwrap();
+            let u = (axis + 1) % 3;
+            let v = (axis + 2) % 3;
+            for j in 0..size {
+                for i in 0..size {
+                    let mut p = origin;
+                    p[axis] += if d[axis] > 0 { size } else { -1 };
+                    p[u] += i;
+                    p[v] += j;
+                    sky.push_back(p);
+                    block_light.push_back(p);
+                }
+            }
+        }
+
+        self.propagate(world, blocks, Channel::Sky, sky);
+        self.propagate(world, blocks, Channel::Block, block_light);
+
+        // the top of the chunk below isn't open to the sky any more, which
+        // only matters for columns that aren't still fully lit from above
+        let below = pos.offset(0, -1, 0);
+        if self.maps.contains_key(&below) {
+            let origin = below.origin();
+            let mut top = Vec::new();
+            for z in 0..size {
+                for x in 0..size {
+                    let p = add(origin, [x, size - 1, z]);
+                    if self.get(Channel::Sky, add(p, [0, 1, 0])) != Some(MAX_LIGHT) {
+                        top.push(p);
+                    }
+                }
+            }
+            self.relight(world, blocks, Channel::Sky, &top);
+        }
+    }
+
+    pub fn remove_chunk(&mut self, pos: ChunkPos) {
+        self.maps.remove(&pos);
+    }
+
+    // the light of the chunk at `center` and the ones around it, shares the
+    // light maps the same way World::region shares chunks
+    pub fn region(&self, center: ChunkPos) -> Lighting {
+        let mut region = Lighting::new();
+        for dz in -1..2 {
+            for dy in -1..2 {
+                for dx in -1..2 {
+                    let pos = center.offset(dx, dy, dz);
+                    if let Some(map) = self.maps.get(&pos) {
+                        region.maps.insert(pos, map.clone());
+                    }
+                }
+            }
+        }
+        region
+    }
+
+    // chunks without light data read as fully sky lit
+    pub fn sky(&self, x: i32, y: i32, z: i32) -> u8 {
+        self.get(Channel::Sky, [x, y, z]).unwrap_or(MAX_LIGHT)
+    }
+
+    pub fn block(&self, x: i32, y: i32, z: i32) -> u8 {
+        self.get(Channel::Block, [x, y, z]).unwrap_or(0)
+    }
+
+    // brings the light up to date after the block at x, y, z changed. light
+    // that may have come through the old block is taken away first and then
+    // filled back in from whatever is left around it. returns the chunks
+    // whose meshes need to pick up the new light.
+    pub fn update(
+        &mut self,
+        world: &World,
+        blocks: &BlockRegistry,
+        x: i32,
+        y: i32,
+        z: i32,
+    ) -> HashSet<ChunkPos> {
+        self.changed.clear();
+        let pos = ChunkPos::containing(x, y, z);
+        if !self.maps.contains_key(&pos) {
+            self.light_chunk(world, blocks, pos);
+        } else {
+            self.relight(world, blocks, Channel::Sky, &[[x, y, z]]);
+            self.relight(world, blocks, Channel::Block, &[[x, y, z]]);
+        }
+        mem::replace(&mut self.changed, HashSet::new())
+    }
+
+    fn get(&self, channel: Channel, p: [i32; 3]) -> Option<u8> {
+        self.maps
+            .get(&ChunkPos::containing(p[0], p[1], p[2]))
+            .map(|map| map.get(channel, index(p)))
+    }
+
+    fn set(&mut self, channel: Channel, p: [i32; 3], level: u8) {
+        let pos = ChunkPos::containing(p[0], p[1], p[2]);
+        let changed = match self.maps.get_mut(&pos) {
+            Some(map) if map.get(channel, index(p)) != level => {
+                Arc::make_mut(map).set(channel, index(p), level);
+                true
+            }
+            _ => false,
+        };
+        if !changed {
+            return;
+        }
+
+        // faces in the neighbouring chunks sample blocks along the border
+        let border = |v: i32| {
+            let local = chunk::local(v);
+            local == 0 || local == CHUNK_SIZE - 1
+        };
+        if border(p[0]) || border(p[1]) || border(p[2]) {
+            self.changed
+                .extend(chunk::chunks_touching(p[0], p[1], p[2]));
+        } else {
+            self.changed.insert(pos);
+        }
+    }
+
+    // the level a block gets from its own sources, emitted block light or
+    // sky light shining in from above the loaded world
+    fn source(&self, world: &World, blocks: &BlockRegistry, channel: Channel, p: [i32; 3]) -> u8 {
+        let block = world.get_block(p[0], p[1], p[2]);
+        match channel {
+            Channel::Block => blocks.light_emission(block),
+            Channel::Sky => {
+                let pos = ChunkPos::containing(p[0], p[1], p[2]);
+                let top = chunk::local(p[1]) == CHUNK_SIZE - 1;
+                if top && !blocks.is_opaque(block) && !self.maps.contains_key(&pos.offset(0, 1, 0))
+                {
+                    MAX_LIGHT
+                } else {
+                    0
+                }
+            }
+        }
+    }
+
+    fn seed(
+        &mut self,
+        world: &World,
+        blocks: &BlockRegistry,
+        pos: ChunkPos,
+        sky: &mut VecDeque<[i32; 3]>,
+        block_light: &mut VecDeque<[i32; 3]>,
+    ) {
+        let chunk = match world.chunk(pos) {
+            Some(chunk) => chunk,
+            None => return,
+        };
+        let origin = pos.origin();
+        let size = CHUNK_SIZE as i32;
+
+        if !chunk.is_empty() {
+            for y in 0..CHUNK_SIZE {
+                for z in 0..CHUNK_SIZE {
+                    for x in 0..CHUNK_SIZE {
+                        let emission = blocks.light_emission(chunk.get(x, y, z));
+                        if emission > 0 {
+                            let p = add(origin, [x as i32, y as i32, z as i32]);
+                            self.set(Channel::Block, p, emission);
+                            block_light.push_back(p);
+                        }
+                    }
+                }
+            }
+        }
+
+        for z in 0..size {
+            for x in 0..size {
+                let p = add(origin, [x, size - 1, z]);
+                if self.source(world, blocks, Channel::Sky, p) > 0 {
+                    self.set(Channel::Sky, p, MAX_LIGHT);
+                    sky.push_back(p);
+                }
+            }
+        }
+    }
+
+    fn propagate(
+        &mut self,
+        world: &World,
+        blocks: &BlockRegistry,
+        channel: Channel,
+        mut queue: VecDeque<[i32; 3]>,
+    ) {
+        while let Some(p) = queue.pop_front() {
+            let level = match self.get(channel, p) {
+                Some(level) if level > 0 => level,
+                _ => continue,
+            };
+            for &d in DIRECTIONS.iter() {
+                let next = spread(channel, level, d);
+                if next == 0 {
+                    continue;
+                }
+                let q = add(p, d);
+                if blocks.is_opaque(world.get_block(q[0], q[1], q[2])) {
+                    continue;
+                }
+                match self.get(channel, q) {
+                    Some(current) if current < next => {
+                        self.set(channel, q, next);
+                        queue.push_back(q);
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+
+    // relights around a set of changed blocks at once, which is a lot
+    // cheaper than one at a time when they share the light they darken
+    fn relight(
+        &mut self,
+        world: &World,
+        blocks: &BlockRegistry,
+        channel: Channel,
+        changed: &[[i32; 3]],
+    ) {
+        let mut removal = VecDeque::new();
+        let mut refill = VecDeque::new();
+
+        for &p in changed {
+            let old = self.get(channel, p).unwrap_or(0);
+            self.set(channel, p, 0);
+            removal.push_back((p, old));
+        }
+
+        // darken everything that got its light through the changed blocks,
+        // the lit blocks at the edge of that area fill it back in afterwards
+        while let Some((p, level)) = removal.pop_front() {
+            for &d in DIRECTIONS.iter() {
+                let q = add(p, d);
+                let current = match self.get(channel, q) {
+                    Some(current) if current > 0 => current,
+                    _ => continue,
+                };
+                if current < level || (level > 0 && spread(channel, level, d) == current) {
+                    self.set(channel, q, 0);
+                    removal.push_back((q, current));
+                    let source = self.source(world, blocks, channel, q);
+                    if source > 0 {
+                        self.set(channel, q, source);
+                        refill.push_back(q);
+                    }
+                } else {
+                    refill.push_back(q);
+                }
+            }
+        }
+
+        for &p in changed {
+            let source = self.source(world, blocks, channel, p);
+            if source > 0 {
+                self.set(channel, p, source);
+                refill.push_back(p);
+            }
+        }
+
+        self.propagate(world, blocks, channel, refill);
+    }
+}
+
+impl Default for Lighting {
+    fn default() -> Lighting {
+        Lighting::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use block;
+    use chunk::Chunk;
+    use noise;
+
+    // three by three by three chunks of solid stone with a hollow box in the
+    // middle one
+    fn sealed_cave(half: i32) -> World {
+        let mut world = World::new();
+        for y in -1..2 {
+            for z in -1..2 {
+                for x in -1..2 {
+                    world.insert_chunk(ChunkPos::new(x, y, z), Chunk::filled(block::STONE));
+                }
+            }
+        }
+        for y in -half..half + 1 {
+            for z in -half..half + 1 {
+                for x in -half..half + 1 {
+                    world.set_block(16 + x, 16 + y, 16 + z, block::AIR);
+                }
+            }
+        }
+        world
+    }
+
+    fn manhattan(a: [i32; 3], b: [i32; 3]) -> i32 {
+        (a[0] - b[0]).abs() + (a[1] - b[1]).abs() + (a[2] - b[2]).abs()
+    }
+
+    // compares every block of the loaded world against a fresh computation
+    fn assert_matches_fresh(world: &World, lighting: &Lighting) {
+        let fresh = Lighting::compute(world, &block::test_registry());
+        for (&pos, _) in world.chunks() {
+            let a = &lighting.maps[&pos];
+            let b = &fresh.maps[&pos];
+            for i in 0..CHUNK_VOLUME {
+                assert_eq!(
+                    a.values[i], b.values[i],
+                    "chunk {:?} index {}: incremental {:x} fresh {:x}",
+                    pos, i, a.values[i], b.values[i]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn torch_in_sealed_cave_lights_expected_radius() {
+        let blocks = block::test_registry();
+        let half = 14;
+        let mut world = sealed_cave(half);
+        let torch = [16, 16, 16];
+        world.set_block(torch[0], torch[1], torch[2], block::TORCH);
+        let lighting = Lighting::compute(&world, &blocks);
+
+        for y in -32..64 {
+            for z in -32..64 {
+                for x in -32..64 {
+                    let p = [x, y, z];
+                    let inside = (0..3).all(|a| (p[a] - torch[a]).abs() <= half);
+                    let expected = if p == torch {
+                        14
+                    } else if inside {
+                        (14 - manhattan(p, torch)).max(0) as u8
+                    } else {
+                        0
+                    };
+                    assert_eq!(lighting.block(x, y, z), expected, "at {:?}", p);
+                    assert_eq!(lighting.sky(x, y, z), 0, "at {:?}", p);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn sky_light_falls_through_open_columns() {
+        let blocks = block::test_registry();
+        let mut world = World::new();
+        world.insert_chunk(ChunkPos::new(0, 0, 0), Chunk::new());
+        world.insert_chunk(ChunkPos::new(0, -1, 0), Chunk::new());
+        // a roof with a single hole in it
+        for z in 0..CHUNK_SIZE as i32 {
+            for x in 0..CHUNK_SIZE as i32 {
+                if (x, z) != (10, 10) {
+                    world.set_block(x, 20, z, block::STONE);
+                }
+            }
+        }
+        let lighting = Lighting::compute(&world, &blocks);
+
+        assert_eq!(lighting.sky(0, 31, 0), MAX_LIGHT);
+        assert_eq!(lighting.sky(0, 21, 0), MAX_LIGHT);
+        assert_eq!(lighting.sky(0, 20, 0), 0);
+        // straight down through the hole without losing anything
+        assert_eq!(lighting.sky(10, 20, 10), MAX_LIGHT);
+        assert_eq!(lighting.sky(10, -32, 10), MAX_LIGHT);
+        // and spreading out sideways underneath the roof
+        assert_eq!(lighting.sky(12, 5, 10), MAX_LIGHT - 2);
+        assert_eq!(lighting.sky(10, 19, 13), MAX_LIGHT - 3);
+    }
+
+    #[test]
+    fn removing_torch_darkens_cave() {
+        let blocks = block::test_registry();
+        let mut world = sealed_cave(6);
+        world.set_block(16, 16, 16, block::TORCH);
+        let mut lighting = Lighting::compute(&world, &blocks);
+        assert_eq!(lighting.block(16, 16, 20), 10);
+
+        world.set_block(16, 16, 16, block::AIR);
+        let changed = lighting.update(&world, &blocks, 16, 16, 16);
+        // the light reaches 14 blocks at most, not past the middle chunk
+        assert_eq!(
+            changed.into_iter().collect::<Vec<_>>(),
+            vec![ChunkPos::new(0, 0, 0)]
+        );
+        for (&pos, _) in world.chunks() {
+            assert!(lighting.maps[&pos].values.iter().all(|&v| v == 0));
+        }
+
+        world.set_block(18, 16, 16, block::TORCH);
+        lighting.update(&world, &blocks, 18, 16, 16);
+        assert_eq!(lighting.block(16, 16, 16), 12);
+        assert_matches_fresh(&world, &lighting);
+    }
+
+    #[test]
+    fn opening_cave_lets_sky_in() {
+        let blocks = block::test_registry();
+        let mut world = sealed_cave(4);
+        let mut lighting = Lighting::compute(&world, &blocks);
+        assert_eq!(lighting.sky(16, 16, 16), 0);
+
+        // a shaft from the top of the world down into the cave
+        for y in 21..80 {
+            world.set_block(16, y, 16, block::AIR);
+            lighting.update(&world, &blocks, 16, y, 16);
+        }
+        assert_eq!(lighting.sky(16, 12, 16), MAX_LIGHT);
+        assert_eq!(lighting.sky(18, 12, 16), MAX_LIGHT - 2);
+        assert_matches_fresh(&world, &lighting);
+
+        // closing it again puts the cave back into darkness
+        world.set_block(16, 40, 16, block::STONE);
+        lighting.update(&world, &blocks, 16, 40, 16);
+        assert_eq!(lighting.sky(16, 16, 16), 0);
+        assert_matches_fresh(&world, &lighting);
+    }
+
+    #[test]
+    fn added_chunks_match_full_computation() {
+        let blocks = block::test_registry();
+        let mut world = World::new();
+        world.insert_chunk(ChunkPos::new(0, 0, 0), Chunk::new());
+        world.set_block(5, 5, 5, block::TORCH);
+        let mut lighting = Lighting::compute(&world, &blocks);
+        assert_eq!(lighting.sky(20, 0, 20), MAX_LIGHT);
+
+        // a roof goes on top and a neighbour with a torch of its own
+        world.insert_chunk(ChunkPos::new(0, 1, 0), Chunk::filled(block::STONE));
+        lighting.add_chunk(&world, &blocks, ChunkPos::new(0, 1, 0));
+        world.insert_chunk(ChunkPos::new(1, 0, 0), Chunk::new());
+        world.set_block(33, 5, 5, block::TORCH);
+        lighting.add_chunk(&world, &blocks, ChunkPos::new(1, 0, 0));
+
+        assert_eq!(lighting.sky(5, 0, 20), 0);
+        assert_eq!(lighting.sky(30, 0, 20), MAX_LIGHT - 2);
+        assert_matches_fresh(&world, &lighting);
+    }
+
+    #[test]
+    fn incremental_updates_match_full_computation() {
+        let blocks = block::test_registry();
+        let mut world = World::new();
+        for cy in -1..1 {
+            for cz in 0..2 {
+                for cx in 0..2 {
+                    world.insert_chunk(ChunkPos::new(cx, cy, cz), Chunk::new());
+                }
+            }
+        }
+        for z in 0..64 {
+            for x in 0..64 {
+                let height = ((x / 6 + z / 9) % 5) as i32;
+                for y in -32..height {
+                    world.set_block(x, y, z, block::STONE);
+                }
+            }
+        }
+        let mut lighting = Lighting::compute(&world, &blocks);
+
+        let kinds = [block::AIR, block::STONE, block::TORCH, block::WATER];
+        for i in 0..300 {
+            let r = noise::mix(i);
+            let x = (r % 64) as i32;
+            let z = ((r >> 8) % 64) as i32;
+            let y = ((r >> 16) % 24) as i32 - 16;
+            let block = kinds[((r >> 24) % 4) as usize];
+            world.set_block(x, y, z, block);
+            lighting.update(&world, &blocks, x, y, z);
+        }
+        assert_matches_fresh(&world, &lighting);
+    }
+}
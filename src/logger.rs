@@ -0,0 +1,82 @@
+use log;
+use log::Level;
+use log::LevelFilter;
+use log::Log;
+use log::Metadata;
+use log::Record;
+
+use std::env;
+use std::fmt;
+
+// RSCRAFT_LOG picks the most verbose level that's printed, e.g.
+// RSCRAFT_LOG=debug
+pub const LEVEL_VAR: &'static str = "RSCRAFT_LOG";
+
+// everything goes to stderr so that it doesn't mix with output like
+// `--print-config`
+struct Logger;
+
+static LOGGER: Logger = Logger;
+
+impl Log for Logger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+    }
+
+    fn log(&self, record: &Record) {
+        if self.enabled(record.metadata()) {
+            eprintln!(
+                "{}",
+                format_record(record.level(), record.target(), record.args())
+            );
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+pub fn init() {
+    let level = level_from(
+        env::var(LEVEL_VAR)
+            .ok()
+            .as_ref()
+            .map(|value| value.as_str()),
+    );
+    // only fails if a logger was already installed
+    if log::set_logger(&LOGGER).is_ok() {
+        log::set_max_level(level);
+    }
+}
+
+// info unless something else is asked for
+fn level_from(value: Option<&str>) -> LevelFilter {
+    value
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(LevelFilter::Info)
+}
+
+fn format_record(level: Level, target: &str, message: &fmt::Arguments) -> String {
+    format!("[{} {}] {}", level, target, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels() {
+        assert_eq!(level_from(None), LevelFilter::Info);
+        assert_eq!(level_from(Some("debug")), LevelFilter::Debug);
+        assert_eq!(level_from(Some("WARN")), LevelFilter::Warn);
+        assert_eq!(level_from(Some("off")), LevelFilter::Off);
+        assert_eq!(level_from(Some("loud")), LevelFilter::Info);
+    }
+
+    #[test]
+    fn records_name_their_level_and_target() {
+        assert_eq!(
+            format_record(Level::Warn, "vulkan", &format_args!("{} leaked", "image")),
+            "[WARN vulkan] image leaked"
+        );
+    }
+}
@@ -3,362 +3,341 @@ extern crate vulkano;
 #[macro_use]
 extern crate vulkano_shader_derive;
 extern crate cgmath;
+extern crate flate2;
+extern crate image;
+#[macro_use]
+extern crate log;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+extern crate toml;
 extern crate vulkano_win;
 extern crate winit;
 
-use vulkano::buffer::BufferUsage;
-use vulkano::buffer::CpuAccessibleBuffer;
-use vulkano::command_buffer::AutoCommandBufferBuilder;
-use vulkano::command_buffer::DynamicState;
-use vulkano::device::Device;
-use vulkano::framebuffer::Framebuffer;
-use vulkano::framebuffer::Subpass;
-use vulkano::instance::Instance;
-use vulkano::pipeline::vertex::SingleBufferDefinition;
-use vulkano::pipeline::vertex::TwoBuffersDefinition;
-use vulkano::pipeline::viewport::Viewport;
-use vulkano::pipeline::GraphicsPipeline;
-use vulkano::swapchain;
-use vulkano::swapchain::AcquireError;
-use vulkano::swapchain::PresentMode;
-use vulkano::swapchain::SurfaceTransform;
-use vulkano::swapchain::Swapchain;
-use vulkano::swapchain::SwapchainCreationError;
-use vulkano::sync::now;
-use vulkano::sync::GpuFuture;
-use vulkano_win::VkSurfaceBuild;
-
+use std::collections::HashMap;
+use std::error::Error;
 use std::mem;
 use std::sync::Arc;
 
-mod teapot;
-
-fn main() {
-    let instance = {
-        let extensions = vulkano_win::required_extensions();
-        Instance::new(None, &extensions, None).expect("failed to create Vulkan instance")
-    };
+use atlas::Atlas;
+use block::BlockRegistry;
+use block::RenderLayer;
+use breaking::Breaking;
+use camera::Camera;
+use camera::KeyBindings;
+use camera::Movement;
+use chunk::ChunkPos;
+use jobs::JobPool;
+use mesh::MeshData;
+use mesher::ChunkMesh;
+use physics::FixedTimestep;
+use physics::Player;
+use physics::PlayerInput;
+use renderer::Mesh;
+use renderer::RenderError;
+use renderer::Renderer;
+use renderer::Scene;
+use renderer::SceneObject;
+use settings::Settings;
+use storage::WorldStorage;
+use streaming::ChunkManager;
+use worldgen::TerrainGenerator;
+
+mod arena;
+mod atlas;
+mod block;
+mod breaking;
+mod camera;
+mod chunk;
+mod debug;
+mod frustum;
+mod gpu;
+mod headless;
+mod jobs;
+mod light;
+mod logger;
+mod mesh;
+mod mesher;
+mod noise;
+mod obj;
+mod physics;
+mod pipeline;
+mod raycast;
+mod renderer;
+mod settings;
+mod storage;
+mod streaming;
+mod world;
+mod worldgen;
+
+// how far away blocks can be broken and placed
+const REACH: f32 = 8.0;
+// what right click places until something is broken, after that it places
+// what the last broken block dropped. any block from assets/blocks.toml.
+const PLACED_BLOCK: &'static str = "stone";
+const WORKER_THREADS: usize = 3;
+// the horizontal distance comes from the settings
+const VERTICAL_VIEW_DISTANCE: i32 = 2;
+// translucent faces of chunks this close to the camera are sorted again
+// once it has moved RESORT_AFTER blocks away from where they were last
+// sorted or into another chunk, further out they're left in the order they
+// were meshed in
+const RESORT_DISTANCE: i32 = 1;
+const RESORT_AFTER: f32 = 2.0;
+const LAYERS: [RenderLayer; 3] = [
+    RenderLayer::Opaque,
+    RenderLayer::Cutout,
+    RenderLayer::Translucent,
+];
+
+// the uploaded meshes of every chunk, one per render layer that has any
+// faces. translucent faces are kept around on the cpu as well so that they
+// can be sorted again as the camera moves.
+#[derive(Default)]
+struct ChunkMeshes {
+    uploaded: HashMap<(ChunkPos, RenderLayer), (Mesh, cgmath::Vector3<f32>)>,
+    translucent: HashMap<ChunkPos, MeshData>,
+}
 
-    let physical = vulkano::instance::PhysicalDevice::enumerate(&instance)
-        .next()
-        .expect("no device available");
-    println!(
-        "Using device: {} (type: {:?})",
-        physical.name(),
-        physical.ty()
-    );
+impl ChunkMeshes {
+    fn remove(&mut self, pos: ChunkPos) {
+        for &layer in LAYERS.iter() {
+            self.uploaded.remove(&(pos, layer));
+        }
+        self.translucent.remove(&pos);
+    }
+}
 
-    let mut events_loop = winit::EventsLoop::new();
-    let window = winit::WindowBuilder::new()
-        .build_vk_surface(&events_loop, instance.clone())
-        .unwrap();
+fn main() {
+    logger::init();
+
+    // `rscraft --headless out.png [width height] [model.obj]` renders one
+    // frame of a model offscreen, the teapot by default
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() > 1 && args[1] == "--headless" {
+        let path = args.get(2).map(|s| s.as_str()).unwrap_or("frame.png");
+        let width = args.get(3).and_then(|s| s.parse().ok()).unwrap_or(1024);
+        let height = args.get(4).and_then(|s| s.parse().ok()).unwrap_or(768);
+        let model = args
+            .get(5)
+            .map(|s| s.as_str())
+            .unwrap_or("assets/teapot.obj");
+        match headless::render_to_png(path, [width, height], model) {
+            Ok(()) => println!("Wrote {}", path),
+            Err(err) => {
+                eprintln!("error: {}", err);
+                std::process::exit(1);
+            }
+        }
+        return;
+    }
 
-    let mut dimensions = {
-        let (width, height) = window.window().get_inner_size().unwrap();
-        [width, height]
+    // everything else is read from settings.toml and the flags
+    let options = Settings::from_args(&args[1..]).and_then(|options| {
+        let bindings = KeyBindings::new(&options.settings.keys)?;
+        Ok((options, bindings))
+    });
+    let (settings, bindings) = match options {
+        Ok((options, bindings)) => {
+            if options.print_config {
+                print!("{}", options.settings.to_toml());
+                return;
+            }
+            if options.list_devices {
+                if let Err(err) = renderer::list_devices(&winit::EventsLoop::new()) {
+                    eprintln!("error: {}", err);
+                    std::process::exit(1);
+                }
+                return;
+            }
+            (options.settings, bindings)
+        }
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(2);
+        }
     };
 
-    println!("Dimensions: {}, {}", dimensions[0], dimensions[1]);
-
-    let queue = physical
-        .queue_families()
-        .find(|&q| {
-            // We take the first queue that supports drawing to our window.
-            q.supports_graphics() && window.is_supported(q).unwrap_or(false)
-        })
-        .expect("couldn't find a graphical queue family");
-
-    let (device, mut queues) = {
-        let device_ext = vulkano::device::DeviceExtensions {
-            khr_swapchain: true,
-            ..vulkano::device::DeviceExtensions::none()
-        };
+    if let Err(err) = run(&settings, &bindings) {
+        eprintln!("error: {}", err);
+        std::process::exit(1);
+    }
+}
 
-        Device::new(
-            physical,
-            physical.supported_features(),
-            &device_ext,
-            [(queue, 0.5)].iter().cloned(),
-        ).expect("failed to create device")
-    };
+// opens the window and plays until it's closed
+fn run(settings: &Settings, bindings: &KeyBindings) -> Result<(), Box<Error>> {
+    let blocks = Arc::new(BlockRegistry::load(block::DEFAULT_PATH)?);
+    let mut placed_block = blocks
+        .by_name(PLACED_BLOCK)
+        .ok_or_else(|| format!("there's no '{}' block to place", PLACED_BLOCK))?;
 
-    let queue = queues.next().unwrap();
-
-    let (mut swapchain, mut images) = {
-        let caps = window
-            .capabilities(physical)
-            .expect("failed to get surface capabilities");
-
-        println!(
-            "caps image extent min: {:?} max: {:?}, current_extent: {:?}",
-            caps.min_image_extent, caps.max_image_extent, caps.current_extent
-        );
-
-        let alpha = caps.supported_composite_alpha.iter().next().unwrap();
-        dimensions = caps.current_extent.unwrap_or(dimensions);
-
-        let format = caps.supported_formats[0].0;
-
-        Swapchain::new(
-            device.clone(),
-            window.clone(),
-            caps.min_image_count,
-            format,
-            dimensions,
-            1,
-            caps.supported_usage_flags,
-            &queue,
-            SurfaceTransform::Identity,
-            alpha,
-            PresentMode::Fifo,
-            true,
-            None,
-        ).expect("failed to create swapchain")
-    };
+    let mut events_loop = winit::EventsLoop::new();
+    let mut renderer = Renderer::new_windowed(&events_loop, settings)?;
+    grab_cursor(&renderer);
+
+    let (atlas, atlas_image) = Atlas::load("assets/blocks")
+        .map_err(|err| format!("failed to load the block textures: {}", err))?;
+    renderer.upload_atlas(&atlas_image)?;
+    // not fatal, those faces just show the missing texture
+    for ty in blocks.types() {
+        let mut textures: Vec<_> = ty.textures.iter().collect();
+        textures.sort();
+        textures.dedup();
+        for texture in textures
+            .into_iter()
+            .filter(|&texture| !atlas.contains(texture))
+        {
+            warn!(
+                "block '{}' uses texture '{}', which isn't in assets/blocks",
+                ty.name, texture
+            );
+        }
+    }
 
-    let mut depth_buffer = vulkano::image::attachment::AttachmentImage::transient(
-        device.clone(),
-        dimensions,
-        vulkano::format::D16Unorm,
-    ).unwrap();
-
-    let vertex_buffer = vulkano::buffer::cpu_access::CpuAccessibleBuffer::from_iter(
-        device.clone(),
-        vulkano::buffer::BufferUsage::all(),
-        teapot::VERTICES.iter().cloned(),
-    ).expect("failed to create vertex buffer");
-
-    let normals_buffer = vulkano::buffer::cpu_access::CpuAccessibleBuffer::from_iter(
-        device.clone(),
-        vulkano::buffer::BufferUsage::all(),
-        teapot::NORMALS.iter().cloned(),
-    ).expect("failed to create normals buffer");
-
-    let index_buffer = vulkano::buffer::cpu_access::CpuAccessibleBuffer::from_iter(
-        device.clone(),
-        vulkano::buffer::BufferUsage::all(),
-        teapot::INDICES.iter().cloned(),
-    ).expect("failed to create index buffer");
-
-    // note: this teapot was meant for OpenGL where the origin is at the lower left
-    //       instead the origin is at the upper left in vulkan, so we reverse the Y axis
-    let mut proj = cgmath::perspective(
-        cgmath::Rad(std::f32::consts::FRAC_PI_2),
-        { dimensions[0] as f32 / dimensions[1] as f32 },
-        0.01,
-        1024.0,
+    let storage = WorldStorage::open("world")?;
+    let pool = JobPool::new(
+        WORKER_THREADS,
+        Arc::new(TerrainGenerator::new(0, &blocks)?),
+        Arc::new(atlas),
+        blocks.clone(),
     );
-    let view = cgmath::Matrix4::look_at(
-        cgmath::Point3::new(0.3, 0.3, 1.0),
-        cgmath::Point3::new(0.0, 0.0, 0.0),
-        cgmath::Vector3::new(0.0, -1.0, 0.0),
+    let mut chunks = ChunkManager::new(
+        storage,
+        pool,
+        blocks.clone(),
+        settings.render_distance,
+        VERTICAL_VIEW_DISTANCE,
     );
-    let scale = cgmath::Matrix4::from_scale(0.01);
+    let mut chunk_meshes = ChunkMeshes::default();
+    // the chunk and position of the camera when translucent faces were last
+    // sorted
+    let mut sorted_from: Option<(ChunkPos, [f32; 3])> = None;
+
+    let mut camera = Camera::new(cgmath::Point3::new(0.0, 30.0, 40.0), 0.0, -0.4);
+    let mut movement = Movement::default();
+    // F switches between flying and walking, the player only exists while
+    // walking
+    let mut player: Option<Player> = None;
+    let mut clock = FixedTimestep::new();
+    // left breaks the block under the crosshair for as long as it's held
+    let mut breaking = Breaking::default();
+    let mut holding_left = false;
+
+    let mut last_frame = std::time::Instant::now();
+    let mut last_title = std::time::Instant::now();
 
-    let uniform_buffer = vulkano::buffer::cpu_pool::CpuBufferPool::<vs::ty::Data>::new(
-        device.clone(),
-        vulkano::buffer::BufferUsage::all(),
-    );
-
-    let vs = vs::Shader::load(device.clone()).expect("failed to create shader module");
-    let fs = fs::Shader::load(device.clone()).expect("failed to create shader module");
-
-    let render_pass = Arc::new(
-        single_pass_renderpass!(device.clone(),
-        attachments: {
-            color: {
-                load: Clear,
-                store: Store,
-                format: swapchain.format(),
-                samples: 1,
-            },
-            depth: {
-                    load: Clear,
-                    store: DontCare,
-                    format: vulkano::format::Format::D16Unorm,
-                    samples: 1,
+    loop {
+        if renderer.is_paused() {
+            // nothing can be drawn while minimized, so rather than spinning
+            // this waits until the window changes size again
+            let mut done = false;
+            events_loop.run_forever(|ev| match ev {
+                winit::Event::WindowEvent {
+                    event: winit::WindowEvent::Closed,
+                    ..
+                } => {
+                    done = true;
+                    winit::ControlFlow::Break
+                }
+                winit::Event::WindowEvent {
+                    event: winit::WindowEvent::Resized(_, _),
+                    ..
+                } => {
+                    renderer.resize();
+                    winit::ControlFlow::Break
                 }
-        },
-        pass: {
-            color: [color],
-            depth_stencil: {depth}
+                _ => winit::ControlFlow::Continue,
+            });
+            if done {
+                return Ok(());
+            }
+            // the time spent minimized isn't simulated
+            last_frame = std::time::Instant::now();
         }
-    ).unwrap(),
-    );
-
-    let pipeline = Arc::new(
-        GraphicsPipeline::start()
-            .vertex_input(TwoBuffersDefinition::new())
-            .vertex_shader(vs.main_entry_point(), ())
-            .triangle_list()
-            .viewports_dynamic_scissors_irrelevant(1)
-            .fragment_shader(fs.main_entry_point(), ())
-            .depth_stencil_simple_depth()
-            .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
-            .build(device.clone())
-            .unwrap(),
-    );
-
-    let mut framebuffers: Option<Vec<Arc<vulkano::framebuffer::Framebuffer<_, _>>>> = None;
-
-    let mut recreate_swapchain = false;
-
-    let mut previous_frame_end = Box::new(now(device.clone())) as Box<GpuFuture>;
-    let rotation_start = std::time::Instant::now();
 
-    loop {
-        previous_frame_end.cleanup_finished();
-
-        if recreate_swapchain {
-            dimensions = {
-                // using this causes it to flip out and get stuck with
-                // SwapchainCreationError::UnsupportedDimensions errors
-                // seems like high dpi issues ???
-                let window_inner = window.window().get_inner_size().unwrap();
-                // [new_width, new_height]
-                let current_extent = window
-                    .capabilities(physical)
-                    .unwrap()
-                    .current_extent
-                    .unwrap();
-
-                println!(
-                    "in recreate swapchain, window_inner: {:?}, current_extent: {:?}",
-                    window_inner, current_extent
-                );
-                current_extent
-            };
-
-            println!("Recreate Dimensions: {}, {}", dimensions[0], dimensions[1]);
-
-            let (new_swapchain, new_images) = match swapchain.recreate_with_dimension(dimensions) {
-                Ok(r) => r,
-                Err(SwapchainCreationError::UnsupportedDimensions) => {
-                    continue;
+        let dt = {
+            let elapsed = last_frame.elapsed();
+            last_frame = std::time::Instant::now();
+            elapsed.as_secs() as f32 + elapsed.subsec_nanos() as f32 / 1_000_000_000.0
+        };
+        match player {
+            Some(ref mut player) => {
+                let input = PlayerInput {
+                    direction: camera.walk_direction(&movement),
+                    jump: movement.jumping(),
+                };
+                for _ in 0..clock.advance(dt) {
+                    player.step(chunks.world(), &blocks, &input);
                 }
-                Err(err) => panic!("{:?}", err),
-            };
-
-            mem::replace(&mut swapchain, new_swapchain);
-            mem::replace(&mut images, new_images);
-
-            let new_depth_buffer = vulkano::image::attachment::AttachmentImage::transient(
-                device.clone(),
-                dimensions,
-                vulkano::format::D16Unorm,
-            ).unwrap();
-            std::mem::replace(&mut depth_buffer, new_depth_buffer);
-
-            framebuffers = None;
-
-            proj = cgmath::perspective(
-                cgmath::Rad(std::f32::consts::FRAC_PI_2),
-                { dimensions[0] as f32 / dimensions[1] as f32 },
-                0.01,
-                100.0,
-            );
+                let eye = player.eye(clock.alpha());
+                camera.position = cgmath::Point3::new(eye[0], eye[1], eye[2]);
+            }
+            None => camera.update(&movement, dt),
+        }
 
-            recreate_swapchain = false;
+        let p = camera.position;
+        let eye = [p.x, p.y, p.z];
+        let camera_block = [p.x.floor() as i32, p.y.floor() as i32, p.z.floor() as i32];
+        let camera_chunk = ChunkPos::containing(camera_block[0], camera_block[1], camera_block[2]);
+        let changes = chunks.update(camera_chunk);
+        for pos in changes.unloaded {
+            chunk_meshes.remove(pos);
         }
-        if framebuffers.is_none() {
-            let new_framebuffers = Some(
-                images
-                    .iter()
-                    .map(|image| {
-                        Arc::new(
-                            vulkano::framebuffer::Framebuffer::start(render_pass.clone())
-                                .add(image.clone())
-                                .unwrap()
-                                .add(depth_buffer.clone())
-                                .unwrap()
-                                .build()
-                                .unwrap(),
-                        )
-                    })
-                    .collect::<Vec<_>>(),
-            );
-            std::mem::replace(&mut framebuffers, new_framebuffers);
+        upload_chunk_meshes(&mut renderer, &mut chunk_meshes, changes.meshes, eye)?;
+        let resort = match sorted_from {
+            Some((chunk, from)) => {
+                let moved = (0..3).map(|i| (eye[i] - from[i]).powi(2)).sum::<f32>();
+                chunk != camera_chunk || moved >= RESORT_AFTER * RESORT_AFTER
+            }
+            None => true,
+        };
+        if resort {
+            sorted_from = Some((camera_chunk, eye));
+            resort_translucent(&mut renderer, &mut chunk_meshes, camera_chunk, eye)?;
         }
 
-        let uniform_buffer_subbuffer = {
-            let elapsed = rotation_start.elapsed();
-            let rotation =
-                elapsed.as_secs() as f64 + elapsed.subsec_nanos() as f64 / 1_000_000_000.0;
-            let rotation = cgmath::Matrix3::from_angle_y(cgmath::Rad(rotation as f32))
-                * cgmath::Matrix3::from_angle_z(cgmath::Rad(rotation as f32));
-
-            let uniform_data = vs::ty::Data {
-                world: cgmath::Matrix4::from(rotation).into(),
-                view: (view * scale).into(),
-                proj: proj.into(),
-            };
-
-            uniform_buffer.next(uniform_data).unwrap()
+        let scene = Scene {
+            view: camera.view(),
+            objects: chunk_meshes
+                .uploaded
+                .iter()
+                .map(|(&(_, layer), &(ref mesh, translation))| SceneObject {
+                    mesh: mesh,
+                    world: cgmath::Matrix4::from_translation(translation),
+                    layer: layer,
+                })
+                .collect(),
         };
 
-        let set = Arc::new(
-            vulkano::descriptor::descriptor_set::PersistentDescriptorSet::start(
-                pipeline.clone(),
-                0,
-            ).add_buffer(uniform_buffer_subbuffer)
-                .unwrap()
-                .build()
-                .unwrap(),
-        );
-
-        let (image_num, acquire_future) =
-            match swapchain::acquire_next_image(swapchain.clone(), None) {
-                Ok(r) => r,
-                Err(AcquireError::OutOfDate) => {
-                    recreate_swapchain = true;
-                    continue;
-                }
-                Err(err) => panic!("{:?}", err),
-            };
+        let drawn = match renderer.draw(&scene) {
+            Ok(Some(frame)) => renderer.present(frame),
+            Ok(None) => Ok(()),
+            Err(err) => Err(err),
+        };
+        match drawn {
+            Ok(()) => {}
+            Err(RenderError::SurfaceLost) => {
+                warn!("lost the window surface, opening a new window");
+                renderer.rebuild_surface(&events_loop)?;
+                grab_cursor(&renderer);
+            }
+            Err(err) => return Err(err.into()),
+        }
 
-        let command_buffer =
-            vulkano::command_buffer::AutoCommandBufferBuilder::primary_one_time_submit(
-                device.clone(),
-                queue.family(),
-            ).unwrap()
-                .begin_render_pass(
-                    framebuffers.as_ref().unwrap()[image_num].clone(),
-                    false,
-                    vec![[0.0, 0.0, 1.0, 1.0].into(), 1f32.into()],
-                )
-                .unwrap()
-                .draw_indexed(
-                    pipeline.clone(),
-                    vulkano::command_buffer::DynamicState {
-                        line_width: None,
-                        viewports: Some(vec![vulkano::pipeline::viewport::Viewport {
-                            origin: [0.0, 0.0],
-                            dimensions: [dimensions[0] as f32, dimensions[1] as f32],
-                            depth_range: 0.0..1.0,
-                        }]),
-                        scissors: None,
-                    },
-                    (vertex_buffer.clone(), normals_buffer.clone()),
-                    index_buffer.clone(),
-                    set.clone(),
-                    (),
-                )
-                .unwrap()
-                .end_render_pass()
-                .unwrap()
-                .build()
-                .unwrap();
-
-        let future = previous_frame_end
-            .join(acquire_future)
-            .then_execute(queue.clone(), command_buffer)
-            .unwrap()
-            .then_swapchain_present(queue.clone(), swapchain.clone(), image_num)
-            .then_signal_fence_and_flush()
-            .unwrap();
-        previous_frame_end = Box::new(future) as Box<_>;
+        if last_title.elapsed().as_secs() >= 1 {
+            last_title = std::time::Instant::now();
+            let stats = renderer.stats();
+            if let Some(window) = renderer.window() {
+                window.set_title(&format!(
+                    "rscraft - {} meshes drawn, {} culled",
+                    stats.drawn, stats.culled
+                ));
+            }
+        }
 
         let mut done = false;
+        let mut toggle_walking = false;
+        let mut clicks = Vec::new();
         events_loop.poll_events(|ev| match ev {
             winit::Event::WindowEvent {
                 event: winit::WindowEvent::Closed,
@@ -367,51 +346,196 @@ fn main() {
             winit::Event::WindowEvent {
                 event: winit::WindowEvent::Resized(_, _),
                 ..
-            } => recreate_swapchain = true,
-            x => println!("event: {:?}", x),
+            } => renderer.resize(),
+            winit::Event::WindowEvent {
+                event:
+                    winit::WindowEvent::KeyboardInput {
+                        input:
+                            winit::KeyboardInput {
+                                state,
+                                virtual_keycode: Some(key),
+                                ..
+                            },
+                        ..
+                    },
+                ..
+            } => {
+                let pressed = state == winit::ElementState::Pressed;
+                if key == bindings.quit {
+                    done = true;
+                } else if key == bindings.walk {
+                    toggle_walking |= pressed;
+                } else {
+                    movement.key(bindings, key, pressed);
+                }
+            }
+            winit::Event::WindowEvent {
+                event: winit::WindowEvent::MouseInput { state, button, .. },
+                ..
+            } => {
+                let pressed = state == winit::ElementState::Pressed;
+                if button == winit::MouseButton::Left {
+                    holding_left = pressed;
+                } else if pressed {
+                    clicks.push(button);
+                }
+            }
+            winit::Event::DeviceEvent {
+                event: winit::DeviceEvent::MouseMotion { delta: (dx, dy) },
+                ..
+            } => camera.rotate(dx, dy),
+            _ => (),
         });
         if done {
-            return;
+            return Ok(());
+        }
+
+        if toggle_walking {
+            player = match player {
+                Some(_) => None,
+                None => {
+                    let p = camera.position;
+                    clock = FixedTimestep::new();
+                    Some(Player::new([p.x, p.y - physics::EYE_HEIGHT, p.z]))
+                }
+            };
+        }
+
+        let forward = camera.forward();
+        let origin = [camera.position.x, camera.position.y, camera.position.z];
+        let direction = [forward.x, forward.y, forward.z];
+        match raycast::raycast(chunks.world(), &blocks, origin, direction, REACH) {
+            Some(hit) if holding_left => {
+                if let Some(drop) = breaking.update(&blocks, hit.position, hit.block, dt) {
+                    let p = hit.position;
+                    chunks.set_block(p[0], p[1], p[2], block::AIR);
+                    if let Some(drop) = drop {
+                        placed_block = drop;
+                    }
+                }
+            }
+            _ => breaking.stop(),
+        }
+
+        // right places a block against the face under the crosshair
+        for button in clicks {
+            if button != winit::MouseButton::Right {
+                continue;
+            }
+            let hit = match raycast::raycast(chunks.world(), &blocks, origin, direction, REACH) {
+                Some(hit) if hit.normal != [0; 3] => hit,
+                _ => continue,
+            };
+            let (p, n) = (hit.position, hit.normal);
+            let (x, y, z) = (p[0] + n[0], p[1] + n[1], p[2] + n[2]);
+
+            // don't place blocks where the player is standing
+            if let Some(ref player) = player {
+                let (fx, fy, fz) = (x as f32, y as f32, z as f32);
+                let cell = physics::Aabb::new([fx, fy, fz], [fx + 1.0, fy + 1.0, fz + 1.0]);
+                if blocks.is_collidable(placed_block) && player.aabb().intersects(&cell) {
+                    continue;
+                }
+            }
+            chunks.set_block(x, y, z, placed_block);
         }
     }
 }
 
-mod vs {
-    #[derive(VulkanoShader)]
-    #[ty = "vertex"]
-    #[src = "
-#version 450
-layout(location = 0) in vec3 position;
-layout(location = 1) in vec3 normal;
-layout(location = 0) out vec3 v_normal;
-layout(set = 0, binding = 0) uniform Data {
-    mat4 world;
-    mat4 view;
-    mat4 proj;
-} uniforms;
-void main() {
-    mat4 worldview = uniforms.view * uniforms.world;
-    v_normal = transpose(inverse(mat3(worldview))) * normal;
-    gl_Position = uniforms.proj * worldview * vec4(position, 1.0);
+// replaces the meshes of chunks with freshly built ones, all uploaded in one
+// batch. layers without any visible faces don't keep a mesh around at all.
+fn upload_chunk_meshes(
+    renderer: &mut Renderer,
+    meshes: &mut ChunkMeshes,
+    meshed: Vec<(ChunkPos, ChunkMesh)>,
+    eye: [f32; 3],
+) -> Result<(), RenderError> {
+    let mut layers = Vec::new();
+    for (pos, mut mesh) in meshed {
+        meshes.remove(pos);
+        mesh.translucent.sort_back_to_front(chunk_local(pos, eye));
+        if !mesh.translucent.is_empty() {
+            meshes.translucent.insert(pos, mesh.translucent.clone());
+        }
+        for &layer in LAYERS.iter() {
+            let data = mem::replace(mesh.layer_mut(layer), MeshData::new());
+            if !data.is_empty() {
+                layers.push((pos, layer, data));
+            }
+        }
+    }
+    upload_layers(renderer, meshes, layers)
 }
-"]
-    struct Dummy;
+
+// sorts the translucent faces of the chunks around the camera for where it
+// is now. the vertices stay where they are, only the indices are replaced.
+fn resort_translucent(
+    renderer: &mut Renderer,
+    meshes: &mut ChunkMeshes,
+    camera_chunk: ChunkPos,
+    eye: [f32; 3],
+) -> Result<(), RenderError> {
+    let near = |pos: ChunkPos| {
+        (pos.x - camera_chunk.x).abs() <= RESORT_DISTANCE
+            && (pos.y - camera_chunk.y).abs() <= RESORT_DISTANCE
+            && (pos.z - camera_chunk.z).abs() <= RESORT_DISTANCE
+    };
+    for (&pos, data) in meshes.translucent.iter_mut() {
+        if near(pos) {
+            data.sort_back_to_front(chunk_local(pos, eye));
+        }
+    }
+
+    let mut updates = Vec::new();
+    for (&(pos, layer), &mut (ref mut mesh, _)) in meshes.uploaded.iter_mut() {
+        if layer != RenderLayer::Translucent || !near(pos) {
+            continue;
+        }
+        if let Some(data) = meshes.translucent.get(&pos) {
+            updates.push((mesh, &data.indices[..]));
+        }
+    }
+    renderer.update_indices(&mut updates)
+}
+
+fn upload_layers(
+    renderer: &mut Renderer,
+    meshes: &mut ChunkMeshes,
+    layers: Vec<(ChunkPos, RenderLayer, MeshData)>,
+) -> Result<(), RenderError> {
+    if layers.is_empty() {
+        return Ok(());
+    }
+
+    let uploaded = renderer.upload_meshes(
+        &layers
+            .iter()
+            .map(|&(_, _, ref data)| data)
+            .collect::<Vec<_>>(),
+    )?;
+    for ((pos, layer, _), mesh) in layers.into_iter().zip(uploaded) {
+        let origin = pos.origin();
+        let translation =
+            cgmath::Vector3::new(origin[0] as f32, origin[1] as f32, origin[2] as f32);
+        meshes.uploaded.insert((pos, layer), (mesh, translation));
+    }
+    Ok(())
 }
 
-mod fs {
-    #[derive(VulkanoShader)]
-    #[ty = "fragment"]
-    #[src = "
-#version 450
-layout(location = 0) in vec3 v_normal;
-layout(location = 0) out vec4 f_color;
-const vec3 LIGHT = vec3(0.0, 0.0, 1.0);
-void main() {
-    float brightness = dot(normalize(v_normal), normalize(LIGHT));
-    vec3 dark_color = vec3(0.6, 0.0, 0.0);
-    vec3 regular_color = vec3(1.0, 0.0, 0.0);
-    f_color = vec4(mix(dark_color, regular_color, brightness), 1.0);
+// chunk meshes are relative to the chunk origin
+fn chunk_local(pos: ChunkPos, p: [f32; 3]) -> [f32; 3] {
+    let origin = pos.origin();
+    [
+        p[0] - origin[0] as f32,
+        p[1] - origin[1] as f32,
+        p[2] - origin[2] as f32,
+    ]
 }
-"]
-    struct Dummy;
+
+fn grab_cursor(renderer: &Renderer) {
+    if let Some(window) = renderer.window() {
+        window
+            .set_cursor_state(winit::CursorState::Grab)
+            .unwrap_or_else(|err| warn!("couldn't grab the cursor: {}", err));
+    }
 }
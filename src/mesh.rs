@@ -0,0 +1,233 @@
+use atlas::TileRect;
+use std::cmp::Ordering;
+
+// `uv` is in texels of the tile, i.e. it goes past 1.0 on quads spanning
+// several blocks and the fragment shader wraps it into the `tile` rectangle of
+// the atlas
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Vertex {
+    pub position: (f32, f32, f32),
+    pub uv: (f32, f32),
+    pub tile: (f32, f32, f32, f32),
+}
+
+impl_vertex!(Vertex, position, uv, tile);
+
+// `light` is the (sky, block) light level of the vertex scaled to 0..1 and
+// `occlusion` is how much ambient light reaches it, 1.0 being unoccluded
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Normal {
+    pub normal: (f32, f32, f32),
+    pub light: (f32, f32),
+    pub occlusion: f32,
+}
+
+impl_vertex!(Normal, normal, light, occlusion);
+
+// cpu side geometry in the layout the pipeline's TwoBuffersDefinition expects
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MeshData {
+    pub vertices: Vec<Vertex>,
+    pub normals: Vec<Normal>,
+    pub indices: Vec<u32>,
+}
+
+impl MeshData {
+    pub fn new() -> MeshData {
+        MeshData::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.indices.is_empty()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.indices.len() / 3
+    }
+
+    // axis aligned bounds of all vertices as (min, max)
+    pub fn bounds(&self) -> ([f32; 3], [f32; 3]) {
+        let mut min = [::std::f32::MAX; 3];
+        let mut max = [::std::f32::MIN; 3];
+        for vertex in &self.vertices {
+            let p = [vertex.position.0, vertex.position.1, vertex.position.2];
+            for axis in 0..3 {
+                min[axis] = min[axis].min(p[axis]);
+                max[axis] = max[axis].max(p[axis]);
+            }
+        }
+        (min, max)
+    }
+
+    // reorders the triangles so that the ones farthest from `eye` come first,
+    // the order blended geometry has to be drawn in. `eye` is in the same
+    // space as the vertex positions.
+    pub fn sort_back_to_front(&mut self, eye: [f32; 3]) {
+        let mut triangles = Vec::with_capacity(self.indices.len() / 3);
+        for triangle in self.indices.chunks(3) {
+            let mut distance = 0.0;
+            for axis in 0..3 {
+                let mut center = 0.0;
+                for &i in triangle {
+                    let p = self.vertices[i as usize].position;
+                    center += [p.0, p.1, p.2][axis] / 3.0;
+                }
+                distance += (center - eye[axis]) * (center - eye[axis]);
+            }
+            triangles.push((distance, [triangle[0], triangle[1], triangle[2]]));
+        }
+        triangles.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+
+        self.indices.clear();
+        for &(_, triangle) in &triangles {
+            self.indices.extend_from_slice(&triangle);
+        }
+    }
+
+    // corners go counter-clockwise when looking at the front of the quad. the
+    // quad is split along the diagonal with more ambient light, otherwise a
+    // single dark corner bleeds across the whole quad and the shading
+    // depends on which way it happens to be split.
+    pub fn push_quad(
+        &mut self,
+        corners: [[f32; 3]; 4],
+        normal: [f32; 3],
+        uvs: [[f32; 2]; 4],
+        tile: TileRect,
+        light: [[f32; 2]; 4],
+        occlusion: [f32; 4],
+    ) {
+        let base = self.vertices.len() as u32;
+        for (i, (corner, uv)) in corners.iter().zip(uvs.iter()).enumerate() {
+            self.vertices.push(Vertex {
+                position: (corner[0], corner[1], corner[2]),
+                uv: (uv[0], uv[1]),
+                tile: (tile[0], tile[1], tile[2], tile[3]),
+            });
+            self.normals.push(Normal {
+                normal: (normal[0], normal[1], normal[2]),
+                light: (light[i][0], light[i][1]),
+                occlusion: occlusion[i],
+            });
+        }
+        if occlusion[0] + occlusion[2] >= occlusion[1] + occlusion[3] {
+            self.indices
+                .extend_from_slice(&[base, base + 1, base + 2, base, base + 2, base + 3]);
+        } else {
+            self.indices.extend_from_slice(&[
+                base + 1,
+                base + 2,
+                base + 3,
+                base + 1,
+                base + 3,
+                base,
+            ]);
+        }
+    }
+}
+
+// where one mesh ended up inside a MeshBatch, indices stay relative to the
+// mesh's first vertex so the vertex buffers are bound starting from there
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct BatchRange {
+    pub first_vertex: usize,
+    pub vertex_count: usize,
+    pub first_index: usize,
+    pub index_count: usize,
+}
+
+// several meshes packed back to back, so that uploading them takes one
+// staging buffer per attribute instead of three buffers per mesh
+#[derive(Default)]
+pub struct MeshBatch {
+    pub vertices: Vec<Vertex>,
+    pub normals: Vec<Normal>,
+    pub indices: Vec<u32>,
+    pub ranges: Vec<BatchRange>,
+}
+
+impl MeshBatch {
+    pub fn new() -> MeshBatch {
+        MeshBatch::default()
+    }
+
+    pub fn push(&mut self, data: &MeshData) -> BatchRange {
+        let range = BatchRange {
+            first_vertex: self.vertices.len(),
+            vertex_count: data.vertices.len(),
+            first_index: self.indices.len(),
+            index_count: data.indices.len(),
+        };
+        self.vertices.extend_from_slice(&data.vertices);
+        self.normals.extend_from_slice(&data.normals);
+        self.indices.extend_from_slice(&data.indices);
+        self.ranges.push(range);
+        range
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quad(z: f32) -> MeshData {
+        let mut data = MeshData::new();
+        data.push_quad(
+            [[0.0, 0.0, z], [1.0, 0.0, z], [1.0, 1.0, z], [0.0, 1.0, z]],
+            [0.0, 0.0, 1.0],
+            [[0.0; 2]; 4],
+            [0.0; 4],
+            [[1.0, 0.0]; 4],
+            [1.0; 4],
+        );
+        data
+    }
+
+    #[test]
+    fn batches_keep_every_mesh_intact() {
+        let meshes = vec![quad(0.0), MeshData::new(), quad(1.0), quad(2.0)];
+        let mut batch = MeshBatch::new();
+        for data in &meshes {
+            batch.push(data);
+        }
+
+        assert_eq!(batch.ranges.len(), meshes.len());
+        assert_eq!(batch.vertices.len(), 12);
+        assert_eq!(batch.normals.len(), 12);
+        assert_eq!(batch.indices.len(), 18);
+        for (data, range) in meshes.iter().zip(batch.ranges.iter()) {
+            let vertices = range.first_vertex..range.first_vertex + range.vertex_count;
+            let indices = range.first_index..range.first_index + range.index_count;
+            assert_eq!(&batch.vertices[vertices.clone()], &data.vertices[..]);
+            assert_eq!(&batch.normals[vertices], &data.normals[..]);
+            assert_eq!(&batch.indices[indices], &data.indices[..]);
+        }
+    }
+
+    #[test]
+    fn sorting_puts_far_triangles_first() {
+        let mut data = MeshData::new();
+        for &z in [1.0, 0.0, 2.0].iter() {
+            data.push_quad(
+                [[0.0, 0.0, z], [1.0, 0.0, z], [1.0, 1.0, z], [0.0, 1.0, z]],
+                [0.0, 0.0, 1.0],
+                [[0.0; 2]; 4],
+                [0.0; 4],
+                [[1.0, 0.0]; 4],
+                [1.0; 4],
+            );
+        }
+        let depths = |data: &MeshData| {
+            data.indices
+                .chunks(3)
+                .map(|triangle| data.vertices[triangle[0] as usize].position.2)
+                .collect::<Vec<_>>()
+        };
+
+        data.sort_back_to_front([0.5, 0.5, 10.0]);
+        assert_eq!(depths(&data), vec![0.0, 0.0, 1.0, 1.0, 2.0, 2.0]);
+        data.sort_back_to_front([0.5, 0.5, -10.0]);
+        assert_eq!(depths(&data), vec![2.0, 2.0, 1.0, 1.0, 0.0, 0.0]);
+        assert_eq!(data.triangle_count(), 6);
+    }
+}
@@ -0,0 +1,952 @@
+use atlas::Atlas;
+use block;
+use block::Block;
+use block::BlockRegistry;
+use block::RenderLayer;
+use chunk;
+use chunk::Chunk;
+use chunk::ChunkPos;
+use chunk::CHUNK_BITS;
+use chunk::CHUNK_SIZE;
+use light::Lighting;
+use light::MAX_LIGHT;
+use mesh::MeshData;
+use world::World;
+
+pub struct Face {
+    pub normal: [i32; 3],
+    // unit cube corners, counter-clockwise seen from outside
+    pub corners: [[f32; 3]; 4],
+}
+
+pub const FACES: [Face; 6] = [
+    Face {
+        normal: [1, 0, 0],
+        corners: [
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [1.0, 1.0, 1.0],
+            [1.0, 0.0, 1.0],
+        ],
+    },
+    Face {
+        normal: [-1, 0, 0],
+        corners: [
+            [0.0, 0.0, 1.0],
+            [0.0, 1.0, 1.0],
+            [0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0],
+        ],
+    },
+    Face {
+        normal: [0, 1, 0],
+        corners: [
+            [0.0, 1.0, 0.0],
+            [0.0, 1.0, 1.0],
+            [1.0, 1.0, 1.0],
+            [1.0, 1.0, 0.0],
+        ],
+    },
+    Face {
+        normal: [0, -1, 0],
+        corners: [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 0.0, 1.0],
+            [0.0, 0.0, 1.0],
+        ],
+    },
+    Face {
+        normal: [0, 0, 1],
+        corners: [
+            [1.0, 0.0, 1.0],
+            [1.0, 1.0, 1.0],
+            [0.0, 1.0, 1.0],
+            [0.0, 0.0, 1.0],
+        ],
+    },
+    Face {
+        normal: [0, 0, -1],
+        corners: [
+            [0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [1.0, 0.0, 0.0],
+        ],
+    },
+];
+
+// the faces of a chunk split up by the pipeline that draws them
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ChunkMesh {
+    pub opaque: MeshData,
+    pub cutout: MeshData,
+    pub translucent: MeshData,
+}
+
+impl ChunkMesh {
+    pub fn new() -> ChunkMesh {
+        ChunkMesh::default()
+    }
+
+    pub fn layer(&self, layer: RenderLayer) -> &MeshData {
+        match layer {
+            RenderLayer::Opaque => &self.opaque,
+            RenderLayer::Cutout => &self.cutout,
+            RenderLayer::Translucent => &self.translucent,
+        }
+    }
+
+    pub fn layer_mut(&mut self, layer: RenderLayer) -> &mut MeshData {
+        match layer {
+            RenderLayer::Opaque => &mut self.opaque,
+            RenderLayer::Cutout => &mut self.cutout,
+            RenderLayer::Translucent => &mut self.translucent,
+        }
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.opaque.triangle_count()
+            + self.cutout.triangle_count()
+            + self.translucent.triangle_count()
+    }
+}
+
+// a chunk together with the 26 chunks around it, so that lookups just past
+// the chunk border can see the neighbouring blocks. neighbours that aren't
+// loaded read as air, the faces facing them get culled once they're loaded
+// and the chunk is meshed again.
+pub struct Neighborhood<'a> {
+    chunks: [Option<&'a Chunk>; 27],
+    blocks: &'a BlockRegistry,
+}
+
+impl<'a> Neighborhood<'a> {
+    pub fn new(world: &'a World, blocks: &'a BlockRegistry, pos: ChunkPos) -> Neighborhood<'a> {
+        let mut chunks = [None; 27];
+        for dy in -1..2 {
+            for dz in -1..2 {
+                for dx in -1..2 {
+                    let i = ((dy + 1) * 9 + (dz + 1) * 3 + (dx + 1)) as usize;
+                    chunks[i] = world.chunk(pos.offset(dx, dy, dz));
+                }
+            }
+        }
+        Neighborhood {
+            chunks: chunks,
+            blocks: blocks,
+        }
+    }
+
+    pub fn center(&self) -> Option<&'a Chunk> {
+        self.chunks[13]
+    }
+
+    // coordinates are relative to the center chunk and may reach one chunk
+    // beyond it in every direction
+    pub fn get(&self, x: i32, y: i32, z: i32) -> Block {
+        let cx = ((x >> CHUNK_BITS) + 1) as usize;
+        let cy = ((y >> CHUNK_BITS) + 1) as usize;
+        let cz = ((z >> CHUNK_BITS) + 1) as usize;
+        match self.chunks[(cy * 3 + cz) * 3 + cx] {
+            Some(chunk) => chunk.get(chunk::local(x), chunk::local(y), chunk::local(z)),
+            None => block::AIR,
+        }
+    }
+
+    fn is_opaque(&self, p: [i32; 3]) -> bool {
+        self.blocks.is_opaque(self.get(p[0], p[1], p[2]))
+    }
+
+    fn hides_face(&self, p: [i32; 3], block: Block) -> bool {
+        self.blocks.hides_face(self.get(p[0], p[1], p[2]), block)
+    }
+}
+
+// one quad per block face that isn't hidden behind a full block, positions
+// are relative to the chunk origin
+pub fn mesh_chunk(
+    world: &World,
+    light: &Lighting,
+    pos: ChunkPos,
+    atlas: &Atlas,
+    blocks: &BlockRegistry,
+) -> ChunkMesh {
+    let mut mesh = ChunkMesh::new();
+
+    let neighborhood = Neighborhood::new(world, blocks, pos);
+    match neighborhood.center() {
+        Some(chunk) if !chunk.is_empty() => (),
+        _ => return mesh,
+    }
+
+    let origin = pos.origin();
+    let size = CHUNK_SIZE as i32;
+    for y in 0..size {
+        for z in 0..size {
+            for x in 0..size {
+                if neighborhood.get(x, y, z).is_present() {
+                    push_block(&mut mesh, &neighborhood, light, origin, atlas, [x, y, z]);
+                }
+            }
+        }
+    }
+
+    mesh
+}
+
+// merges coplanar faces of the same block and light into larger quads. every
+// face direction is swept one layer at a time, `d` is the axis along the
+// normal and `u`/`v` span the layer.
+pub fn mesh_chunk_greedy(
+    world: &World,
+    light: &Lighting,
+    pos: ChunkPos,
+    atlas: &Atlas,
+    blocks: &BlockRegistry,
+) -> ChunkMesh {
+    let mut mesh = ChunkMesh::new();
+
+    let neighborhood = Neighborhood::new(world, blocks, pos);
+    match neighborhood.center() {
+        Some(chunk) if !chunk.is_empty() => (),
+        _ => return mesh,
+    }
+
+    let origin = pos.origin();
+    let size = CHUNK_SIZE as i32;
+    let mut mask: Vec<Option<FaceCell>> = vec![None; CHUNK_SIZE * CHUNK_SIZE];
+
+    for (face_index, face) in FACES.iter().enumerate() {
+        let d = face.normal.iter().position(|&n| n != 0).unwrap();
+        let sign = face.normal[d];
+        let u = (d + 1) % 3;
+        let v = (d + 2) % 3;
+        let mut step_u = [0; 3];
+        step_u[u] = 1;
+        let mut step_v = [0; 3];
+        step_v[v] = 1;
+        let back_u = [-step_u[0], -step_u[1], -step_u[2]];
+        let back_v = [-step_v[0], -step_v[1], -step_v[2]];
+
+        for layer in 0..size {
+            // which cells of this layer have a visible face, and what it
+            // looks like
+            for j in 0..size {
+                for i in 0..size {
+                    let mut p = [0; 3];
+                    p[d] = layer;
+                    p[u] = i;
+                    p[v] = j;
+                    let block = neighborhood.get(p[0], p[1], p[2]);
+                    p[d] += sign;
+                    let visible = blocks.is_full_cube(block) && !neighborhood.hides_face(p, block);
+                    mask[(j * size + i) as usize] = if visible {
+                        let corner_light = |side_u, side_v| {
+                            vertex_light(&neighborhood, light, origin, p, side_u, side_v)
+                        };
+                        let corner_occlusion =
+                            |side_u, side_v| vertex_occlusion(&neighborhood, p, side_u, side_v);
+                        Some(FaceCell {
+                            block: block,
+                            light: [
+                                corner_light(back_u, back_v),
+                                corner_light(step_u, back_v),
+                                corner_light(step_u, step_v),
+                                corner_light(back_u, step_v),
+                            ],
+                            occlusion: [
+                                corner_occlusion(back_u, back_v),
+                                corner_occlusion(step_u, back_v),
+                                corner_occlusion(step_u, step_v),
+                                corner_occlusion(back_u, step_v),
+                            ],
+                        })
+                    } else {
+                        None
+                    };
+                }
+            }
+
+            for j in 0..size {
+                let mut i = 0;
+                while i < size {
+                    let cell = match mask[(j * size + i) as usize] {
+                        Some(cell) => cell,
+                        None => {
+                            i += 1;
+                            continue;
+                        }
+                    };
+
+                    // shading that changes across a face can't be stretched
+                    // over several of them
+                    let mut width = 1;
+                    while cell.flat_along_u()
+                        && i + width < size
+                        && mask[(j * size + i + width) as usize] == Some(cell)
+                    {
+                        width += 1;
+                    }
+
+                    let mut height = 1;
+                    'grow: while cell.flat_along_v() && j + height < size {
+                        for k in 0..width {
+                            if mask[((j + height) * size + i + k) as usize] != Some(cell) {
+                                break 'grow;
+                            }
+                        }
+                        height += 1;
+                    }
+
+                    for dj in 0..height {
+                        for di in 0..width {
+                            mask[((j + dj) * size + i + di) as usize] = None;
+                        }
+                    }
+
+                    let mut start = [0.0; 3];
+                    start[d] = (layer + if sign > 0 { 1 } else { 0 }) as f32;
+                    start[u] = i as f32;
+                    start[v] = j as f32;
+                    let mut du = [0.0; 3];
+                    du[u] = width as f32;
+                    let mut dv = [0.0; 3];
+                    dv[v] = height as f32;
+
+                    // u x v points along +d, so negative faces wind the other way
+                    let (l, o) = (cell.light, cell.occlusion);
+                    let (corners, lights, occlusion) = if sign > 0 {
+                        (
+                            [
+                                start,
+                                add(start, du),
+                                add(add(start, du), dv),
+                                add(start, dv),
+                            ],
+                            [l[0], l[1], l[2], l[3]],
+                            [o[0], o[1], o[2], o[3]],
+                        )
+                    } else {
+                        (
+                            [
+                                start,
+                                add(start, dv),
+                                add(add(start, du), dv),
+                                add(start, du),
+                            ],
+                            [l[0], l[3], l[2], l[1]],
+                            [o[0], o[3], o[2], o[1]],
+                        )
+                    };
+                    let n = face.normal;
+                    mesh.layer_mut(blocks.layer(cell.block)).push_quad(
+                        corners,
+                        [n[0] as f32, n[1] as f32, n[2] as f32],
+                        face_uvs(n, &corners),
+                        atlas.rect(blocks.texture(cell.block, face_index)),
+                        lights,
+                        occlusion,
+                    );
+
+                    i += width;
+                }
+            }
+        }
+    }
+
+    // blocks that don't fill their cell don't merge with anything
+    for y in 0..size {
+        for z in 0..size {
+            for x in 0..size {
+                let block = neighborhood.get(x, y, z);
+                if block.is_present() && !blocks.is_full_cube(block) {
+                    push_block(&mut mesh, &neighborhood, light, origin, atlas, [x, y, z]);
+                }
+            }
+        }
+    }
+
+    mesh
+}
+
+// the visible faces of a single block. faces next to a full cube are hidden,
+// except for the top of a block that doesn't reach the top of its cell and
+// faces that only see through blocks behind them.
+fn push_block(
+    mesh: &mut ChunkMesh,
+    neighborhood: &Neighborhood,
+    light: &Lighting,
+    origin: [i32; 3],
+    atlas: &Atlas,
+    cell: [i32; 3],
+) {
+    let blocks = neighborhood.blocks;
+    let block = neighborhood.get(cell[0], cell[1], cell[2]);
+    let height = blocks.height(block);
+
+    for (face_index, face) in FACES.iter().enumerate() {
+        let n = face.normal;
+        let front = add_i(cell, n);
+        let inside = n[1] == 1 && !blocks.is_full_cube(block);
+        if !inside && neighborhood.hides_face(front, block) {
+            continue;
+        }
+        // light comes from the block the face looks into, which for a face
+        // inside the cell is the cell itself
+        let sample = if inside { cell } else { front };
+
+        let mut lights = [[0.0; 2]; 4];
+        let mut occlusion = [0.0; 4];
+        for (i, corner) in face.corners.iter().enumerate() {
+            let mut sides = [[0; 3]; 2];
+            let tangents = (0..3).filter(|&axis| n[axis] == 0);
+            for (side, axis) in sides.iter_mut().zip(tangents) {
+                side[axis] = if corner[axis] > 0.5 { 1 } else { -1 };
+            }
+            lights[i] = vertex_light(neighborhood, light, origin, sample, sides[0], sides[1]);
+            occlusion[i] = vertex_occlusion(neighborhood, sample, sides[0], sides[1]);
+        }
+
+        let mut corners = face.corners;
+        for corner in corners.iter_mut() {
+            corner[0] += cell[0] as f32;
+            corner[1] = corner[1] * height + cell[1] as f32;
+            corner[2] += cell[2] as f32;
+        }
+        mesh.layer_mut(blocks.layer(block)).push_quad(
+            corners,
+            [n[0] as f32, n[1] as f32, n[2] as f32],
+            face_uvs(n, &corners),
+            atlas.rect(blocks.texture(block, face_index)),
+            lights,
+            occlusion,
+        );
+    }
+}
+
+// a visible face in a greedy meshing layer, faces only merge when they look
+// exactly the same. `light` and `occlusion` are per corner, starting at the
+// low u and low v corner and going round through high u.
+#[derive(Copy, Clone, PartialEq)]
+struct FaceCell {
+    block: Block,
+    light: [[f32; 2]; 4],
+    occlusion: [f32; 4],
+}
+
+impl FaceCell {
+    fn corners_equal(&self, a: usize, b: usize) -> bool {
+        self.light[a] == self.light[b] && self.occlusion[a] == self.occlusion[b]
+    }
+
+    fn flat_along_u(&self) -> bool {
+        self.corners_equal(0, 1) && self.corners_equal(3, 2)
+    }
+
+    fn flat_along_v(&self) -> bool {
+        self.corners_equal(0, 3) && self.corners_equal(1, 2)
+    }
+}
+
+// smooth light for the corner of a face, the average over the four blocks in
+// front of the face that share the corner. `front` is the block the face
+// looks into and `side_u`/`side_v` point from it towards the corner. blocks
+// that stop light are left out, and so is the diagonal one when both sides
+// are blocked as no light gets around that corner.
+fn vertex_light(
+    neighborhood: &Neighborhood,
+    light: &Lighting,
+    origin: [i32; 3],
+    front: [i32; 3],
+    side_u: [i32; 3],
+    side_v: [i32; 3],
+) -> [f32; 2] {
+    let a = add_i(front, side_u);
+    let b = add_i(front, side_v);
+    let c = add_i(a, side_v);
+    let opaque = |p: [i32; 3]| neighborhood.is_opaque(p);
+
+    let mut samples = vec![front];
+    if !opaque(a) {
+        samples.push(a);
+    }
+    if !opaque(b) {
+        samples.push(b);
+    }
+    if samples.len() > 1 && !opaque(c) {
+        samples.push(c);
+    }
+
+    let mut sum = [0.0; 2];
+    for p in &samples {
+        let p = add_i(origin, *p);
+        sum[0] += light.sky(p[0], p[1], p[2]) as f32;
+        sum[1] += light.block(p[0], p[1], p[2]) as f32;
+    }
+    let scale = samples.len() as f32 * MAX_LIGHT as f32;
+    [sum[0] / scale, sum[1] / scale]
+}
+
+// ambient occlusion for the corner of a face from the three blocks in front
+// of the face that touch the corner, the two along the edges and the one on
+// the diagonal. with both edges blocked the corner is fully occluded no
+// matter what the diagonal is.
+fn vertex_occlusion(
+    neighborhood: &Neighborhood,
+    front: [i32; 3],
+    side_u: [i32; 3],
+    side_v: [i32; 3],
+) -> f32 {
+    let a = add_i(front, side_u);
+    let b = add_i(front, side_v);
+    let c = add_i(a, side_v);
+    let opaque = |p: [i32; 3]| neighborhood.is_opaque(p) as u32;
+
+    let level = if opaque(a) + opaque(b) == 2 {
+        0
+    } else {
+        3 - opaque(a) - opaque(b) - opaque(c)
+    };
+    level as f32 / 3.0
+}
+
+// texture coordinates in blocks, oriented so that textures are upright and
+// not mirrored when looking at the face from the outside
+fn face_uvs(normal: [i32; 3], corners: &[[f32; 3]; 4]) -> [[f32; 2]; 4] {
+    let mut uvs = [[0.0; 2]; 4];
+    for (uv, p) in uvs.iter_mut().zip(corners.iter()) {
+        *uv = match normal {
+            [1, 0, 0] => [-p[2], -p[1]],
+            [-1, 0, 0] => [p[2], -p[1]],
+            [0, 0, 1] => [p[0], -p[1]],
+            [0, 0, -1] => [-p[0], -p[1]],
+            [0, 1, 0] => [p[0], p[2]],
+            _ => [p[0], -p[2]],
+        };
+    }
+    uvs
+}
+
+fn add(a: [f32; 3], b: [f32; 3]) -> [f32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn add_i(a: [i32; 3], b: [i32; 3]) -> [i32; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::path::Path;
+
+    // a bumpy heightmap of a few block types spanning two chunks, so that
+    // the border between them is exercised as well
+    fn terrain() -> World {
+        let mut world = World::new();
+        for x in 0..(CHUNK_SIZE as i32 * 2) {
+            for z in 0..CHUNK_SIZE as i32 {
+                let height = 8 + (x / 5 + z / 7) % 4;
+                for y in 0..height {
+                    let block = if y == height - 1 {
+                        block::GRASS
+                    } else if y > height - 4 {
+                        block::DIRT
+                    } else {
+                        block::STONE
+                    };
+                    world.set_block(x, y, z, block);
+                }
+            }
+        }
+        world
+    }
+
+    // splits every quad into unit squares keyed by their lower corner and
+    // normal, counting how often each one is covered
+    fn unit_faces(mesh: &MeshData) -> HashMap<([i32; 3], [i32; 3]), u32> {
+        let mut faces = HashMap::new();
+        for quad in 0..mesh.vertices.len() / 4 {
+            let n = mesh.normals[quad * 4].normal;
+            let normal = [n.0 as i32, n.1 as i32, n.2 as i32];
+
+            let mut min = [::std::i32::MAX; 3];
+            let mut max = [::std::i32::MIN; 3];
+            for vertex in &mesh.vertices[quad * 4..quad * 4 + 4] {
+                let p = vertex.position;
+                let p = [p.0 as i32, p.1 as i32, p.2 as i32];
+                for axis in 0..3 {
+                    min[axis] = min[axis].min(p[axis]);
+                    max[axis] = max[axis].max(p[axis]);
+                }
+            }
+            for axis in 0..3 {
+                if min[axis] == max[axis] {
+                    max[axis] += 1;
+                }
+            }
+
+            for x in min[0]..max[0] {
+                for y in min[1]..max[1] {
+                    for z in min[2]..max[2] {
+                        *faces.entry(([x, y, z], normal)).or_insert(0) += 1;
+                    }
+                }
+            }
+        }
+        faces
+    }
+
+    #[test]
+    fn greedy_covers_same_surface_as_naive() {
+        let blocks = block::test_registry();
+        let world = terrain();
+        for &pos in [ChunkPos::new(0, 0, 0), ChunkPos::new(1, 0, 0)].iter() {
+            let naive = unit_faces(
+                &mesh_chunk(&world, &Lighting::new(), pos, &Atlas::new(), &blocks).opaque,
+            );
+            let greedy = unit_faces(
+                &mesh_chunk_greedy(&world, &Lighting::new(), pos, &Atlas::new(), &blocks).opaque,
+            );
+            assert!(greedy.values().all(|&count| count == 1));
+            assert_eq!(naive, greedy);
+        }
+    }
+
+    #[test]
+    fn greedy_covers_same_surface_with_lighting() {
+        let blocks = block::test_registry();
+        let mut world = terrain();
+        world.set_block(20, 12, 20, block::TORCH);
+        let light = Lighting::compute(&world, &blocks);
+        let pos = ChunkPos::new(0, 0, 0);
+        let naive = unit_faces(&mesh_chunk(&world, &light, pos, &Atlas::new(), &blocks).opaque);
+        let greedy =
+            unit_faces(&mesh_chunk_greedy(&world, &light, pos, &Atlas::new(), &blocks).opaque);
+        assert!(greedy.values().all(|&count| count == 1));
+        assert_eq!(naive, greedy);
+    }
+
+    #[test]
+    fn faces_take_light_from_the_block_in_front() {
+        // torches let light past them, this needs a light that fills its cell
+        let blocks = BlockRegistry::parse(
+            &format!(
+                "{}\n[[block]]\nname = \"lamp\"\nid = 100\nlight = 14\ntextures = {{ all = \"stone\" }}",
+                include_str!("../assets/blocks.toml")
+            ),
+            Path::new(block::DEFAULT_PATH),
+        ).unwrap();
+        let lamp = blocks.by_name("lamp").unwrap();
+        let mut world = World::new();
+        world.insert_chunk(ChunkPos::new(0, 0, 0), Chunk::filled(block::STONE));
+        world.set_block(10, 10, 10, block::AIR);
+        world.set_block(10, 11, 10, lamp);
+        let light = Lighting::compute(&world, &blocks);
+        let mesh = mesh_chunk(
+            &world,
+            &light,
+            ChunkPos::new(0, 0, 0),
+            &Atlas::new(),
+            &blocks,
+        )
+        .opaque;
+
+        // the walls of the one block cave and the bottom of the lamp face into
+        // the lit air block, everything else at the chunk borders is dark
+        let inside = |p: (f32, f32, f32)| [p.0, p.1, p.2].iter().all(|&v| v >= 10.0 && v <= 11.0);
+        let mut cave_quads = 0;
+        for quad in 0..mesh.vertices.len() / 4 {
+            if (0..4).all(|k| inside(mesh.vertices[quad * 4 + k].position)) {
+                cave_quads += 1;
+                for k in 0..4 {
+                    let light = mesh.normals[quad * 4 + k].light;
+                    assert_eq!(light, (0.0, 13.0 / MAX_LIGHT as f32));
+                }
+            }
+        }
+        assert_eq!(cave_quads, 6);
+    }
+
+    #[test]
+    fn corners_next_to_blocks_are_occluded() {
+        let blocks = block::test_registry();
+        let mut world = World::new();
+        for x in 0..8 {
+            for z in 0..8 {
+                world.set_block(x, 0, z, block::STONE);
+            }
+        }
+        world.set_block(3, 1, 3, block::STONE);
+        let mesh = mesh_chunk(
+            &world,
+            &Lighting::new(),
+            ChunkPos::new(0, 0, 0),
+            &Atlas::new(),
+            &blocks,
+        )
+        .opaque;
+
+        // occlusion of the top face of the floor block at x, z for each of
+        // its corners, keyed by the corner position
+        let floor_top = |x: f32, z: f32| {
+            let mut corners = HashMap::new();
+            for quad in 0..mesh.vertices.len() / 4 {
+                let p = mesh.vertices[quad * 4].position;
+                let normal = mesh.normals[quad * 4].normal;
+                if normal != (0.0, 1.0, 0.0) || p.1 != 1.0 {
+                    continue;
+                }
+                let vertices = &mesh.vertices[quad * 4..quad * 4 + 4];
+                if vertices
+                    .iter()
+                    .all(|v| v.position.0 >= x && v.position.0 <= x + 1.0)
+                    && vertices
+                        .iter()
+                        .all(|v| v.position.2 >= z && v.position.2 <= z + 1.0)
+                {
+                    for k in 0..4 {
+                        let v = mesh.vertices[quad * 4 + k].position;
+                        let key = ((v.0 - x) as i32, (v.2 - z) as i32);
+                        corners.insert(key, mesh.normals[quad * 4 + k].occlusion);
+                    }
+                }
+            }
+            corners
+        };
+
+        // right next to the block along +x, the two corners touching it
+        let beside = floor_top(4.0, 3.0);
+        assert_eq!(beside[&(0, 0)], 2.0 / 3.0);
+        assert_eq!(beside[&(0, 1)], 2.0 / 3.0);
+        assert_eq!(beside[&(1, 0)], 1.0);
+        assert_eq!(beside[&(1, 1)], 1.0);
+
+        // diagonally across, only one corner touches the block
+        let diagonal = floor_top(4.0, 4.0);
+        assert_eq!(diagonal[&(0, 0)], 2.0 / 3.0);
+        assert_eq!(diagonal[&(1, 0)], 1.0);
+        assert_eq!(diagonal[&(0, 1)], 1.0);
+        assert_eq!(diagonal[&(1, 1)], 1.0);
+
+        // far away nothing is occluded
+        let open = floor_top(7.0, 7.0);
+        assert!(open.values().all(|&occlusion| occlusion == 1.0));
+    }
+
+    #[test]
+    fn inner_corners_are_fully_occluded() {
+        let blocks = block::test_registry();
+        let mut world = World::new();
+        world.set_block(0, 0, 0, block::STONE);
+        world.set_block(1, 0, 0, block::STONE);
+        world.set_block(0, 0, 1, block::STONE);
+        world.set_block(1, 1, 0, block::STONE);
+        world.set_block(0, 1, 1, block::STONE);
+        let mesh = mesh_chunk(
+            &world,
+            &Lighting::new(),
+            ChunkPos::new(0, 0, 0),
+            &Atlas::new(),
+            &blocks,
+        )
+        .opaque;
+
+        // the top of the corner block sits between two walls
+        let occlusion = (0..mesh.vertices.len())
+            .filter(|&i| {
+                mesh.normals[i].normal == (0.0, 1.0, 0.0)
+                    && mesh.vertices[i].position == (1.0, 1.0, 1.0)
+            })
+            .map(|i| mesh.normals[i].occlusion)
+            .collect::<Vec<_>>();
+        assert_eq!(occlusion, vec![0.0]);
+    }
+
+    #[test]
+    fn quads_split_along_the_lighter_diagonal() {
+        let mut mesh = MeshData::new();
+        let corners = [
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [1.0, 1.0, 0.0],
+            [0.0, 1.0, 0.0],
+        ];
+        let normal = [0.0, 0.0, 1.0];
+        let light = [[1.0, 0.0]; 4];
+
+        mesh.push_quad(corners, normal, [[0.0; 2]; 4], [0.0; 4], light, [1.0; 4]);
+        assert_eq!(&mesh.indices[0..6], &[0, 1, 2, 0, 2, 3]);
+
+        // a dark first corner, the split has to avoid it
+        mesh.push_quad(
+            corners,
+            normal,
+            [[0.0; 2]; 4],
+            [0.0; 4],
+            light,
+            [0.0, 1.0, 1.0, 1.0],
+        );
+        assert_eq!(&mesh.indices[6..12], &[5, 6, 7, 5, 7, 4]);
+
+        // a bright first corner keeps its diagonal
+        mesh.push_quad(
+            corners,
+            normal,
+            [[0.0; 2]; 4],
+            [0.0; 4],
+            light,
+            [1.0, 0.0, 0.0, 0.0],
+        );
+        assert_eq!(&mesh.indices[12..18], &[8, 9, 10, 8, 10, 11]);
+    }
+
+    #[test]
+    fn slabs_are_half_height_and_hide_nothing() {
+        let blocks = block::test_registry();
+        let mut world = World::new();
+        world.set_block(0, 0, 0, block::SLAB);
+        world.set_block(1, 0, 0, block::STONE);
+        let pos = ChunkPos::new(0, 0, 0);
+
+        for mesh in vec![
+            mesh_chunk(&world, &Lighting::new(), pos, &Atlas::new(), &blocks).opaque,
+            mesh_chunk_greedy(&world, &Lighting::new(), pos, &Atlas::new(), &blocks).opaque,
+        ] {
+            // the slab loses the face against the stone, the stone keeps all
+            // of its own
+            assert_eq!(mesh.triangle_count(), 2 * (5 + 6));
+            let (min, max) = mesh.bounds();
+            assert_eq!(min, [0.0, 0.0, 0.0]);
+            assert_eq!(max, [2.0, 1.0, 1.0]);
+            let slab_top = mesh.vertices.iter().filter(|v| v.position.1 == 0.5).count();
+            // the whole top face and the upper edge of three sides
+            assert_eq!(slab_top, 4 + 3 * 2);
+        }
+    }
+
+    #[test]
+    fn see_through_blocks_get_their_own_layers() {
+        let blocks = block::test_registry();
+        let glass = blocks.by_name("glass").unwrap();
+        let leaves = blocks.by_name("leaves").unwrap();
+        let mut world = World::new();
+        world.set_block(0, 0, 0, block::STONE);
+        world.set_block(1, 0, 0, block::WATER);
+        world.set_block(2, 0, 0, block::WATER);
+        world.set_block(3, 0, 0, glass);
+        world.set_block(0, 1, 0, leaves);
+        world.set_block(1, 1, 0, leaves);
+        let pos = ChunkPos::new(0, 0, 0);
+
+        for mesh in vec![
+            mesh_chunk(&world, &Lighting::new(), pos, &Atlas::new(), &blocks),
+            mesh_chunk_greedy(&world, &Lighting::new(), pos, &Atlas::new(), &blocks),
+        ] {
+            // the stone shows through the water and the leaves on top of it,
+            // but water and leaves hide faces between blocks of their own
+            assert_eq!(
+                unit_faces(&mesh.opaque).len(),
+                6,
+                "stone lost a face behind see through blocks"
+            );
+            let leaf_faces = unit_faces(&mesh.cutout);
+            assert_eq!(leaf_faces.len(), 9);
+            assert!(!leaf_faces.contains_key(&([1, 1, 0], [1, 0, 0])));
+            // water against water or stone is hidden, water against glass
+            // isn't
+            let water_and_glass = unit_faces(&mesh.translucent);
+            assert_eq!(water_and_glass.len(), 4 + 5 + 6);
+            assert!(!water_and_glass.contains_key(&([2, 0, 0], [-1, 0, 0])));
+            assert!(water_and_glass.contains_key(&([3, 0, 0], [1, 0, 0])));
+            assert!(water_and_glass.contains_key(&([3, 0, 0], [-1, 0, 0])));
+        }
+    }
+
+    #[test]
+    fn greedy_reduces_triangles_on_terrain() {
+        let blocks = block::test_registry();
+        let world = terrain();
+        let pos = ChunkPos::new(0, 0, 0);
+        let naive =
+            mesh_chunk(&world, &Lighting::new(), pos, &Atlas::new(), &blocks).triangle_count();
+        let greedy = mesh_chunk_greedy(&world, &Lighting::new(), pos, &Atlas::new(), &blocks)
+            .triangle_count();
+        // ambient occlusion keeps the faces along every step of the terrain
+        // from merging with the rest of the plateau
+        assert!(greedy * 7 <= naive, "naive {} greedy {}", naive, greedy);
+    }
+
+    #[test]
+    fn greedy_flat_slab_is_six_quads() {
+        let blocks = block::test_registry();
+        let mut world = World::new();
+        for x in 0..CHUNK_SIZE as i32 {
+            for z in 0..CHUNK_SIZE as i32 {
+                world.set_block(x, 0, z, block::STONE);
+            }
+        }
+        let pos = ChunkPos::new(0, 0, 0);
+        assert_eq!(
+            mesh_chunk(&world, &Lighting::new(), pos, &Atlas::new(), &blocks).triangle_count(),
+            4352
+        );
+        assert_eq!(
+            mesh_chunk_greedy(&world, &Lighting::new(), pos, &Atlas::new(), &blocks)
+                .triangle_count(),
+            12
+        );
+    }
+
+    #[test]
+    fn greedy_keeps_block_types_apart() {
+        let blocks = block::test_registry();
+        let mut world = World::new();
+        world.set_block(0, 0, 0, block::STONE);
+        world.set_block(1, 0, 0, block::STONE);
+        let pos = ChunkPos::new(0, 0, 0);
+        assert_eq!(
+            mesh_chunk_greedy(&world, &Lighting::new(), pos, &Atlas::new(), &blocks)
+                .triangle_count(),
+            12
+        );
+
+        world.set_block(1, 0, 0, block::DIRT);
+        assert_eq!(
+            mesh_chunk_greedy(&world, &Lighting::new(), pos, &Atlas::new(), &blocks)
+                .triangle_count(),
+            20
+        );
+    }
+
+    #[test]
+    fn greedy_quads_face_their_normal() {
+        let blocks = block::test_registry();
+        let mesh = mesh_chunk_greedy(
+            &terrain(),
+            &Lighting::new(),
+            ChunkPos::new(0, 0, 0),
+            &Atlas::new(),
+            &blocks,
+        )
+        .opaque;
+        for quad in 0..mesh.vertices.len() / 4 {
+            let p = |k: usize| {
+                let p = mesh.vertices[quad * 4 + k].position;
+                [p.0, p.1, p.2]
+            };
+            let (a, b, c) = (p(0), p(1), p(2));
+            let e1 = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+            let e2 = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+            let cross = [
+                e1[1] * e2[2] - e1[2] * e2[1],
+                e1[2] * e2[0] - e1[0] * e2[2],
+                e1[0] * e2[1] - e1[1] * e2[0],
+            ];
+            let n = mesh.normals[quad * 4].normal;
+            assert!(cross[0] * n.0 + cross[1] * n.1 + cross[2] * n.2 > 0.0);
+        }
+    }
+}
@@ -0,0 +1,95 @@
+// splitmix64, used to derive everything random from a single seed
+pub fn mix(seed: u64) -> u64 {
+    let mut z = seed.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// classic improved perlin noise with the permutation table shuffled by seed
+pub struct Perlin {
+    perm: [u8; 512],
+}
+
+impl Perlin {
+    pub fn new(seed: u64) -> Perlin {
+        let mut p = [0u8; 256];
+        for (i, v) in p.iter_mut().enumerate() {
+            *v = i as u8;
+        }
+
+        let mut state = seed;
+        for i in (1..256).rev() {
+            state = mix(state);
+            let j = (state % (i as u64 + 1)) as usize;
+            p.swap(i, j);
+        }
+
+        let mut perm = [0u8; 512];
+        for i in 0..512 {
+            perm[i] = p[i & 255];
+        }
+        Perlin { perm: perm }
+    }
+
+    // roughly in [-1, 1]
+    pub fn noise2(&self, x: f64, y: f64) -> f64 {
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let xf = x - x0;
+        let yf = y - y0;
+        let xi = (x0 as i64 & 255) as usize;
+        let yi = (y0 as i64 & 255) as usize;
+
+        let perm = &self.perm;
+        let aa = perm[perm[xi] as usize + yi];
+        let ab = perm[perm[xi] as usize + yi + 1];
+        let ba = perm[perm[xi + 1] as usize + yi];
+        let bb = perm[perm[xi + 1] as usize + yi + 1];
+
+        let u = fade(xf);
+        let v = fade(yf);
+        lerp(
+            v,
+            lerp(u, grad(aa, xf, yf), grad(ba, xf - 1.0, yf)),
+            lerp(u, grad(ab, xf, yf - 1.0), grad(bb, xf - 1.0, yf - 1.0)),
+        )
+    }
+
+    // fractal brownian motion, octaves are summed with halving amplitude and
+    // the result is normalized back into roughly [-1, 1]
+    pub fn fbm2(&self, x: f64, y: f64, octaves: u32) -> f64 {
+        let mut total = 0.0;
+        let mut amplitude = 1.0;
+        let mut frequency = 1.0;
+        let mut max = 0.0;
+        for _ in 0..octaves {
+            total += self.noise2(x * frequency, y * frequency) * amplitude;
+            max += amplitude;
+            amplitude *= 0.5;
+            frequency *= 2.0;
+        }
+        total / max
+    }
+}
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+fn grad(hash: u8, x: f64, y: f64) -> f64 {
+    match hash & 7 {
+        0 => x + y,
+        1 => -x + y,
+        2 => x - y,
+        3 => -x - y,
+        4 => x,
+        5 => -x,
+        6 => y,
+        _ => -y,
+    }
+}
@@ -0,0 +1,298 @@
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::path::Path;
+
+use mesh::MeshData;
+use mesh::Normal;
+use mesh::Vertex;
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(io::Error),
+    Parse { line: usize, message: String },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ObjError::Io(ref err) => write!(f, "failed to read obj file: {}", err),
+            ObjError::Parse { line, ref message } => write!(f, "line {}: {}", line, message),
+        }
+    }
+}
+
+impl error::Error for ObjError {
+    fn description(&self) -> &str {
+        match *self {
+            ObjError::Io(ref err) => err.description(),
+            ObjError::Parse { ref message, .. } => message,
+        }
+    }
+}
+
+impl From<io::Error> for ObjError {
+    fn from(err: io::Error) -> ObjError {
+        ObjError::Io(err)
+    }
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<MeshData, ObjError> {
+    let file = File::open(path)?;
+    parse(BufReader::new(file))
+}
+
+// a face corner as (position index, normal index), both zero based
+type Corner = (usize, Option<usize>);
+
+// supports the subset of the format that matters for meshes: `v`, `vn` and
+// `f` with any of the `v`, `v/vt`, `v//vn` and `v/vt/vn` corner forms,
+// including negative (relative) indices. polygons are triangulated as fans
+// and smooth normals are generated for corners that don't have one.
+pub fn parse<R: BufRead>(reader: R) -> Result<MeshData, ObjError> {
+    let mut positions: Vec<[f32; 3]> = Vec::new();
+    let mut normals: Vec<[f32; 3]> = Vec::new();
+    let mut triangles: Vec<[Corner; 3]> = Vec::new();
+
+    for (number, line) in reader.lines().enumerate() {
+        let line = line?;
+        let number = number + 1;
+        let mut parts = line.split_whitespace();
+
+        match parts.next() {
+            Some("v") => positions.push(parse_vec3(&mut parts, number)?),
+            Some("vn") => normals.push(parse_vec3(&mut parts, number)?),
+            Some("f") => {
+                let corners = parts
+                    .map(|part| parse_corner(part, positions.len(), normals.len(), number))
+                    .collect::<Result<Vec<_>, _>>()?;
+                if corners.len() < 3 {
+                    return Err(parse_error(number, "face with fewer than 3 vertices"));
+                }
+                for i in 1..corners.len() - 1 {
+                    triangles.push([corners[0], corners[i], corners[i + 1]]);
+                }
+            }
+            // texture coordinates, groups, materials, smoothing groups and
+            // comments don't affect the geometry
+            _ => (),
+        }
+    }
+
+    let generated = generate_normals(&positions, &triangles);
+
+    // every distinct position/normal pair becomes one vertex
+    let mut mesh = MeshData::new();
+    let mut vertex_ids: HashMap<Corner, u32> = HashMap::new();
+    for triangle in &triangles {
+        for &corner in triangle.iter() {
+            let id = *vertex_ids.entry(corner).or_insert_with(|| {
+                let (position, normal) = corner;
+                let p = positions[position];
+                let n = match normal {
+                    Some(normal) => normals[normal],
+                    None => generated[position],
+                };
+                // an empty tile rectangle samples the white tile of the atlas
+                mesh.vertices.push(Vertex {
+                    position: (p[0], p[1], p[2]),
+                    uv: (0.0, 0.0),
+                    tile: (0.0, 0.0, 0.0, 0.0),
+                });
+                // models aren't part of the world, they get full daylight
+                mesh.normals.push(Normal {
+                    normal: (n[0], n[1], n[2]),
+                    light: (1.0, 0.0),
+                    occlusion: 1.0,
+                });
+                mesh.vertices.len() as u32 - 1
+            });
+            mesh.indices.push(id);
+        }
+    }
+
+    Ok(mesh)
+}
+
+fn parse_error(line: usize, message: &str) -> ObjError {
+    ObjError::Parse {
+        line: line,
+        message: message.to_string(),
+    }
+}
+
+fn parse_vec3<'a, I>(parts: &mut I, line: usize) -> Result<[f32; 3], ObjError>
+where
+    I: Iterator<Item = &'a str>,
+{
+    let mut v = [0.0; 3];
+    for component in v.iter_mut() {
+        *component = parts
+            .next()
+            .ok_or_else(|| parse_error(line, "expected 3 components"))?
+            .parse()
+            .map_err(|_| parse_error(line, "invalid number"))?;
+    }
+    Ok(v)
+}
+
+// obj indices start at 1, negative ones count back from the last element
+fn resolve_index(index: &str, len: usize, line: usize) -> Result<usize, ObjError> {
+    let index: i64 = index
+        .parse()
+        .map_err(|_| parse_error(line, "invalid index"))?;
+    let resolved = if index < 0 {
+        len as i64 + index
+    } else {
+        index - 1
+    };
+    if resolved < 0 || resolved >= len as i64 {
+        return Err(parse_error(line, "index out of range"));
+    }
+    Ok(resolved as usize)
+}
+
+fn parse_corner(
+    part: &str,
+    position_count: usize,
+    normal_count: usize,
+    line: usize,
+) -> Result<Corner, ObjError> {
+    let mut indices = part.split('/');
+    let position = resolve_index(indices.next().unwrap(), position_count, line)?;
+    let normal = match indices.nth(1) {
+        Some(normal) if !normal.is_empty() => Some(resolve_index(normal, normal_count, line)?),
+        _ => None,
+    };
+    Ok((position, normal))
+}
+
+// area weighted average of the normals of all faces using each position
+fn generate_normals(positions: &[[f32; 3]], triangles: &[[Corner; 3]]) -> Vec<[f32; 3]> {
+    let mut normals = vec![[0.0f32; 3]; positions.len()];
+    for triangle in triangles {
+        let a = positions[triangle[0].0];
+        let b = positions[triangle[1].0];
+        let c = positions[triangle[2].0];
+        let e1 = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+        let e2 = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+        let n = [
+            e1[1] * e2[2] - e1[2] * e2[1],
+            e1[2] * e2[0] - e1[0] * e2[2],
+            e1[0] * e2[1] - e1[1] * e2[0],
+        ];
+        for corner in triangle.iter() {
+            let normal = &mut normals[corner.0];
+            normal[0] += n[0];
+            normal[1] += n[1];
+            normal[2] += n[2];
+        }
+    }
+
+    for normal in normals.iter_mut() {
+        let length = (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+        if length > 0.0 {
+            normal[0] /= length;
+            normal[1] /= length;
+            normal[2] /= length;
+        }
+    }
+    normals
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_str(text: &str) -> Result<MeshData, ObjError> {
+        parse(text.as_bytes())
+    }
+
+    fn error_line(text: &str) -> usize {
+        match parse_str(text) {
+            Err(ObjError::Parse { line, .. }) => line,
+            Err(err) => panic!("expected a parse error, got {}", err),
+            Ok(_) => panic!("expected a parse error"),
+        }
+    }
+
+    const SQUARE: &'static str = "v 0 0 0\nv 1 0 0\nv 1 1 0\nv 0 1 0\n";
+
+    #[test]
+    fn polygons_are_fans() {
+        let quad = parse_str(&format!("{}f 1 2 3 4\n", SQUARE)).unwrap();
+        assert_eq!(quad.vertices.len(), 4);
+        assert_eq!(quad.indices, vec![0, 1, 2, 0, 2, 3]);
+
+        let pentagon = parse_str(&format!("{}v 0.5 1.5 0\nf 1 2 3 5 4\n", SQUARE)).unwrap();
+        assert_eq!(pentagon.triangle_count(), 3);
+        assert_eq!(pentagon.indices, vec![0, 1, 2, 0, 2, 3, 0, 3, 4]);
+    }
+
+    #[test]
+    fn every_corner_form_is_understood() {
+        let header = format!("{}vt 0 0\nvt 1 0\nvt 1 1\nvn 0 0 -1\n", SQUARE);
+        // the given normal where there is one, otherwise the generated one
+        // which points the other way
+        let given = (0.0, 0.0, -1.0);
+        let generated = (0.0, 0.0, 1.0);
+        let faces = vec![
+            ("f 1 2 3", generated),
+            ("f 1/1 2/2 3/3", generated),
+            ("f 1//1 2//1 3//1", given),
+            ("f 1/1/1 2/2/1 3/3/1", given),
+        ];
+        for (face, normal) in faces {
+            let mesh = parse_str(&format!("{}{}\n", header, face)).unwrap();
+            assert_eq!(mesh.vertices.len(), 3, "{}", face);
+            assert_eq!(mesh.vertices[1].position, (1.0, 0.0, 0.0), "{}", face);
+            assert_eq!(mesh.normals[0].normal, normal, "{}", face);
+        }
+    }
+
+    #[test]
+    fn negative_indices_count_back() {
+        let absolute = parse_str(&format!("{}f 2 3 4\n", SQUARE)).unwrap();
+        let relative = parse_str(&format!("{}f -3 -2 -1\n", SQUARE)).unwrap();
+        assert_eq!(relative.vertices, absolute.vertices);
+        assert_eq!(relative.indices, absolute.indices);
+    }
+
+    #[test]
+    fn generated_normals_are_area_weighted() {
+        // a large triangle facing +z and a small one facing +y share the
+        // first vertex
+        let mesh = parse_str(
+            "v 0 0 0\nv 4 0 0\nv 0 4 0\nv 0 0 1\nv 1 0 0\n\
+             f 1 2 3\nf 1 4 5\n",
+        ).unwrap();
+        let normal_at = |p: (f32, f32, f32)| {
+            let i = mesh.vertices.iter().position(|v| v.position == p).unwrap();
+            mesh.normals[i].normal
+        };
+
+        let shared = normal_at((0.0, 0.0, 0.0));
+        assert!(shared.1 > 0.0 && shared.1 < 0.1, "{:?}", shared);
+        assert!(shared.2 > 0.99, "{:?}", shared);
+        let length = shared.0 * shared.0 + shared.1 * shared.1 + shared.2 * shared.2;
+        assert!((length - 1.0).abs() < 1e-5);
+        assert_eq!(normal_at((4.0, 0.0, 0.0)), (0.0, 0.0, 1.0));
+        assert_eq!(normal_at((0.0, 0.0, 1.0)), (0.0, 1.0, 0.0));
+    }
+
+    #[test]
+    fn bad_input_is_an_error() {
+        assert_eq!(error_line(&format!("{}f 1 2 9\n", SQUARE)), 5);
+        assert_eq!(error_line(&format!("{}f 0 1 2\n", SQUARE)), 5);
+        assert_eq!(error_line(&format!("{}f -5 1 2\n", SQUARE)), 5);
+        assert_eq!(error_line(&format!("{}f 1//2 2//2 3//2\n", SQUARE)), 5);
+        assert_eq!(error_line(&format!("{}f 1 2\n", SQUARE)), 5);
+        assert_eq!(error_line(&format!("{}f 1 two 3\n", SQUARE)), 5);
+        assert_eq!(error_line("v 1 0 0\nv 1 x 3\n"), 2);
+        assert_eq!(error_line("vn 1 0\n"), 1);
+    }
+}
@@ -0,0 +1,499 @@
+use block::BlockRegistry;
+use world::World;
+
+// the simulation always advances in steps of this many seconds, whatever the
+// frame rate, so the same input gives the same result everywhere
+pub const TIMESTEP: f32 = 1.0 / 60.0;
+// more steps than this in one frame and the simulation falls behind instead
+// of trying to catch up forever
+const MAX_STEPS: u32 = 10;
+
+// blocks per second (squared)
+pub const GRAVITY: f32 = 28.0;
+pub const TERMINAL_VELOCITY: f32 = 60.0;
+pub const JUMP_SPEED: f32 = 9.0;
+pub const WALK_SPEED: f32 = 4.3;
+// ledges up to this high are walked onto without jumping
+pub const STEP_HEIGHT: f32 = 0.5;
+
+pub const PLAYER_WIDTH: f32 = 0.6;
+pub const PLAYER_HEIGHT: f32 = 1.8;
+pub const EYE_HEIGHT: f32 = 1.62;
+
+// boxes closer than this count as touching, it keeps rounding errors from
+// letting a box sink into the one it's resting on
+const EPSILON: f32 = 1e-4;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Aabb {
+    pub min: [f32; 3],
+    pub max: [f32; 3],
+}
+
+impl Aabb {
+    pub fn new(min: [f32; 3], max: [f32; 3]) -> Aabb {
+        Aabb { min: min, max: max }
+    }
+
+    pub fn translate(&self, offset: [f32; 3]) -> Aabb {
+        let mut moved = *self;
+        for axis in 0..3 {
+            moved.min[axis] += offset[axis];
+            moved.max[axis] += offset[axis];
+        }
+        moved
+    }
+
+    pub fn intersects(&self, other: &Aabb) -> bool {
+        (0..3).all(|axis| self.min[axis] < other.max[axis] && self.max[axis] > other.min[axis])
+    }
+
+    // the box grown to cover everything it passes through on the way to
+    // `offset`
+    pub fn sweep(&self, offset: [f32; 3]) -> Aabb {
+        let mut swept = *self;
+        for axis in 0..3 {
+            if offset[axis] < 0.0 {
+                swept.min[axis] += offset[axis];
+            } else {
+                swept.max[axis] += offset[axis];
+            }
+        }
+        swept
+    }
+
+    // how far the box can move along `axis`, up to `distance`, before it runs
+    // into `other`. boxes that don't overlap on the other two axes never
+    // collide.
+    fn clip(&self, other: &Aabb, axis: usize, distance: f32) -> f32 {
+        for a in 0..3 {
+            if a != axis
+                && (self.max[a] <= other.min[a] + EPSILON || self.min[a] >= other.max[a] - EPSILON)
+            {
+                return distance;
+            }
+        }
+        if distance > 0.0 && self.max[axis] <= other.min[axis] + EPSILON {
+            distance.min(other.min[axis] - self.max[axis])
+        } else if distance < 0.0 && self.min[axis] >= other.max[axis] - EPSILON {
+            distance.max(other.max[axis] - self.min[axis])
+        } else {
+            distance
+        }
+    }
+}
+
+// boxes of every block that can be collided with inside `region`
+fn block_boxes(world: &World, blocks: &BlockRegistry, region: &Aabb) -> Vec<Aabb> {
+    let mut boxes = Vec::new();
+    let min = [
+        region.min[0].floor() as i32,
+        // slabs and full blocks never reach above their cell, but the cell
+        // below could hold something taller in the future
+        region.min[1].floor() as i32 - 1,
+        region.min[2].floor() as i32,
+    ];
+    let max = [
+        region.max[0].ceil() as i32,
+        region.max[1].ceil() as i32,
+        region.max[2].ceil() as i32,
+    ];
+    for y in min[1]..max[1] {
+        for z in min[2]..max[2] {
+            for x in min[0]..max[0] {
+                let block = world.get_block(x, y, z);
+                if blocks.is_collidable(block) {
+                    let (x, y, z) = (x as f32, y as f32, z as f32);
+                    let height = blocks.height(block);
+                    boxes.push(Aabb::new([x, y, z], [x + 1.0, y + height, z + 1.0]));
+                }
+            }
+        }
+    }
+    boxes
+}
+
+// moves the box by up to `offset`, stopping at the first solid block on each
+// axis. the axes are resolved one after another, vertical first, so that
+// sliding along walls and floors works. returns the distance actually moved.
+pub fn move_box(world: &World, blocks: &BlockRegistry, aabb: &Aabb, offset: [f32; 3]) -> [f32; 3] {
+    let boxes = block_boxes(world, blocks, &aabb.sweep(offset));
+    let mut moving = *aabb;
+    let mut moved = [0.0; 3];
+    for &axis in [1, 0, 2].iter() {
+        let mut distance = offset[axis];
+        for other in &boxes {
+            distance = moving.clip(other, axis, distance);
+        }
+        let mut step = [0.0; 3];
+        step[axis] = distance;
+        moving = moving.translate(step);
+        moved[axis] = distance;
+    }
+    moved
+}
+
+// what the player wants to do during a step. `direction` is the horizontal
+// walking direction as (x, z), at most unit length.
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct PlayerInput {
+    pub direction: [f32; 2],
+    pub jump: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Player {
+    // center of the bottom of the bounding box
+    pub position: [f32; 3],
+    pub velocity: [f32; 3],
+    pub on_ground: bool,
+    // position before the latest step, for interpolating between steps
+    pub previous_position: [f32; 3],
+}
+
+impl Player {
+    pub fn new(position: [f32; 3]) -> Player {
+        Player {
+            position: position,
+            velocity: [0.0; 3],
+            on_ground: false,
+            previous_position: position,
+        }
+    }
+
+    pub fn aabb(&self) -> Aabb {
+        let p = self.position;
+        let half = PLAYER_WIDTH / 2.0;
+        Aabb::new(
+            [p[0] - half, p[1], p[2] - half],
+            [p[0] + half, p[1] + PLAYER_HEIGHT, p[2] + half],
+        )
+    }
+
+    // eye position `alpha` of the way from the previous step to the latest
+    pub fn eye(&self, alpha: f32) -> [f32; 3] {
+        let mut eye = [0.0; 3];
+        for axis in 0..3 {
+            let from = self.previous_position[axis];
+            eye[axis] = from + (self.position[axis] - from) * alpha;
+        }
+        eye[1] += EYE_HEIGHT;
+        eye
+    }
+
+    // advances the simulation by one TIMESTEP
+    pub fn step(&mut self, world: &World, blocks: &BlockRegistry, input: &PlayerInput) {
+        self.previous_position = self.position;
+        let dt = TIMESTEP;
+
+        self.velocity[0] = input.direction[0] * WALK_SPEED;
+        self.velocity[2] = input.direction[1] * WALK_SPEED;
+        if input.jump && self.on_ground {
+            self.velocity[1] = JUMP_SPEED;
+        }
+        self.velocity[1] = (self.velocity[1] - GRAVITY * dt).max(-TERMINAL_VELOCITY);
+
+        let offset = [
+            self.velocity[0] * dt,
+            self.velocity[1] * dt,
+            self.velocity[2] * dt,
+        ];
+        let aabb = self.aabb();
+        let mut moved = move_box(world, blocks, &aabb, offset);
+
+        // blocked sideways while standing, see if it's a ledge low enough to
+        // step onto: lift the box, move it sideways and put it back down
+        let blocked = moved[0] != offset[0] || moved[2] != offset[2];
+        if blocked && self.on_ground {
+            let up = move_box(world, blocks, &aabb, [0.0, STEP_HEIGHT, 0.0]);
+            let lifted = aabb.translate(up);
+            let side = move_box(world, blocks, &lifted, [offset[0], 0.0, offset[2]]);
+            let shifted = lifted.translate(side);
+            let down = move_box(world, blocks, &shifted, [0.0, -up[1], 0.0]);
+
+            let horizontal = |m: [f32; 3]| m[0] * m[0] + m[2] * m[2];
+            if horizontal(side) > horizontal(moved) {
+                moved = [side[0], up[1] + down[1], side[2]];
+            }
+        }
+
+        for axis in 0..3 {
+            self.position[axis] += moved[axis];
+        }
+
+        let blocked_vertically = moved[1] != offset[1];
+        self.on_ground = blocked_vertically && offset[1] < 0.0;
+        if blocked_vertically {
+            self.velocity[1] = 0.0;
+        }
+    }
+}
+
+// turns variable frame times into a whole number of fixed steps, carrying
+// the remainder over to the next frame
+pub struct FixedTimestep {
+    accumulator: f32,
+}
+
+impl FixedTimestep {
+    pub fn new() -> FixedTimestep {
+        FixedTimestep { accumulator: 0.0 }
+    }
+
+    // how many steps to run for a frame that took `dt` seconds
+    pub fn advance(&mut self, dt: f32) -> u32 {
+        self.accumulator += dt;
+        let mut steps = 0;
+        while self.accumulator >= TIMESTEP {
+            self.accumulator -= TIMESTEP;
+            steps += 1;
+        }
+        if steps > MAX_STEPS {
+            steps = MAX_STEPS;
+            self.accumulator = 0.0;
+        }
+        steps
+    }
+
+    // how far the current time is between the last step and the next, for
+    // interpolating what gets drawn
+    pub fn alpha(&self) -> f32 {
+        self.accumulator / TIMESTEP
+    }
+}
+
+impl Default for FixedTimestep {
+    fn default() -> FixedTimestep {
+        FixedTimestep::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use block;
+
+    // a stone floor with its top at y = 1
+    fn floor() -> World {
+        let mut world = World::new();
+        for x in -8..8 {
+            for z in -8..8 {
+                world.set_block(x, 0, z, block::STONE);
+            }
+        }
+        world
+    }
+
+    fn run(player: &mut Player, world: &World, input: PlayerInput, steps: u32) {
+        let blocks = block::test_registry();
+        for _ in 0..steps {
+            player.step(world, &blocks, &input);
+        }
+    }
+
+    fn approx(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn falls_and_lands_on_the_floor() {
+        let world = floor();
+        let mut player = Player::new([0.5, 10.0, 0.5]);
+        run(&mut player, &world, PlayerInput::default(), 120);
+
+        assert!(approx(player.position[1], 1.0), "{:?}", player.position);
+        assert!(player.on_ground);
+        assert_eq!(player.velocity[1], 0.0);
+    }
+
+    #[test]
+    fn does_not_tunnel_through_thin_floors() {
+        let world = floor();
+        let mut player = Player::new([0.5, 200.0, 0.5]);
+        run(&mut player, &world, PlayerInput::default(), 600);
+        assert!(approx(player.position[1], 1.0), "{:?}", player.position);
+    }
+
+    #[test]
+    fn jumps_a_little_over_one_block() {
+        let blocks = block::test_registry();
+        let world = floor();
+        let mut player = Player::new([0.5, 1.0, 0.5]);
+        run(&mut player, &world, PlayerInput::default(), 2);
+        assert!(player.on_ground);
+
+        let jump = PlayerInput {
+            direction: [0.0, 0.0],
+            jump: true,
+        };
+        player.step(&world, &blocks, &jump);
+        assert!(!player.on_ground);
+
+        let mut highest = player.position[1];
+        for _ in 0..120 {
+            player.step(&world, &blocks, &PlayerInput::default());
+            highest = highest.max(player.position[1]);
+        }
+        assert!(highest > 2.2 && highest < 2.6, "peaked at {}", highest);
+        assert!(player.on_ground);
+        assert!(approx(player.position[1], 1.0));
+    }
+
+    #[test]
+    fn walls_stop_the_player() {
+        let mut world = floor();
+        for y in 1..4 {
+            for z in -8..8 {
+                world.set_block(3, y, z, block::STONE);
+            }
+        }
+        let mut player = Player::new([0.5, 1.0, 0.5]);
+        let walk = PlayerInput {
+            direction: [1.0, 0.0],
+            jump: false,
+        };
+        run(&mut player, &world, walk, 120);
+
+        assert!(approx(player.position[0], 3.0 - PLAYER_WIDTH / 2.0));
+        assert!(approx(player.position[1], 1.0));
+    }
+
+    #[test]
+    fn slides_along_walls() {
+        let mut world = floor();
+        for y in 1..4 {
+            for z in -8..8 {
+                world.set_block(3, y, z, block::STONE);
+            }
+        }
+        let mut player = Player::new([0.5, 1.0, 0.5]);
+        let diagonal = PlayerInput {
+            direction: [0.6, 0.8],
+            jump: false,
+        };
+        run(&mut player, &world, diagonal, 60);
+
+        // blocked on x, still moving along z at the full z speed
+        assert!(approx(player.position[0], 3.0 - PLAYER_WIDTH / 2.0));
+        assert!(approx(player.position[2], 0.5 + 0.8 * WALK_SPEED));
+    }
+
+    #[test]
+    fn steps_up_onto_slabs() {
+        let mut world = floor();
+        for z in -8..8 {
+            world.set_block(2, 1, z, block::SLAB);
+            world.set_block(3, 1, z, block::SLAB);
+        }
+        let mut player = Player::new([0.5, 1.0, 0.5]);
+        let walk = PlayerInput {
+            direction: [1.0, 0.0],
+            jump: false,
+        };
+        run(&mut player, &world, PlayerInput::default(), 2);
+        run(&mut player, &world, walk, 30);
+
+        assert!(player.position[0] > 2.5, "{:?}", player.position);
+        assert!(approx(player.position[1], 1.5), "{:?}", player.position);
+        assert!(player.on_ground);
+    }
+
+    #[test]
+    fn full_blocks_need_a_jump() {
+        let mut world = floor();
+        for z in -8..8 {
+            world.set_block(2, 1, z, block::STONE);
+        }
+        let walk = PlayerInput {
+            direction: [1.0, 0.0],
+            jump: false,
+        };
+        let mut player = Player::new([0.5, 1.0, 0.5]);
+        run(&mut player, &world, walk, 60);
+        assert!(approx(player.position[0], 2.0 - PLAYER_WIDTH / 2.0));
+        assert!(approx(player.position[1], 1.0));
+
+        let jump = PlayerInput {
+            direction: [1.0, 0.0],
+            jump: true,
+        };
+        run(&mut player, &world, jump, 60);
+        assert!(player.position[0] > 2.0);
+        assert!(player.position[1] >= 2.0);
+    }
+
+    #[test]
+    fn ceilings_stop_jumps() {
+        let blocks = block::test_registry();
+        let mut world = floor();
+        world.set_block(0, 3, 0, block::STONE);
+        let mut player = Player::new([0.5, 1.0, 0.5]);
+        run(&mut player, &world, PlayerInput::default(), 2);
+
+        let jump = PlayerInput {
+            direction: [0.0, 0.0],
+            jump: true,
+        };
+        player.step(&world, &blocks, &jump);
+        let mut highest = player.position[1];
+        for _ in 0..60 {
+            player.step(&world, &blocks, &PlayerInput::default());
+            highest = highest.max(player.position[1]);
+        }
+        // the head is at most at the ceiling, which is at y = 3
+        assert!(
+            highest + PLAYER_HEIGHT <= 3.0 + 1e-3,
+            "peaked at {}",
+            highest
+        );
+        assert!(player.on_ground);
+    }
+
+    #[test]
+    fn water_does_not_collide() {
+        let mut world = floor();
+        world.set_block(0, 0, 0, block::WATER);
+        world.set_block(0, -1, 0, block::STONE);
+        let mut player = Player::new([0.5, 3.0, 0.5]);
+        run(&mut player, &world, PlayerInput::default(), 120);
+        // fits the 0.6 wide box through the one block gap
+        assert!(approx(player.position[1], 0.0), "{:?}", player.position);
+    }
+
+    #[test]
+    fn simulation_is_deterministic() {
+        let blocks = block::test_registry();
+        let mut world = floor();
+        world.set_block(2, 1, 0, block::SLAB);
+        world.set_block(-2, 1, 1, block::STONE);
+
+        let simulate = || {
+            let mut player = Player::new([0.5, 4.0, 0.5]);
+            for i in 0..600 {
+                let angle = i as f32 * 0.05;
+                let input = PlayerInput {
+                    direction: [angle.cos(), angle.sin()],
+                    jump: i % 45 == 0,
+                };
+                player.step(&world, &blocks, &input);
+            }
+            player
+        };
+        assert_eq!(simulate(), simulate());
+    }
+
+    #[test]
+    fn fixed_timestep_carries_the_remainder() {
+        let mut clock = FixedTimestep::new();
+        assert_eq!(clock.advance(TIMESTEP * 0.5), 0);
+        assert!(approx(clock.alpha(), 0.5));
+        assert_eq!(clock.advance(TIMESTEP * 0.75), 1);
+        assert!(approx(clock.alpha(), 0.25));
+        assert_eq!(clock.advance(TIMESTEP * 3.0), 3);
+
+        // a long stall doesn't queue up seconds worth of steps
+        assert_eq!(clock.advance(5.0), MAX_STEPS);
+        assert_eq!(clock.advance(0.0), 0);
+    }
+}
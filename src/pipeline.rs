@@ -0,0 +1,206 @@
+use vulkano::descriptor::PipelineLayoutAbstract;
+use vulkano::device::Device;
+use vulkano::format::Format;
+use vulkano::framebuffer::RenderPassAbstract;
+use vulkano::framebuffer::Subpass;
+use vulkano::pipeline::depth_stencil::DepthStencil;
+use vulkano::pipeline::vertex::TwoBuffersDefinition;
+use vulkano::pipeline::GraphicsPipeline;
+
+use std::sync::Arc;
+
+use block::RenderLayer;
+use debug;
+use mesh::Normal;
+use mesh::Vertex;
+use renderer::RenderError;
+
+pub const DEPTH_FORMAT: Format = Format::D16Unorm;
+
+pub type Pipeline = GraphicsPipeline<
+    TwoBuffersDefinition<Vertex, Normal>,
+    Box<PipelineLayoutAbstract + Send + Sync>,
+    Arc<RenderPassAbstract + Send + Sync>,
+>;
+
+// one pipeline for every render layer
+pub struct Pipelines {
+    pub opaque: Arc<Pipeline>,
+    pub cutout: Arc<Pipeline>,
+    pub translucent: Arc<Pipeline>,
+}
+
+impl Pipelines {
+    pub fn new(
+        device: Arc<Device>,
+        render_pass: Arc<RenderPassAbstract + Send + Sync>,
+    ) -> Result<Pipelines, RenderError> {
+        let pipeline = |layer: RenderLayer, name: &str| -> Result<Arc<Pipeline>, RenderError> {
+            let pipeline = graphics_pipeline(device.clone(), render_pass.clone(), layer)?;
+            debug::name(&*pipeline, name);
+            Ok(pipeline)
+        };
+        Ok(Pipelines {
+            opaque: pipeline(RenderLayer::Opaque, "opaque pipeline")?,
+            cutout: pipeline(RenderLayer::Cutout, "cutout pipeline")?,
+            translucent: pipeline(RenderLayer::Translucent, "translucent pipeline")?,
+        })
+    }
+
+    pub fn get(&self, layer: RenderLayer) -> &Arc<Pipeline> {
+        match layer {
+            RenderLayer::Opaque => &self.opaque,
+            RenderLayer::Cutout => &self.cutout,
+            RenderLayer::Translucent => &self.translucent,
+        }
+    }
+}
+
+// shared by the windowed and the headless paths, the only difference between
+// the two is the format of the color attachment. the opaque and cutout layers
+// are drawn in the first subpass, the translucent one is blended on top of
+// them in the second.
+pub fn render_pass(
+    device: Arc<Device>,
+    color_format: Format,
+) -> Result<Arc<RenderPassAbstract + Send + Sync>, RenderError> {
+    let render_pass = ordered_passes_renderpass!(device,
+        attachments: {
+            color: {
+                load: Clear,
+                store: Store,
+                format: color_format,
+                samples: 1,
+            },
+            depth: {
+                load: Clear,
+                store: DontCare,
+                format: DEPTH_FORMAT,
+                samples: 1,
+            }
+        },
+        passes: [
+            {
+                color: [color],
+                depth_stencil: {depth},
+                input: []
+            },
+            {
+                color: [color],
+                depth_stencil: {depth},
+                input: []
+            }
+        ]
+    )?;
+    Ok(Arc::new(render_pass))
+}
+
+pub fn graphics_pipeline(
+    device: Arc<Device>,
+    render_pass: Arc<RenderPassAbstract + Send + Sync>,
+    layer: RenderLayer,
+) -> Result<Arc<Pipeline>, RenderError> {
+    let vs = vs::Shader::load(device.clone())?;
+    let fs = fs::Shader::load(device.clone())?;
+
+    // cutout textures are either see through or not at all, their see
+    // through texels are dropped instead of blended
+    let constants = fs::SpecializationConstants {
+        alpha_cutoff: if layer == RenderLayer::Cutout {
+            0.5
+        } else {
+            0.0
+        },
+    };
+    let builder = GraphicsPipeline::start()
+        .vertex_input(TwoBuffersDefinition::new())
+        .vertex_shader(vs.main_entry_point(), ())
+        .triangle_list()
+        .viewports_dynamic_scissors_irrelevant(1)
+        .fragment_shader(fs.main_entry_point(), constants);
+
+    let pipeline = match layer {
+        RenderLayer::Opaque | RenderLayer::Cutout => builder
+            .depth_stencil_simple_depth()
+            .render_pass(Subpass::from(render_pass, 0).unwrap())
+            .build(device)?,
+        // translucent faces still hide behind opaque ones, but they don't
+        // write depth so that the translucent faces behind them show through
+        RenderLayer::Translucent => builder
+            .blend_alpha_blending()
+            .depth_stencil(DepthStencil {
+                depth_write: false,
+                ..DepthStencil::simple_depth_test()
+            })
+            .render_pass(Subpass::from(render_pass, 1).unwrap())
+            .build(device)?,
+    };
+    Ok(Arc::new(pipeline))
+}
+
+pub mod vs {
+    #[derive(VulkanoShader)]
+    #[ty = "vertex"]
+    #[src = "
+#version 450
+layout(location = 0) in vec3 position;
+layout(location = 1) in vec2 uv;
+layout(location = 2) in vec4 tile;
+layout(location = 3) in vec3 normal;
+layout(location = 4) in vec2 light;
+layout(location = 5) in float occlusion;
+layout(location = 0) out vec3 v_normal;
+layout(location = 1) out vec2 v_uv;
+layout(location = 2) out vec4 v_tile;
+layout(location = 3) out vec2 v_light;
+layout(location = 4) out float v_occlusion;
+layout(set = 0, binding = 0) uniform Data {
+    mat4 world;
+    mat4 view;
+    mat4 proj;
+} uniforms;
+void main() {
+    mat4 worldview = uniforms.view * uniforms.world;
+    v_normal = transpose(inverse(mat3(worldview))) * normal;
+    v_uv = uv;
+    v_tile = tile;
+    v_light = light;
+    v_occlusion = occlusion;
+    gl_Position = uniforms.proj * worldview * vec4(position, 1.0);
+}
+"]
+    struct Dummy;
+}
+
+pub mod fs {
+    #[derive(VulkanoShader)]
+    #[ty = "fragment"]
+    #[src = "
+#version 450
+layout(location = 0) in vec3 v_normal;
+layout(location = 1) in vec2 v_uv;
+layout(location = 2) in vec4 v_tile;
+layout(location = 3) in vec2 v_light;
+layout(location = 4) in float v_occlusion;
+layout(location = 0) out vec4 f_color;
+layout(set = 0, binding = 1) uniform sampler2D atlas;
+layout(constant_id = 0) const float alpha_cutoff = 0.0;
+const vec3 LIGHT = vec3(0.0, 0.0, 1.0);
+void main() {
+    // wrap into the tile, the gradients come from the unwrapped coordinates
+    // so the mip level doesn't jump at the block edges
+    vec2 atlas_uv = v_tile.xy + fract(v_uv) * v_tile.zw;
+    vec4 color = textureGrad(atlas, atlas_uv, dFdx(v_uv) * v_tile.zw, dFdy(v_uv) * v_tile.zw);
+    if (color.a < alpha_cutoff) {
+        discard;
+    }
+    float brightness = dot(normalize(v_normal), normalize(LIGHT));
+    // v_light is (sky, block), every level below full is 20% darker
+    float level = max(v_light.x, v_light.y);
+    float light = max(pow(0.8, (1.0 - level) * 15.0), 0.05);
+    float ambient = mix(0.4, 1.0, v_occlusion);
+    f_color = vec4(color.rgb * light * ambient * mix(0.6, 1.0, brightness), color.a);
+}
+"]
+    struct Dummy;
+}
@@ -0,0 +1,210 @@
+use block::Block;
+use block::BlockRegistry;
+use world::World;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct RayHit {
+    pub position: [i32; 3],
+    pub block: Block,
+    // points out of the face the ray entered through, all zero when the ray
+    // starts inside the block
+    pub normal: [i32; 3],
+    pub distance: f32,
+}
+
+// walks the ray one block at a time (Amanatides & Woo) and returns the first
+// block within `max_distance` that can be targeted, liquids are looked
+// through. `direction` doesn't need to be
+// normalized, the distance is in blocks either way.
+pub fn raycast(
+    world: &World,
+    blocks: &BlockRegistry,
+    origin: [f32; 3],
+    direction: [f32; 3],
+    max_distance: f32,
+) -> Option<RayHit> {
+    let length =
+        (direction[0] * direction[0] + direction[1] * direction[1] + direction[2] * direction[2])
+            .sqrt();
+    if length == 0.0 {
+        return None;
+    }
+
+    let mut cell = [0; 3];
+    let mut step = [0; 3];
+    // distance along the ray to the next block boundary on each axis, and
+    // between two boundaries
+    let mut t_max = [::std::f32::INFINITY; 3];
+    let mut t_delta = [::std::f32::INFINITY; 3];
+    for axis in 0..3 {
+        let d = direction[axis] / length;
+        cell[axis] = origin[axis].floor() as i32;
+        if d > 0.0 {
+            step[axis] = 1;
+            t_max[axis] = (cell[axis] as f32 + 1.0 - origin[axis]) / d;
+            t_delta[axis] = 1.0 / d;
+        } else if d < 0.0 {
+            step[axis] = -1;
+            t_max[axis] = (origin[axis] - cell[axis] as f32) / -d;
+            t_delta[axis] = 1.0 / -d;
+        }
+    }
+
+    let block = world.get_block(cell[0], cell[1], cell[2]);
+    if blocks.is_targetable(block) {
+        return Some(RayHit {
+            position: cell,
+            block: block,
+            normal: [0; 3],
+            distance: 0.0,
+        });
+    }
+
+    loop {
+        let mut axis = 0;
+        for a in 1..3 {
+            if t_max[a] < t_max[axis] {
+                axis = a;
+            }
+        }
+        let distance = t_max[axis];
+        if distance > max_distance {
+            return None;
+        }
+
+        cell[axis] += step[axis];
+        t_max[axis] += t_delta[axis];
+
+        let block = world.get_block(cell[0], cell[1], cell[2]);
+        if blocks.is_targetable(block) {
+            let mut normal = [0; 3];
+            normal[axis] = -step[axis];
+            return Some(RayHit {
+                position: cell,
+                block: block,
+                normal: normal,
+                distance: distance,
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use block;
+
+    fn approx(a: f32, b: f32) -> bool {
+        (a - b).abs() < 1e-4
+    }
+
+    #[test]
+    fn hits_block_straight_ahead() {
+        let blocks = block::test_registry();
+        let mut world = World::new();
+        world.set_block(5, 0, 0, block::STONE);
+
+        let hit = raycast(&world, &blocks, [0.5, 0.5, 0.5], [1.0, 0.0, 0.0], 10.0).unwrap();
+        assert_eq!(hit.position, [5, 0, 0]);
+        assert_eq!(hit.block, block::STONE);
+        assert_eq!(hit.normal, [-1, 0, 0]);
+        assert!(approx(hit.distance, 4.5));
+    }
+
+    #[test]
+    fn reports_the_face_entered() {
+        let blocks = block::test_registry();
+        let mut world = World::new();
+        world.set_block(0, 0, 0, block::DIRT);
+
+        let from_above = raycast(&world, &blocks, [0.5, 3.0, 0.5], [0.0, -1.0, 0.0], 10.0).unwrap();
+        assert_eq!(from_above.normal, [0, 1, 0]);
+        assert!(approx(from_above.distance, 2.0));
+
+        let from_behind =
+            raycast(&world, &blocks, [0.5, 0.5, -2.0], [0.0, 0.0, 1.0], 10.0).unwrap();
+        assert_eq!(from_behind.normal, [0, 0, -1]);
+        assert!(approx(from_behind.distance, 2.0));
+
+        // unnormalized directions measure the same distance
+        let scaled = raycast(&world, &blocks, [3.5, 0.5, 0.5], [-10.0, 0.0, 0.0], 10.0).unwrap();
+        assert_eq!(scaled.normal, [1, 0, 0]);
+        assert!(approx(scaled.distance, 2.5));
+    }
+
+    #[test]
+    fn works_across_negative_coordinates() {
+        let blocks = block::test_registry();
+        let mut world = World::new();
+        world.set_block(-40, -3, -7, block::SAND);
+
+        let origin = [-35.5, -2.5, -6.5];
+        let direction = [-4.5, -0.5, -0.5];
+        let hit = raycast(&world, &blocks, origin, direction, 20.0).unwrap();
+        assert_eq!(hit.position, [-40, -3, -7]);
+        assert_eq!(hit.normal, [1, 0, 0]);
+    }
+
+    #[test]
+    fn diagonal_rays_visit_every_block_on_the_way() {
+        // the ray crosses x = 1 before y = 1, so it goes through (1, 0, 0)
+        // on its way to (1, 1, 0)
+        let blocks = block::test_registry();
+        let mut world = World::new();
+        world.set_block(1, 0, 0, block::STONE);
+        let hit = raycast(&world, &blocks, [0.5, 0.2, 0.5], [1.0, 1.0, 0.0], 10.0).unwrap();
+        assert_eq!(hit.position, [1, 0, 0]);
+        assert_eq!(hit.normal, [-1, 0, 0]);
+        assert!(approx(hit.distance, 0.5 * 2.0f32.sqrt()));
+
+        let mut world = World::new();
+        world.set_block(1, 1, 0, block::STONE);
+        let hit = raycast(&world, &blocks, [0.5, 0.2, 0.5], [1.0, 1.0, 0.0], 10.0).unwrap();
+        assert_eq!(hit.position, [1, 1, 0]);
+        assert_eq!(hit.normal, [0, -1, 0]);
+    }
+
+    #[test]
+    fn misses_beyond_max_distance() {
+        let blocks = block::test_registry();
+        let mut world = World::new();
+        world.set_block(0, 0, 10, block::STONE);
+
+        assert!(raycast(&world, &blocks, [0.5, 0.5, 0.5], [0.0, 0.0, 1.0], 9.0).is_none());
+        assert!(raycast(&world, &blocks, [0.5, 0.5, 0.5], [0.0, 0.0, 1.0], 9.5).is_some());
+        assert!(raycast(&world, &blocks, [0.5, 0.5, 0.5], [0.0, 0.0, -1.0], 100.0).is_none());
+        assert!(raycast(&world, &blocks, [0.5, 0.5, 0.5], [0.0, 0.0, 0.0], 100.0).is_none());
+    }
+
+    #[test]
+    fn starting_inside_a_block() {
+        let blocks = block::test_registry();
+        let mut world = World::new();
+        world.set_block(2, 2, 2, block::STONE);
+
+        let hit = raycast(&world, &blocks, [2.5, 2.5, 2.5], [0.0, 1.0, 0.0], 10.0).unwrap();
+        assert_eq!(hit.position, [2, 2, 2]);
+        assert_eq!(hit.normal, [0, 0, 0]);
+        assert_eq!(hit.distance, 0.0);
+    }
+
+    #[test]
+    fn looks_through_liquids() {
+        let blocks = block::test_registry();
+        let mut world = World::new();
+        for x in 0..4 {
+            world.set_block(x, 0, 0, block::WATER);
+        }
+        world.set_block(4, 0, 0, block::SAND);
+
+        let hit = raycast(&world, &blocks, [0.5, 0.5, 0.5], [1.0, 0.0, 0.0], 10.0).unwrap();
+        assert_eq!(hit.position, [4, 0, 0]);
+        assert_eq!(hit.normal, [-1, 0, 0]);
+
+        // torches can be walked through but still be picked
+        world.set_block(2, 0, 0, block::TORCH);
+        let hit = raycast(&world, &blocks, [0.5, 0.5, 0.5], [1.0, 0.0, 0.0], 10.0).unwrap();
+        assert_eq!(hit.block, block::TORCH);
+    }
+}
@@ -0,0 +1,1810 @@
+use cgmath;
+use cgmath::Matrix4;
+use cgmath::SquareMatrix;
+use image::ImageBuffer;
+use image::Rgba;
+use vulkano::buffer::BufferAccess;
+use vulkano::buffer::BufferInner;
+use vulkano::buffer::BufferSlice;
+use vulkano::buffer::BufferUsage;
+use vulkano::buffer::CpuAccessibleBuffer;
+use vulkano::buffer::CpuBufferPool;
+use vulkano::buffer::DeviceLocalBuffer;
+use vulkano::buffer::TypedBufferAccess;
+use vulkano::command_buffer::AutoCommandBuffer;
+use vulkano::command_buffer::AutoCommandBufferBuilder;
+use vulkano::command_buffer::AutoCommandBufferBuilderContextError;
+use vulkano::command_buffer::BeginRenderPassError;
+use vulkano::command_buffer::BuildError;
+use vulkano::command_buffer::CommandBuffer;
+use vulkano::command_buffer::CommandBufferExecError;
+use vulkano::command_buffer::CopyBufferError;
+use vulkano::command_buffer::CopyBufferImageError;
+use vulkano::command_buffer::DrawIndexedError;
+use vulkano::command_buffer::DynamicState;
+use vulkano::descriptor::descriptor_set::PersistentDescriptorSet;
+use vulkano::descriptor::descriptor_set::PersistentDescriptorSetBuildError;
+use vulkano::descriptor::descriptor_set::PersistentDescriptorSetError;
+use vulkano::device::Device;
+use vulkano::device::DeviceCreationError;
+use vulkano::device::DeviceExtensions;
+use vulkano::device::DeviceOwned;
+use vulkano::device::Queue;
+use vulkano::format::Format;
+use vulkano::framebuffer::Framebuffer;
+use vulkano::framebuffer::FramebufferAbstract;
+use vulkano::framebuffer::FramebufferCreationError;
+use vulkano::framebuffer::RenderPassAbstract;
+use vulkano::framebuffer::RenderPassCreationError;
+use vulkano::image::attachment::AttachmentImage;
+use vulkano::image::Dimensions;
+use vulkano::image::ImageAccess;
+use vulkano::image::ImageCreationError;
+use vulkano::image::ImageLayout;
+use vulkano::image::ImageUsage;
+use vulkano::image::ImmutableImage;
+use vulkano::image::SwapchainImage;
+use vulkano::instance::debug::DebugCallback;
+use vulkano::instance::Features;
+use vulkano::instance::Instance;
+use vulkano::instance::InstanceCreationError;
+use vulkano::instance::InstanceExtensions;
+use vulkano::instance::PhysicalDevice;
+use vulkano::instance::PhysicalDeviceType;
+use vulkano::instance::QueueFamily;
+use vulkano::memory::DeviceMemoryAllocError;
+use vulkano::pipeline::viewport::Viewport;
+use vulkano::pipeline::GraphicsPipelineCreationError;
+use vulkano::sampler::Filter;
+use vulkano::sampler::MipmapMode;
+use vulkano::sampler::Sampler;
+use vulkano::sampler::SamplerAddressMode;
+use vulkano::sampler::SamplerCreationError;
+use vulkano::swapchain;
+use vulkano::swapchain::AcquireError;
+use vulkano::swapchain::Capabilities;
+use vulkano::swapchain::CapabilitiesError;
+use vulkano::swapchain::PresentMode;
+use vulkano::swapchain::SupportedPresentModes;
+use vulkano::swapchain::Surface;
+use vulkano::swapchain::SurfaceTransform;
+use vulkano::swapchain::Swapchain;
+use vulkano::swapchain::SwapchainAcquireFuture;
+use vulkano::swapchain::SwapchainCreationError;
+use vulkano::sync::now;
+use vulkano::sync::AccessError;
+use vulkano::sync::FlushError;
+use vulkano::sync::GpuFuture;
+use vulkano::OomError;
+use vulkano_win;
+use vulkano_win::VkSurfaceBuild;
+use winit;
+
+use std::cmp;
+use std::cmp::Ordering;
+use std::error;
+use std::fmt;
+use std::io;
+use std::mem;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::Mutex;
+
+use arena::RangeAllocator;
+use atlas::AtlasImage;
+use block::RenderLayer;
+use debug;
+use frustum;
+use frustum::Frustum;
+use gpu;
+use gpu::Candidate;
+use gpu::DeviceKind;
+use mesh::MeshBatch;
+use mesh::MeshData;
+use mesh::Normal;
+use mesh::Vertex;
+use pipeline;
+use pipeline::vs;
+use pipeline::Pipelines;
+use settings::Settings;
+use settings::Vsync;
+
+// ranges of the renderer's arenas, they're handed back once the mesh and
+// every frame drawing it are gone. empty meshes don't have any buffers and
+// aren't drawn.
+pub struct Mesh {
+    buffers: Option<MeshBuffers>,
+    // model space bounds as (min, max), for culling
+    bounds: ([f32; 3], [f32; 3]),
+}
+
+struct MeshBuffers {
+    vertices: Arc<ArenaSlice<Vertex>>,
+    normals: Arc<ArenaSlice<Normal>>,
+    indices: Arc<ArenaSlice<u32>>,
+}
+
+// vertices in each buffer of the vertex arenas, meshes that don't fit get a
+// buffer of their own size
+const ARENA_VERTICES: usize = 1 << 18;
+// two triangles for every four vertices
+const ARENA_INDICES: usize = ARENA_VERTICES / 4 * 6;
+
+// device local buffers that meshes get ranges of. buffers are only added
+// when none of them has a free range that's big enough and are kept until
+// the renderer is dropped.
+struct Arena<T> {
+    // what the buffers are called in validation messages
+    name: &'static str,
+    buffers: Vec<(Arc<DeviceLocalBuffer<[T]>>, Arc<Mutex<RangeAllocator>>)>,
+    // size of a new buffer
+    len: usize,
+    usage: BufferUsage,
+}
+
+// one range of an arena's buffer. command buffers hold on to the ranges they
+// use, so a range only goes back to the arena once it's dropped and the last
+// frame that drew from it has finished. vulkano locks the whole buffer when
+// it's used directly, so the range has a lock of its own instead, letting
+// uploads into free ranges go ahead while frames draw from the others.
+struct ArenaSlice<T> {
+    buffer: Arc<DeviceLocalBuffer<[T]>>,
+    range: Range<usize>,
+    ranges: Arc<Mutex<RangeAllocator>>,
+    gpu_lock: Mutex<GpuAccess>,
+}
+
+// the same bookkeeping vulkano's own buffers do
+#[derive(Debug, Copy, Clone)]
+enum GpuAccess {
+    None,
+    NonExclusive { num: u32 },
+    Exclusive { num: u32 },
+}
+
+impl<T> Arena<T>
+where
+    T: Send + Sync + 'static,
+{
+    fn new(name: &'static str, len: usize, usage: BufferUsage) -> Arena<T> {
+        Arena {
+            name: name,
+            buffers: Vec::new(),
+            len: len,
+            usage: usage,
+        }
+    }
+
+    fn alloc(
+        &mut self,
+        device: &Arc<Device>,
+        families: &[QueueFamily],
+        len: usize,
+    ) -> Result<Arc<ArenaSlice<T>>, RenderError> {
+        for &(ref buffer, ref ranges) in &self.buffers {
+            if let Some(slice) = arena_slice(buffer, ranges, len) {
+                return Ok(slice);
+            }
+        }
+
+        let size = cmp::max(self.len, len);
+        let buffer = DeviceLocalBuffer::array(
+            device.clone(),
+            size,
+            BufferUsage {
+                transfer_destination: true,
+                ..self.usage
+            },
+            families.iter().cloned(),
+        )?;
+        debug::name(
+            buffer.inner().buffer,
+            &format!("{} {}", self.name, self.buffers.len()),
+        );
+        let ranges = Arc::new(Mutex::new(RangeAllocator::new(size)));
+        let slice = arena_slice(&buffer, &ranges, len);
+        self.buffers.push((buffer, ranges));
+        slice.ok_or_else(|| {
+            RenderError::Unexpected(format!("{} elements don't fit into a new buffer", len))
+        })
+    }
+}
+
+impl<T> Drop for ArenaSlice<T> {
+    fn drop(&mut self) {
+        // poisoned by a panic somewhere else, the range is lost
+        if let Ok(mut ranges) = self.ranges.lock() {
+            ranges.free(self.range.clone());
+        }
+    }
+}
+
+// ranges of one buffer only overlap each other while they're the same
+// range, anything still using a range keeps it from being handed out again
+unsafe impl<T> BufferAccess for ArenaSlice<T>
+where
+    T: Send + Sync + 'static,
+{
+    fn inner(&self) -> BufferInner {
+        let inner = self.buffer.inner();
+        BufferInner {
+            buffer: inner.buffer,
+            offset: inner.offset + self.range.start * mem::size_of::<T>(),
+        }
+    }
+
+    fn size(&self) -> usize {
+        (self.range.end - self.range.start) * mem::size_of::<T>()
+    }
+
+    fn conflicts_buffer(&self, other: &BufferAccess) -> bool {
+        let (ours, theirs) = (self.inner(), other.inner());
+        ours.buffer.key() == theirs.buffer.key()
+            && ours.offset < theirs.offset + other.size()
+            && theirs.offset < ours.offset + self.size()
+    }
+
+    fn conflicts_image(&self, _: &ImageAccess) -> bool {
+        false
+    }
+
+    fn conflict_key(&self) -> u64 {
+        self.buffer.conflict_key()
+    }
+
+    fn try_gpu_lock(&self, exclusive: bool, _: &Queue) -> Result<(), AccessError> {
+        let mut lock = self.gpu_lock.lock().unwrap();
+        *lock = match *lock {
+            GpuAccess::None if exclusive => GpuAccess::Exclusive { num: 1 },
+            GpuAccess::None => GpuAccess::NonExclusive { num: 1 },
+            GpuAccess::NonExclusive { num } if !exclusive => {
+                GpuAccess::NonExclusive { num: num + 1 }
+            }
+            _ => return Err(AccessError::AlreadyInUse),
+        };
+        Ok(())
+    }
+
+    unsafe fn increase_gpu_lock(&self) {
+        let mut lock = self.gpu_lock.lock().unwrap();
+        *lock = match *lock {
+            GpuAccess::None => panic!("increasing the lock of an arena slice that isn't locked"),
+            GpuAccess::NonExclusive { num } => GpuAccess::NonExclusive { num: num + 1 },
+            GpuAccess::Exclusive { num } => GpuAccess::Exclusive { num: num + 1 },
+        };
+    }
+
+    unsafe fn unlock(&self) {
+        let mut lock = self.gpu_lock.lock().unwrap();
+        *lock = match *lock {
+            GpuAccess::None => panic!("unlocking an arena slice that isn't locked"),
+            GpuAccess::NonExclusive { num: 1 } | GpuAccess::Exclusive { num: 1 } => GpuAccess::None,
+            GpuAccess::NonExclusive { num } => GpuAccess::NonExclusive { num: num - 1 },
+            GpuAccess::Exclusive { num } => GpuAccess::Exclusive { num: num - 1 },
+        };
+    }
+}
+
+unsafe impl<T> DeviceOwned for ArenaSlice<T> {
+    fn device(&self) -> &Arc<Device> {
+        self.buffer.device()
+    }
+}
+
+unsafe impl<T> TypedBufferAccess for ArenaSlice<T>
+where
+    T: Send + Sync + 'static,
+{
+    type Content = [T];
+}
+
+fn arena_slice<T>(
+    buffer: &Arc<DeviceLocalBuffer<[T]>>,
+    ranges: &Arc<Mutex<RangeAllocator>>,
+    len: usize,
+) -> Option<Arc<ArenaSlice<T>>>
+where
+    T: Send + Sync + 'static,
+{
+    let range = match ranges.lock() {
+        Ok(mut ranges) => ranges.alloc(len),
+        Err(_) => None,
+    };
+    range.map(|range| {
+        Arc::new(ArenaSlice {
+            buffer: buffer.clone(),
+            range: range,
+            ranges: ranges.clone(),
+            gpu_lock: Mutex::new(GpuAccess::None),
+        })
+    })
+}
+
+pub struct SceneObject<'a> {
+    pub mesh: &'a Mesh,
+    pub world: Matrix4<f32>,
+    pub layer: RenderLayer,
+}
+
+pub struct Scene<'a> {
+    pub view: Matrix4<f32>,
+    pub objects: Vec<SceneObject<'a>>,
+}
+
+// the projection apart from the aspect ratio, which follows the target's
+// dimensions
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Perspective {
+    // vertical, in degrees
+    pub fov: f32,
+    pub near: f32,
+    pub far: f32,
+}
+
+impl Default for Perspective {
+    fn default() -> Perspective {
+        Perspective {
+            fov: 90.0,
+            near: 0.01,
+            far: 1024.0,
+        }
+    }
+}
+
+// how many objects of the last scene were drawn and how many were skipped
+// for being outside the view
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct DrawStats {
+    pub drawn: usize,
+    pub culled: usize,
+}
+
+// everything that can go wrong in the renderer. running out of date
+// swapchains and minimised windows are handled inside and never get this far,
+// a lost surface can be recovered from with `Renderer::rebuild_surface`, the
+// rest is fatal.
+#[derive(Debug)]
+pub enum RenderError {
+    Instance(InstanceCreationError),
+    Window(vulkano_win::CreationError),
+    NoDevice(String),
+    Device(DeviceCreationError),
+    Surface(CapabilitiesError),
+    NoColorFormat,
+    Swapchain(SwapchainCreationError),
+    Pipeline(String),
+    OutOfMemory(String),
+    DeviceLost,
+    SurfaceLost,
+    // anything else vulkano refuses to do, which means a bug in the renderer
+    Unexpected(String),
+}
+
+impl fmt::Display for RenderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RenderError::Instance(ref err) => write!(
+                f,
+                "couldn't initialize Vulkan, is a Vulkan driver installed? ({})",
+                err
+            ),
+            RenderError::Window(ref err) => write!(f, "couldn't open a window: {}", err),
+            RenderError::NoDevice(ref message) => write!(f, "{}", message),
+            RenderError::Device(ref err) => write!(f, "couldn't open the GPU: {}", err),
+            RenderError::Surface(ref err) => {
+                write!(f, "couldn't query the window surface: {}", err)
+            }
+            RenderError::NoColorFormat => write!(
+                f,
+                "the window doesn't support any color format the renderer can draw into"
+            ),
+            RenderError::Swapchain(ref err) => write!(f, "couldn't create the swapchain: {}", err),
+            RenderError::Pipeline(ref message) => {
+                write!(f, "couldn't build the graphics pipeline: {}", message)
+            }
+            RenderError::OutOfMemory(ref message) => write!(f, "ran out of memory: {}", message),
+            RenderError::DeviceLost => write!(
+                f,
+                "lost the GPU, it crashed or was reset. updating the driver or running with \
+                 --validation may help find out why."
+            ),
+            RenderError::SurfaceLost => write!(f, "lost the window surface"),
+            RenderError::Unexpected(ref message) => {
+                write!(f, "unexpected Vulkan error: {}", message)
+            }
+        }
+    }
+}
+
+impl error::Error for RenderError {
+    fn description(&self) -> &str {
+        match *self {
+            RenderError::Instance(..) => "couldn't initialize Vulkan",
+            RenderError::Window(..) => "couldn't open a window",
+            RenderError::NoDevice(ref message) => message,
+            RenderError::Device(..) => "couldn't open the GPU",
+            RenderError::Surface(..) => "couldn't query the window surface",
+            RenderError::NoColorFormat => "no supported color format",
+            RenderError::Swapchain(..) => "couldn't create the swapchain",
+            RenderError::Pipeline(ref message) => message,
+            RenderError::OutOfMemory(ref message) => message,
+            RenderError::DeviceLost => "lost the GPU",
+            RenderError::SurfaceLost => "lost the window surface",
+            RenderError::Unexpected(ref message) => message,
+        }
+    }
+}
+
+impl From<InstanceCreationError> for RenderError {
+    fn from(err: InstanceCreationError) -> RenderError {
+        RenderError::Instance(err)
+    }
+}
+
+impl From<vulkano_win::CreationError> for RenderError {
+    fn from(err: vulkano_win::CreationError) -> RenderError {
+        RenderError::Window(err)
+    }
+}
+
+impl From<DeviceCreationError> for RenderError {
+    fn from(err: DeviceCreationError) -> RenderError {
+        RenderError::Device(err)
+    }
+}
+
+impl From<CapabilitiesError> for RenderError {
+    fn from(err: CapabilitiesError) -> RenderError {
+        match err {
+            CapabilitiesError::SurfaceLost => RenderError::SurfaceLost,
+            err => RenderError::Surface(err),
+        }
+    }
+}
+
+impl From<SwapchainCreationError> for RenderError {
+    fn from(err: SwapchainCreationError) -> RenderError {
+        match err {
+            SwapchainCreationError::DeviceLost => RenderError::DeviceLost,
+            SwapchainCreationError::SurfaceLost => RenderError::SurfaceLost,
+            SwapchainCreationError::OomError(err) => RenderError::OutOfMemory(err.to_string()),
+            err => RenderError::Swapchain(err),
+        }
+    }
+}
+
+impl From<AcquireError> for RenderError {
+    fn from(err: AcquireError) -> RenderError {
+        match err {
+            AcquireError::DeviceLost => RenderError::DeviceLost,
+            AcquireError::SurfaceLost => RenderError::SurfaceLost,
+            AcquireError::OomError(err) => RenderError::OutOfMemory(err.to_string()),
+            err => RenderError::Unexpected(err.to_string()),
+        }
+    }
+}
+
+impl From<FlushError> for RenderError {
+    fn from(err: FlushError) -> RenderError {
+        match err {
+            FlushError::DeviceLost => RenderError::DeviceLost,
+            FlushError::SurfaceLost => RenderError::SurfaceLost,
+            FlushError::OomError(err) => RenderError::OutOfMemory(err.to_string()),
+            err => RenderError::Unexpected(err.to_string()),
+        }
+    }
+}
+
+impl From<OomError> for RenderError {
+    fn from(err: OomError) -> RenderError {
+        RenderError::OutOfMemory(err.to_string())
+    }
+}
+
+impl From<DeviceMemoryAllocError> for RenderError {
+    fn from(err: DeviceMemoryAllocError) -> RenderError {
+        RenderError::OutOfMemory(err.to_string())
+    }
+}
+
+impl From<RenderPassCreationError> for RenderError {
+    fn from(err: RenderPassCreationError) -> RenderError {
+        RenderError::Pipeline(err.to_string())
+    }
+}
+
+impl From<GraphicsPipelineCreationError> for RenderError {
+    fn from(err: GraphicsPipelineCreationError) -> RenderError {
+        RenderError::Pipeline(err.to_string())
+    }
+}
+
+// recording and submitting command buffers only fails when the renderer asks
+// for something invalid
+macro_rules! unexpected_errors {
+    ($($error:ty),*) => {
+        $(
+            impl From<$error> for RenderError {
+                fn from(err: $error) -> RenderError {
+                    RenderError::Unexpected(err.to_string())
+                }
+            }
+        )*
+    };
+}
+
+unexpected_errors!(
+    AutoCommandBufferBuilderContextError,
+    BeginRenderPassError,
+    BuildError,
+    CommandBufferExecError,
+    CopyBufferError,
+    CopyBufferImageError,
+    DrawIndexedError,
+    FramebufferCreationError,
+    ImageCreationError,
+    PersistentDescriptorSetBuildError,
+    PersistentDescriptorSetError,
+    SamplerCreationError
+);
+
+// where the frames end up, either a window's swapchain or a single image
+// that gets copied back to the host after every frame
+pub enum RenderTarget {
+    Window {
+        surface: Arc<Surface<winit::Window>>,
+        swapchain: Arc<Swapchain<winit::Window>>,
+        images: Vec<Arc<SwapchainImage<winit::Window>>>,
+        // kept for rebuilding the window and swapchain from scratch
+        vsync: Vsync,
+        fullscreen: bool,
+    },
+    Offscreen {
+        image: Arc<AttachmentImage<Format>>,
+        output: Arc<CpuAccessibleBuffer<[u8]>>,
+    },
+}
+
+// a recorded frame waiting to be submitted by `Renderer::present`
+pub struct Frame {
+    image_num: usize,
+    acquire_future: Option<SwapchainAcquireFuture<winit::Window>>,
+    command_buffer: AutoCommandBuffer,
+}
+
+pub struct Renderer {
+    instance: Arc<Instance>,
+    // set when validation is on, messages stop when it's dropped
+    _debug_callback: Option<DebugCallback>,
+    physical_index: usize,
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    // the graphics queue again when the device has no separate transfer queue
+    transfer_queue: Arc<Queue>,
+    target: RenderTarget,
+    dimensions: [u32; 2],
+    depth_buffer: Arc<AttachmentImage<Format>>,
+    render_pass: Arc<RenderPassAbstract + Send + Sync>,
+    pipelines: Pipelines,
+    framebuffers: Option<Vec<Arc<FramebufferAbstract + Send + Sync>>>,
+    uniform_buffer: CpuBufferPool<vs::ty::Data>,
+    // every mesh's buffers are ranges of these
+    vertices: Arena<Vertex>,
+    normals: Arena<Normal>,
+    indices: Arena<u32>,
+    texture: Arc<ImmutableImage<Format>>,
+    sampler: Arc<Sampler>,
+    perspective: Perspective,
+    proj: Matrix4<f32>,
+    recreate_swapchain: bool,
+    // set while the window is minimized, nothing gets drawn until it's back
+    paused: bool,
+    previous_frame_end: Box<GpuFuture>,
+    stats: DrawStats,
+}
+
+impl Renderer {
+    pub fn new_windowed(
+        events_loop: &winit::EventsLoop,
+        settings: &Settings,
+    ) -> Result<Renderer, RenderError> {
+        let (instance, debug_callback) =
+            debug::create_instance(vulkano_win::required_extensions(), settings.validation)?;
+
+        let window = build_window(
+            events_loop,
+            &instance,
+            [settings.width, settings.height],
+            settings.fullscreen,
+        )?;
+
+        let physical = pick_device(&instance, settings.gpu, Some(&window))?;
+        info!(
+            "Using device: {} (type: {:?})",
+            physical.name(),
+            physical.ty()
+        );
+
+        // We take the first queue that supports drawing to our window, choosing
+        // the device made sure there is one.
+        let queue = physical
+            .queue_families()
+            .find(|&q| q.supports_graphics() && window.is_supported(q).unwrap_or(false))
+            .ok_or_else(|| RenderError::NoDevice("the GPU can't draw to the window".to_string()))?;
+
+        let transfer = transfer_family(physical);
+        let (device, mut queues) = {
+            let device_ext = DeviceExtensions {
+                khr_swapchain: true,
+                ..debug::device_extensions(physical, settings.validation)
+            };
+
+            Device::new(
+                physical,
+                &device_features(physical),
+                &device_ext,
+                Some((queue, 0.5))
+                    .into_iter()
+                    .chain(transfer.map(|family| (family, 0.5))),
+            )?
+        };
+
+        let queue = queues.next().unwrap();
+        let transfer_queue = queues.next().unwrap_or_else(|| queue.clone());
+
+        let (swapchain, images) =
+            create_swapchain(&device, &window, physical, &queue, settings.vsync)?;
+
+        let color_format = swapchain.format();
+        let dimensions = swapchain.dimensions();
+        debug!("Dimensions: {}, {}", dimensions[0], dimensions[1]);
+        let target = RenderTarget::Window {
+            surface: window,
+            swapchain: swapchain,
+            images: images,
+            vsync: settings.vsync,
+            fullscreen: settings.fullscreen,
+        };
+
+        let physical_index = physical.index();
+        Renderer::init(
+            instance.clone(),
+            debug_callback,
+            physical_index,
+            device,
+            queue,
+            transfer_queue,
+            target,
+            color_format,
+            dimensions,
+            Perspective {
+                fov: settings.fov,
+                near: settings.near_plane(),
+                far: settings.far_plane(),
+            },
+        )
+    }
+
+    // renders into an image instead of a window so that no display is needed,
+    // use `save_png` after `present` to get at the pixels
+    pub fn new_headless(dimensions: [u32; 2]) -> Result<Renderer, RenderError> {
+        let instance = Instance::new(None, &InstanceExtensions::none(), None)?;
+
+        let physical = pick_device(&instance, None, None)?;
+        info!(
+            "Using device: {} (type: {:?})",
+            physical.name(),
+            physical.ty()
+        );
+
+        let queue = physical
+            .queue_families()
+            .find(|&q| q.supports_graphics())
+            .ok_or_else(|| RenderError::NoDevice("the GPU can't draw".to_string()))?;
+
+        let transfer = transfer_family(physical);
+        let (device, mut queues) = Device::new(
+            physical,
+            &device_features(physical),
+            &DeviceExtensions::none(),
+            Some((queue, 0.5))
+                .into_iter()
+                .chain(transfer.map(|family| (family, 0.5))),
+        )?;
+
+        let queue = queues.next().unwrap();
+        let transfer_queue = queues.next().unwrap_or_else(|| queue.clone());
+
+        // the readback in `save_png` assumes 4 bytes per pixel in RGBA order
+        let color_format = Format::R8G8B8A8Unorm;
+
+        let image = AttachmentImage::with_usage(
+            device.clone(),
+            dimensions,
+            color_format,
+            ImageUsage {
+                color_attachment: true,
+                transfer_source: true,
+                ..ImageUsage::none()
+            },
+        )?;
+
+        let output = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::all(),
+            (0..dimensions[0] * dimensions[1] * 4).map(|_| 0u8),
+        )?;
+
+        let target = RenderTarget::Offscreen {
+            image: image,
+            output: output,
+        };
+
+        let physical_index = physical.index();
+        Renderer::init(
+            instance.clone(),
+            None,
+            physical_index,
+            device,
+            queue,
+            transfer_queue,
+            target,
+            color_format,
+            dimensions,
+            Perspective::default(),
+        )
+    }
+
+    fn init(
+        instance: Arc<Instance>,
+        debug_callback: Option<DebugCallback>,
+        physical_index: usize,
+        device: Arc<Device>,
+        queue: Arc<Queue>,
+        transfer_queue: Arc<Queue>,
+        target: RenderTarget,
+        color_format: Format,
+        dimensions: [u32; 2],
+        perspective: Perspective,
+    ) -> Result<Renderer, RenderError> {
+        let depth_buffer =
+            AttachmentImage::transient(device.clone(), dimensions, pipeline::DEPTH_FORMAT)?;
+
+        let render_pass = pipeline::render_pass(device.clone(), color_format)?;
+        let pipelines = Pipelines::new(device.clone(), render_pass.clone())?;
+
+        let uniform_buffer = CpuBufferPool::<vs::ty::Data>::new(device.clone(), BufferUsage::all());
+
+        let proj = projection(dimensions, &perspective);
+
+        // plain white until `upload_atlas` replaces it
+        let (texture, upload) = upload_texture(
+            device.clone(),
+            queue.clone(),
+            &AtlasImage {
+                width: 1,
+                height: 1,
+                levels: vec![vec![255; 4]],
+            },
+        )?;
+
+        // nearest filtering within a mip level never picks up texels of the
+        // neighbouring atlas tiles
+        let sampler = Sampler::new(
+            device.clone(),
+            Filter::Nearest,
+            Filter::Nearest,
+            MipmapMode::Linear,
+            SamplerAddressMode::Repeat,
+            SamplerAddressMode::Repeat,
+            SamplerAddressMode::Repeat,
+            0.0,
+            1.0,
+            0.0,
+            1000.0,
+        )?;
+
+        let previous_frame_end = Box::new(upload) as Box<GpuFuture>;
+        let vertex_usage = BufferUsage {
+            vertex_buffer: true,
+            ..BufferUsage::none()
+        };
+
+        Ok(Renderer {
+            instance: instance,
+            _debug_callback: debug_callback,
+            physical_index: physical_index,
+            device: device,
+            queue: queue,
+            transfer_queue: transfer_queue,
+            target: target,
+            dimensions: dimensions,
+            depth_buffer: depth_buffer,
+            render_pass: render_pass,
+            pipelines: pipelines,
+            framebuffers: None,
+            uniform_buffer: uniform_buffer,
+            vertices: Arena::new("chunk vertices", ARENA_VERTICES, vertex_usage),
+            normals: Arena::new("chunk normals", ARENA_VERTICES, vertex_usage),
+            indices: Arena::new(
+                "chunk indices",
+                ARENA_INDICES,
+                BufferUsage {
+                    index_buffer: true,
+                    ..BufferUsage::none()
+                },
+            ),
+            texture: texture,
+            sampler: sampler,
+            perspective: perspective,
+            proj: proj,
+            recreate_swapchain: false,
+            paused: false,
+            previous_frame_end: previous_frame_end,
+            stats: DrawStats::default(),
+        })
+    }
+
+    pub fn window(&self) -> Option<&winit::Window> {
+        match self.target {
+            RenderTarget::Window { ref surface, .. } => Some(surface.window()),
+            RenderTarget::Offscreen { .. } => None,
+        }
+    }
+
+    pub fn upload_atlas(&mut self, atlas: &AtlasImage) -> Result<(), RenderError> {
+        let (texture, upload) = upload_texture(self.device.clone(), self.queue.clone(), atlas)?;
+        debug::name_image(texture.inner().image, "block atlas");
+        self.texture = texture;
+        self.wait_before_next_frame(upload);
+        Ok(())
+    }
+
+    pub fn upload_mesh(&mut self, data: &MeshData) -> Result<Mesh, RenderError> {
+        Ok(self.upload_meshes(&[data])?.pop().unwrap())
+    }
+
+    // copies the meshes into ranges of the arenas. the copies go through
+    // host visible staging buffers on the transfer queue, one per attribute
+    // for the whole batch, and are finished before the next frame is drawn.
+    pub fn upload_meshes(&mut self, meshes: &[&MeshData]) -> Result<Vec<Mesh>, RenderError> {
+        let mut batch = MeshBatch::new();
+        let ranges = meshes
+            .iter()
+            .map(|data| {
+                if data.is_empty() {
+                    None
+                } else {
+                    Some(batch.push(data))
+                }
+            })
+            .collect::<Vec<_>>();
+        if batch.indices.is_empty() {
+            return Ok(meshes
+                .iter()
+                .map(|data| Mesh {
+                    buffers: None,
+                    bounds: data.bounds(),
+                })
+                .collect());
+        }
+
+        let families = queue_families(&self.queue, &self.transfer_queue);
+        let vertices = staging_buffer(self.device.clone(), &batch.vertices)?;
+        let normals = staging_buffer(self.device.clone(), &batch.normals)?;
+        let indices = staging_buffer(self.device.clone(), &batch.indices)?;
+        let mut builder =
+            AutoCommandBufferBuilder::new(self.device.clone(), self.transfer_queue.family())?;
+        let mut uploaded = Vec::with_capacity(meshes.len());
+        for (range, data) in ranges.into_iter().zip(meshes.iter()) {
+            let range = match range {
+                Some(range) => range,
+                None => {
+                    uploaded.push(Mesh {
+                        buffers: None,
+                        bounds: data.bounds(),
+                    });
+                    continue;
+                }
+            };
+            let vertex_range = range.first_vertex..range.first_vertex + range.vertex_count;
+            let index_range = range.first_index..range.first_index + range.index_count;
+
+            let buffers = MeshBuffers {
+                vertices: self
+                    .vertices
+                    .alloc(&self.device, &families, range.vertex_count)?,
+                normals: self
+                    .normals
+                    .alloc(&self.device, &families, range.vertex_count)?,
+                indices: self
+                    .indices
+                    .alloc(&self.device, &families, range.index_count)?,
+            };
+            builder = copy_range(builder, &vertices, vertex_range.clone(), &buffers.vertices)?;
+            builder = copy_range(builder, &normals, vertex_range, &buffers.normals)?;
+            builder = copy_range(builder, &indices, index_range, &buffers.indices)?;
+            uploaded.push(Mesh {
+                buffers: Some(buffers),
+                bounds: data.bounds(),
+            });
+        }
+
+        let upload = builder.build()?.execute(self.transfer_queue.clone())?;
+        self.wait_before_next_frame(Box::new(upload));
+        Ok(uploaded)
+    }
+
+    // gives the meshes new indices, e.g. with the triangles sorted for
+    // another viewpoint. there have to be as many indices as were uploaded.
+    // they go into new ranges rather than over the old ones, which frames
+    // that are still on the GPU may be drawing from.
+    pub fn update_indices(
+        &mut self,
+        updates: &mut [(&mut Mesh, &[u32])],
+    ) -> Result<(), RenderError> {
+        let mut all = Vec::new();
+        let mut copies = Vec::new();
+        for (i, &(ref mesh, indices)) in updates.iter().enumerate() {
+            let len = match mesh.buffers {
+                Some(ref buffers) => buffers.indices.range.end - buffers.indices.range.start,
+                None => continue,
+            };
+            if indices.len() != len {
+                return Err(RenderError::Unexpected(format!(
+                    "can't replace {} indices with {}",
+                    len,
+                    indices.len()
+                )));
+            }
+            copies.push((i, all.len()..all.len() + len));
+            all.extend_from_slice(indices);
+        }
+        if all.is_empty() {
+            return Ok(());
+        }
+
+        let families = queue_families(&self.queue, &self.transfer_queue);
+        let staging = staging_buffer(self.device.clone(), &all)?;
+        let mut builder =
+            AutoCommandBufferBuilder::new(self.device.clone(), self.transfer_queue.family())?;
+        let mut replaced = Vec::with_capacity(copies.len());
+        for (i, range) in copies {
+            let target = self
+                .indices
+                .alloc(&self.device, &families, range.end - range.start)?;
+            builder = copy_range(builder, &staging, range, &target)?;
+            replaced.push((i, target));
+        }
+        let upload = builder.build()?.execute(self.transfer_queue.clone())?;
+        self.wait_before_next_frame(Box::new(upload));
+
+        for (i, target) in replaced {
+            if let Some(ref mut buffers) = updates[i].0.buffers {
+                buffers.indices = target;
+            }
+        }
+        Ok(())
+    }
+
+    // makes the next frame wait for `future`, e.g. an upload it reads from
+    fn wait_before_next_frame(&mut self, future: Box<GpuFuture>) {
+        let previous_frame_end = mem::replace(
+            &mut self.previous_frame_end,
+            Box::new(now(self.device.clone())) as Box<GpuFuture>,
+        );
+        self.previous_frame_end = Box::new(previous_frame_end.join(future)) as Box<GpuFuture>;
+    }
+
+    // the swapchain is rebuilt lazily at the start of the next `draw`. vulkano
+    // doesn't pass VK_SUBOPTIMAL_KHR on, so besides acquiring and presenting
+    // reporting it out of date, resize events are what trigger a rebuild.
+    pub fn resize(&mut self) {
+        self.recreate_swapchain = true;
+    }
+
+    // records the command buffer for the scene, returns None when there is
+    // nothing to draw into this time around (e.g. the swapchain is out of date)
+    pub fn draw(&mut self, scene: &Scene) -> Result<Option<Frame>, RenderError> {
+        self.previous_frame_end.cleanup_finished();
+
+        if self.recreate_swapchain && !self.recreate()? {
+            return Ok(None);
+        }
+
+        if self.framebuffers.is_none() {
+            let framebuffers = self.create_framebuffers()?;
+            self.framebuffers = Some(framebuffers);
+        }
+
+        let (image_num, acquire_future) = match self.target {
+            RenderTarget::Window { ref swapchain, .. } => {
+                match swapchain::acquire_next_image(swapchain.clone(), None) {
+                    Ok((image_num, future)) => (image_num, Some(future)),
+                    Err(AcquireError::OutOfDate) => {
+                        self.recreate_swapchain = true;
+                        return Ok(None);
+                    }
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            RenderTarget::Offscreen { .. } => (0, None),
+        };
+
+        let mut builder = AutoCommandBufferBuilder::primary_one_time_submit(
+            self.device.clone(),
+            self.queue.family(),
+        )?
+        .begin_render_pass(
+            self.framebuffers.as_ref().unwrap()[image_num].clone(),
+            false,
+            vec![[0.0, 0.0, 1.0, 1.0].into(), 1f32.into()],
+        )?;
+
+        let frustum = Frustum::from_matrix(self.proj * scene.view);
+        self.stats = DrawStats::default();
+
+        // the camera sits at the origin of view space
+        let eye = match scene.view.invert() {
+            Some(inverse) => inverse.w.truncate(),
+            None => cgmath::Vector3::new(0.0, 0.0, 0.0),
+        };
+
+        // visible objects along with the squared distance from the camera to
+        // their center
+        let mut visible = Vec::new();
+        for object in &scene.objects {
+            if object.mesh.buffers.is_none() {
+                continue;
+            }
+
+            let (min, max) = object.mesh.bounds;
+            let (min, max) = frustum::transform_aabb(&object.world, min, max);
+            if !frustum.intersects_aabb(min, max) {
+                self.stats.culled += 1;
+                continue;
+            }
+            self.stats.drawn += 1;
+
+            let mut distance = 0.0;
+            for axis in 0..3 {
+                let offset = (min[axis] + max[axis]) / 2.0 - eye[axis];
+                distance += offset * offset;
+            }
+            visible.push((object, distance));
+        }
+
+        for &layer in [RenderLayer::Opaque, RenderLayer::Cutout].iter() {
+            for &(object, _) in visible.iter().filter(|&&(o, _)| o.layer == layer) {
+                builder = self.draw_object(builder, scene, object)?;
+            }
+        }
+
+        // blending only works out when whatever is behind has been drawn
+        // already, so translucent meshes go from far to near. the faces
+        // within a mesh are sorted when it's built.
+        builder = builder.next_subpass(false)?;
+        let mut translucent = visible
+            .into_iter()
+            .filter(|&(o, _)| o.layer == RenderLayer::Translucent)
+            .collect::<Vec<_>>();
+        translucent.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(Ordering::Equal));
+        for (object, _) in translucent {
+            builder = self.draw_object(builder, scene, object)?;
+        }
+
+        builder = builder.end_render_pass()?;
+
+        if let RenderTarget::Offscreen {
+            ref image,
+            ref output,
+        } = self.target
+        {
+            builder = builder.copy_image_to_buffer(image.clone(), output.clone())?;
+        }
+
+        Ok(Some(Frame {
+            image_num: image_num,
+            acquire_future: acquire_future,
+            command_buffer: builder.build()?,
+        }))
+    }
+
+    fn draw_object(
+        &self,
+        builder: AutoCommandBufferBuilder,
+        scene: &Scene,
+        object: &SceneObject,
+    ) -> Result<AutoCommandBufferBuilder, RenderError> {
+        let buffers = match object.mesh.buffers {
+            Some(ref buffers) => buffers,
+            None => return Ok(builder),
+        };
+        let pipeline = self.pipelines.get(object.layer);
+
+        let uniform_buffer_subbuffer = {
+            let uniform_data = vs::ty::Data {
+                world: object.world.into(),
+                view: scene.view.into(),
+                proj: self.proj.into(),
+            };
+
+            self.uniform_buffer.next(uniform_data)?
+        };
+
+        let set = Arc::new(
+            PersistentDescriptorSet::start(pipeline.clone(), 0)
+                .add_buffer(uniform_buffer_subbuffer)?
+                .add_sampled_image(self.texture.clone(), self.sampler.clone())?
+                .build()?,
+        );
+
+        Ok(builder.draw_indexed(
+            pipeline.clone(),
+            self.dynamic_state(),
+            (buffers.vertices.clone(), buffers.normals.clone()),
+            buffers.indices.clone(),
+            set,
+            (),
+        )?)
+    }
+
+    // submits a frame returned by `draw`, offscreen frames are waited on so
+    // the output buffer can be read straight away
+    pub fn present(&mut self, frame: Frame) -> Result<(), RenderError> {
+        let previous_frame_end = mem::replace(
+            &mut self.previous_frame_end,
+            Box::new(now(self.device.clone())) as Box<GpuFuture>,
+        );
+
+        match self.target {
+            RenderTarget::Window { ref swapchain, .. } => {
+                let future = previous_frame_end
+                    .join(frame.acquire_future.unwrap())
+                    .then_execute(self.queue.clone(), frame.command_buffer)?
+                    .then_swapchain_present(self.queue.clone(), swapchain.clone(), frame.image_num)
+                    .then_signal_fence_and_flush();
+                match future {
+                    Ok(future) => self.previous_frame_end = Box::new(future) as Box<_>,
+                    // the frame is dropped and the swapchain rebuilt before
+                    // the next one
+                    Err(FlushError::OutOfDate) => self.recreate_swapchain = true,
+                    Err(err) => return Err(err.into()),
+                }
+            }
+            RenderTarget::Offscreen { .. } => {
+                previous_frame_end
+                    .then_execute(self.queue.clone(), frame.command_buffer)?
+                    .then_signal_fence_and_flush()?
+                    .wait(None)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn stats(&self) -> DrawStats {
+        self.stats
+    }
+
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        match self.target {
+            RenderTarget::Offscreen { ref output, .. } => {
+                let buffer_content = output
+                    .read()
+                    .map_err(|err| io::Error::new(io::ErrorKind::Other, err.to_string()))?;
+                let image = ImageBuffer::<Rgba<u8>, _>::from_raw(
+                    self.dimensions[0],
+                    self.dimensions[1],
+                    &buffer_content[..],
+                ).ok_or_else(|| {
+                    io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "the readback buffer is smaller than the image",
+                    )
+                })?;
+                image.save(path)
+            }
+            RenderTarget::Window { .. } => Err(io::Error::new(
+                io::ErrorKind::Other,
+                "only offscreen targets can be saved",
+            )),
+        }
+    }
+
+    fn dynamic_state(&self) -> DynamicState {
+        DynamicState {
+            line_width: None,
+            viewports: Some(vec![Viewport {
+                origin: [0.0, 0.0],
+                dimensions: [self.dimensions[0] as f32, self.dimensions[1] as f32],
+                depth_range: 0.0..1.0,
+            }]),
+            scissors: None,
+        }
+    }
+
+    // also names the attachments, this runs whenever they've been replaced
+    fn create_framebuffers(
+        &self,
+    ) -> Result<Vec<Arc<FramebufferAbstract + Send + Sync>>, RenderError> {
+        debug::name_image(self.depth_buffer.inner().image, "depth buffer");
+        match self.target {
+            RenderTarget::Window { ref images, .. } => images
+                .iter()
+                .enumerate()
+                .map(|(i, image)| {
+                    debug::name_image(image.inner().image, &format!("swapchain image {}", i));
+                    let framebuffer = Framebuffer::start(self.render_pass.clone())
+                        .add(image.clone())?
+                        .add(self.depth_buffer.clone())?
+                        .build()?;
+                    Ok(Arc::new(framebuffer) as Arc<FramebufferAbstract + Send + Sync>)
+                })
+                .collect(),
+            RenderTarget::Offscreen { ref image, .. } => {
+                debug::name_image(image.inner().image, "offscreen target");
+                let framebuffer = Arc::new(
+                    Framebuffer::start(self.render_pass.clone())
+                        .add(image.clone())?
+                        .add(self.depth_buffer.clone())?
+                        .build()?,
+                );
+                Ok(vec![framebuffer as Arc<FramebufferAbstract + Send + Sync>])
+            }
+        }
+    }
+
+    // returns false if the swapchain couldn't be recreated yet, in which case
+    // the next call to `draw` tries again
+    fn recreate(&mut self) -> Result<bool, RenderError> {
+        {
+            let physical = PhysicalDevice::from_index(&self.instance, self.physical_index).unwrap();
+
+            if let RenderTarget::Window {
+                ref surface,
+                ref mut swapchain,
+                ref mut images,
+                ..
+            } = self.target
+            {
+                let caps = surface.capabilities(physical)?;
+                let dimensions = match surface_extent(surface, &caps) {
+                    Some(dimensions) => dimensions,
+                    None => {
+                        if !self.paused {
+                            debug!("window minimized, pausing rendering");
+                        }
+                        self.paused = true;
+                        return Ok(false);
+                    }
+                };
+                debug!("Recreate Dimensions: {}, {}", dimensions[0], dimensions[1]);
+
+                let (new_swapchain, new_images) =
+                    match swapchain.recreate_with_dimension(dimensions) {
+                        Ok(r) => r,
+                        // the window is being resized right as this happens,
+                        // the next frame tries again with the newer size
+                        Err(SwapchainCreationError::UnsupportedDimensions) => {
+                            return Ok(false);
+                        }
+                        Err(err) => return Err(err.into()),
+                    };
+
+                *swapchain = new_swapchain;
+                *images = new_images;
+                self.dimensions = swapchain.dimensions();
+            }
+        }
+
+        self.paused = false;
+        self.resize_attachments()?;
+        self.recreate_swapchain = false;
+        Ok(true)
+    }
+
+    // true while the window is minimized. `draw` keeps checking whether it's
+    // back, but there's no point calling it before the window changes again.
+    pub fn is_paused(&self) -> bool {
+        self.paused
+    }
+
+    // rebuilds what depends on the size of the target after it changed
+    fn resize_attachments(&mut self) -> Result<(), RenderError> {
+        self.depth_buffer = AttachmentImage::transient(
+            self.device.clone(),
+            self.dimensions,
+            pipeline::DEPTH_FORMAT,
+        )?;
+        self.framebuffers = None;
+        self.proj = projection(self.dimensions, &self.perspective);
+        Ok(())
+    }
+
+    // replaces the window and its swapchain after `draw` or `present` reported
+    // that the surface was lost. the new window has the old one's size.
+    pub fn rebuild_surface(&mut self, events_loop: &winit::EventsLoop) -> Result<(), RenderError> {
+        let (old_format, vsync, fullscreen) = match self.target {
+            RenderTarget::Window {
+                ref swapchain,
+                vsync,
+                fullscreen,
+                ..
+            } => (swapchain.format(), vsync, fullscreen),
+            RenderTarget::Offscreen { .. } => return Ok(()),
+        };
+        // waits for whatever still uses the old swapchain
+        self.previous_frame_end = Box::new(now(self.device.clone())) as Box<GpuFuture>;
+
+        let physical = PhysicalDevice::from_index(&self.instance, self.physical_index).unwrap();
+        let window = build_window(events_loop, &self.instance, self.dimensions, fullscreen)?;
+        if !window.is_supported(self.queue.family()).unwrap_or(false) {
+            return Err(RenderError::NoDevice(
+                "the GPU can't draw to the new window".to_string(),
+            ));
+        }
+        let (swapchain, images) =
+            create_swapchain(&self.device, &window, physical, &self.queue, vsync)?;
+
+        // the render pass is tied to the color format, which might not be
+        // available any more
+        if swapchain.format() != old_format {
+            self.render_pass = pipeline::render_pass(self.device.clone(), swapchain.format())?;
+            self.pipelines = Pipelines::new(self.device.clone(), self.render_pass.clone())?;
+        }
+
+        self.dimensions = swapchain.dimensions();
+        self.paused = false;
+        self.target = RenderTarget::Window {
+            surface: window,
+            swapchain: swapchain,
+            images: images,
+            vsync: vsync,
+            fullscreen: fullscreen,
+        };
+        self.resize_attachments()?;
+        self.recreate_swapchain = false;
+        Ok(())
+    }
+}
+
+// prints everything about each device that goes into choosing one, plus the
+// formats and present modes a window on it would get
+pub fn list_devices(events_loop: &winit::EventsLoop) -> Result<(), RenderError> {
+    let instance = Instance::new(None, &vulkano_win::required_extensions(), None)?;
+    // formats and present modes depend on the surface, so a window is made
+    // just to ask about them
+    let window = winit::WindowBuilder::new()
+        .with_visibility(false)
+        .build_vk_surface(events_loop, instance.clone());
+    let window = match window {
+        Ok(window) => Some(window),
+        Err(err) => {
+            println!(
+                "couldn't create a window, leaving out surface details: {}",
+                err
+            );
+            None
+        }
+    };
+
+    let candidates: Vec<_> = PhysicalDevice::enumerate(&instance)
+        .map(|physical| candidate(physical, window.as_ref()))
+        .collect();
+    let chosen = gpu::choose(&candidates);
+
+    for (physical, candidate) in PhysicalDevice::enumerate(&instance).zip(&candidates) {
+        let version = physical.api_version();
+        println!(
+            "{}: {} ({:?}){}",
+            physical.index(),
+            physical.name(),
+            physical.ty(),
+            if chosen == Some(physical.index()) {
+                ", chosen by default"
+            } else {
+                ""
+            }
+        );
+        match candidate.score() {
+            Ok(score) => println!("    score: {}", score),
+            Err(reason) => println!("    unusable: {}", reason),
+        }
+        println!(
+            "    api {}.{}.{}, driver {:#x}, vendor {:#06x}, device {:#06x}",
+            version.major,
+            version.minor,
+            version.patch,
+            physical.driver_version(),
+            physical.pci_vendor_id(),
+            physical.pci_device_id()
+        );
+        for heap in physical.memory_heaps() {
+            println!(
+                "    memory heap: {} MiB{}",
+                heap.size() >> 20,
+                if heap.is_device_local() {
+                    ", device local"
+                } else {
+                    ""
+                }
+            );
+        }
+        let limits = physical.limits();
+        println!(
+            "    limits: 2d images up to {}, {} bytes of push constants, {}x anisotropy, \
+             index values up to {}",
+            limits.max_image_dimension_2d(),
+            limits.max_push_constants_size(),
+            limits.max_sampler_anisotropy(),
+            limits.max_draw_indexed_index_value()
+        );
+        for family in physical.queue_families() {
+            let mut abilities = Vec::new();
+            if family.supports_graphics() {
+                abilities.push("graphics");
+            }
+            if family.supports_compute() {
+                abilities.push("compute");
+            }
+            if family.explicitly_supports_transfers() {
+                abilities.push("transfer");
+            }
+            if family.supports_sparse_binding() {
+                abilities.push("sparse binding");
+            }
+            if let Some(ref window) = window {
+                if window.is_supported(family).unwrap_or(false) {
+                    abilities.push("present");
+                }
+            }
+            println!(
+                "    queue family {}: {} queues, {}",
+                family.id(),
+                family.queues_count(),
+                abilities.join(", ")
+            );
+        }
+        if let Some(ref window) = window {
+            match window.capabilities(physical) {
+                Ok(caps) => {
+                    let formats: Vec<_> = caps
+                        .supported_formats
+                        .iter()
+                        .map(|&(format, color_space)| format!("{:?} {:?}", format, color_space))
+                        .collect();
+                    let modes: Vec<_> = caps
+                        .present_modes
+                        .iter()
+                        .map(|mode| format!("{:?}", mode))
+                        .collect();
+                    println!("    formats: {}", formats.join(", "));
+                    println!("    present modes: {}", modes.join(", "));
+                }
+                Err(err) => println!("    no surface support: {}", err),
+            }
+        }
+    }
+    Ok(())
+}
+
+// the device `--gpu` asked for, otherwise the best scoring one. `surface` is
+// None when rendering offscreen.
+fn pick_device<'a>(
+    instance: &'a Arc<Instance>,
+    index: Option<usize>,
+    surface: Option<&Arc<Surface<winit::Window>>>,
+) -> Result<PhysicalDevice<'a>, RenderError> {
+    let index = match index {
+        Some(index) => {
+            let physical = PhysicalDevice::from_index(instance, index).ok_or_else(|| {
+                RenderError::NoDevice(format!(
+                    "there's no GPU with index {}, see --list-devices",
+                    index
+                ))
+            })?;
+            if let Err(reason) = candidate(physical, surface).score() {
+                return Err(RenderError::NoDevice(format!(
+                    "can't use GPU {} ({}): {}",
+                    index,
+                    physical.name(),
+                    reason
+                )));
+            }
+            index
+        }
+        None => {
+            let candidates: Vec<_> = PhysicalDevice::enumerate(instance)
+                .map(|physical| candidate(physical, surface))
+                .collect();
+            gpu::choose(&candidates).ok_or_else(|| {
+                RenderError::NoDevice("no usable GPU was found, see --list-devices".to_string())
+            })?
+        }
+    };
+    Ok(PhysicalDevice::from_index(instance, index).unwrap())
+}
+
+fn build_window(
+    events_loop: &winit::EventsLoop,
+    instance: &Arc<Instance>,
+    dimensions: [u32; 2],
+    fullscreen: bool,
+) -> Result<Arc<Surface<winit::Window>>, RenderError> {
+    let mut builder = winit::WindowBuilder::new()
+        .with_title("rscraft")
+        .with_dimensions(dimensions[0], dimensions[1]);
+    if fullscreen {
+        builder = builder.with_fullscreen(Some(events_loop.get_primary_monitor()));
+    }
+    Ok(builder.build_vk_surface(events_loop, instance.clone())?)
+}
+
+// winit hands out the window size in points, which is only the same as
+// pixels at a hidpi factor of 1. it's only needed on platforms where the
+// surface lets the swapchain pick its size, the others report it in pixels.
+fn surface_extent(surface: &Surface<winit::Window>, caps: &Capabilities) -> Option<[u32; 2]> {
+    let window = surface.window();
+    gpu::swapchain_extent(
+        caps.current_extent,
+        window.get_inner_size(),
+        window.hidpi_factor(),
+        caps.min_image_extent,
+        caps.max_image_extent,
+    )
+}
+
+// tries the color formats the surface offers in turn, moving on to the next
+// one when the driver won't make a swapchain with a format it advertised
+fn create_swapchain(
+    device: &Arc<Device>,
+    surface: &Arc<Surface<winit::Window>>,
+    physical: PhysicalDevice,
+    queue: &Arc<Queue>,
+    vsync: Vsync,
+) -> Result<
+    (
+        Arc<Swapchain<winit::Window>>,
+        Vec<Arc<SwapchainImage<winit::Window>>>,
+    ),
+    RenderError,
+> {
+    let caps = surface.capabilities(physical)?;
+
+    debug!(
+        "caps image extent min: {:?} max: {:?}, current_extent: {:?}",
+        caps.min_image_extent, caps.max_image_extent, caps.current_extent
+    );
+
+    // the spec guarantees at least one
+    let alpha = caps.supported_composite_alpha.iter().next().unwrap();
+    // a window that starts out minimized still needs some swapchain, it's
+    // recreated at the right size once the window shows up
+    let dimensions = surface_extent(surface, &caps).unwrap_or([
+        caps.min_image_extent[0].max(1),
+        caps.min_image_extent[1].max(1),
+    ]);
+    let present_mode = present_mode(vsync, caps.present_modes);
+
+    let mut result = Err(RenderError::NoColorFormat);
+    for &(format, _) in caps
+        .supported_formats
+        .iter()
+        .filter(|&&(format, _)| is_color_format(format))
+    {
+        result = Swapchain::new(
+            device.clone(),
+            surface.clone(),
+            caps.min_image_count,
+            format,
+            dimensions,
+            1,
+            caps.supported_usage_flags,
+            queue,
+            SurfaceTransform::Identity,
+            alpha,
+            present_mode,
+            true,
+            None,
+        ).map_err(RenderError::from);
+        match result {
+            Ok(_) | Err(RenderError::DeviceLost) | Err(RenderError::SurfaceLost) => break,
+            Err(ref err) => warn!("couldn't use {:?}, trying the next format: {}", format, err),
+        }
+    }
+    result
+}
+
+fn candidate(physical: PhysicalDevice, surface: Option<&Arc<Surface<winit::Window>>>) -> Candidate {
+    let (can_draw, has_formats) = match surface {
+        Some(surface) => {
+            let can_draw = physical
+                .queue_families()
+                .any(|q| q.supports_graphics() && surface.is_supported(q).unwrap_or(false));
+            let has_formats = surface
+                .capabilities(physical)
+                .map(|caps| {
+                    caps.supported_formats
+                        .iter()
+                        .any(|&(format, _)| is_color_format(format))
+                })
+                .unwrap_or(false);
+            (can_draw, has_formats)
+        }
+        // every device can render into R8G8B8A8Unorm
+        None => (
+            physical.queue_families().any(|q| q.supports_graphics()),
+            true,
+        ),
+    };
+    Candidate {
+        index: physical.index(),
+        name: physical.name(),
+        kind: match physical.ty() {
+            PhysicalDeviceType::DiscreteGpu => DeviceKind::Discrete,
+            PhysicalDeviceType::IntegratedGpu => DeviceKind::Integrated,
+            PhysicalDeviceType::VirtualGpu => DeviceKind::Virtual,
+            PhysicalDeviceType::Cpu => DeviceKind::Cpu,
+            PhysicalDeviceType::Other => DeviceKind::Other,
+        },
+        can_draw: can_draw,
+        has_formats: has_formats,
+        device_local_memory: physical
+            .memory_heaps()
+            .filter(|heap| heap.is_device_local())
+            .map(|heap| heap.size() as u64)
+            .sum(),
+    }
+}
+
+// swapchain formats the pipeline's 8 bit color output works with. the depth
+// format and the atlas' format are ones every device has to support.
+fn is_color_format(format: Format) -> bool {
+    match format {
+        Format::B8G8R8A8Unorm
+        | Format::B8G8R8A8Srgb
+        | Format::R8G8B8A8Unorm
+        | Format::R8G8B8A8Srgb
+        | Format::A8B8G8R8UnormPack32
+        | Format::A8B8G8R8SrgbPack32 => true,
+        _ => false,
+    }
+}
+
+// nothing the renderer does needs an optional feature. u32 indices are core,
+// but index values past 2^24 need full_draw_index_uint32, so it's turned on
+// where it's available in case a batch ever gets that big.
+fn device_features(physical: PhysicalDevice) -> Features {
+    Features {
+        full_draw_index_uint32: physical.supported_features().full_draw_index_uint32,
+        ..Features::none()
+    }
+}
+
+// a family that does nothing but transfers can run uploads while the graphics
+// queue is busy drawing, not every device has one
+fn transfer_family(physical: PhysicalDevice) -> Option<QueueFamily> {
+    physical.queue_families().find(|&q| {
+        q.explicitly_supports_transfers() && !q.supports_graphics() && !q.supports_compute()
+    })
+}
+
+// arena buffers are shared between both queues rather than handed over from
+// one family to the other after a copy
+fn queue_families<'a>(queue: &'a Queue, transfer_queue: &'a Queue) -> Vec<QueueFamily<'a>> {
+    let mut families = vec![queue.family()];
+    if transfer_queue.family().id() != queue.family().id() {
+        families.push(transfer_queue.family());
+    }
+    families
+}
+
+// a host visible copy of `data` to upload from
+fn staging_buffer<T>(
+    device: Arc<Device>,
+    data: &[T],
+) -> Result<Arc<CpuAccessibleBuffer<[T]>>, RenderError>
+where
+    T: Copy + Send + Sync + 'static,
+{
+    Ok(CpuAccessibleBuffer::from_iter(
+        device,
+        BufferUsage::transfer_source(),
+        data.iter().cloned(),
+    )?)
+}
+
+// records copying `range` of `staging` into an arena slice of the same length
+fn copy_range<T>(
+    builder: AutoCommandBufferBuilder,
+    staging: &Arc<CpuAccessibleBuffer<[T]>>,
+    range: Range<usize>,
+    target: &Arc<ArenaSlice<T>>,
+) -> Result<AutoCommandBufferBuilder, RenderError>
+where
+    T: Send + Sync + 'static,
+{
+    let source = BufferSlice::from_typed_buffer_access(staging.clone())
+        .slice(range)
+        .ok_or_else(|| RenderError::Unexpected("copying past the staging buffer".to_string()))?;
+    Ok(builder.copy_buffer(source, target.clone())?)
+}
+
+// copies every mip level into a new sampled image, the returned future has to
+// be waited on (or joined with) before the image is used
+fn upload_texture(
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    atlas: &AtlasImage,
+) -> Result<(Arc<ImmutableImage<Format>>, Box<GpuFuture>), RenderError> {
+    let (texture, init) = ImmutableImage::uninitialized(
+        device.clone(),
+        Dimensions::Dim2d {
+            width: atlas.width,
+            height: atlas.height,
+        },
+        Format::R8G8B8A8Srgb,
+        atlas.levels.len() as u32,
+        ImageUsage {
+            transfer_destination: true,
+            sampled: true,
+            ..ImageUsage::none()
+        },
+        ImageLayout::ShaderReadOnlyOptimal,
+        Some(queue.family()),
+    )?;
+    let init = Arc::new(init);
+
+    let mut builder = AutoCommandBufferBuilder::new(device.clone(), queue.family())?;
+    for (level, pixels) in atlas.levels.iter().enumerate() {
+        let width = (atlas.width >> level).max(1);
+        let height = (atlas.height >> level).max(1);
+        let source = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::transfer_source(),
+            pixels.iter().cloned(),
+        )?;
+
+        builder = builder.copy_buffer_to_image_dimensions(
+            source,
+            init.clone(),
+            [0, 0, 0],
+            [width, height, 1],
+            0,
+            1,
+            level as u32,
+        )?;
+    }
+
+    let future = builder.build()?.execute(queue)?;
+    Ok((texture, Box::new(future) as Box<GpuFuture>))
+}
+
+// vulkan's clip space has y pointing down, unlike OpenGL which cgmath targets,
+// so the projection flips y to let the rest of the code keep y up
+fn projection(dimensions: [u32; 2], perspective: &Perspective) -> Matrix4<f32> {
+    let flip_y = Matrix4::from_nonuniform_scale(1.0, -1.0, 1.0);
+    flip_y
+        * cgmath::perspective(
+            cgmath::Deg(perspective.fov),
+            { dimensions[0] as f32 / dimensions[1] as f32 },
+            perspective.near,
+            perspective.far,
+        )
+}
+
+// fifo is the only mode every device has to support
+fn present_mode(vsync: Vsync, supported: SupportedPresentModes) -> PresentMode {
+    let wanted = match vsync {
+        Vsync::On => PresentMode::Fifo,
+        Vsync::Off => PresentMode::Immediate,
+        Vsync::Mailbox => PresentMode::Mailbox,
+    };
+    if supported.supports(wanted) {
+        wanted
+    } else {
+        warn!("{:?} presenting isn't supported, using Fifo", wanted);
+        PresentMode::Fifo
+    }
+}
@@ -0,0 +1,510 @@
+use toml;
+
+use std::error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::path::PathBuf;
+
+use chunk::CHUNK_SIZE;
+
+// read from the working directory when `--config` isn't given, it's fine for
+// it not to exist
+pub const DEFAULT_PATH: &'static str = "settings.toml";
+
+pub const USAGE: &'static str = "usage: rscraft [options]
+
+options:
+    --config <path>           settings file to read (default settings.toml)
+    --print-config            print the resulting settings and exit
+    --list-devices            print what each gpu supports and exit
+    --fov <degrees>           vertical field of view
+    --render-distance <n>     chunks kept loaded around the camera
+    --vsync <on|off|mailbox>  how frames are presented
+    --gpu <index>             physical device to render with, by default
+                              the best scoring one
+    --width <pixels>          window width
+    --height <pixels>         window height
+    --fullscreen              fill the primary monitor
+    --windowed                open a window, the default
+    --bind <action>=<key>     e.g. --bind forward=Up
+    --validation              turn on the Vulkan validation layer and log
+                              what it reports
+
+    rscraft --headless [out.png] [width height] [model.obj]";
+
+const NEAR_PLANE: f32 = 0.01;
+
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Vsync {
+    // waits for vertical blank, never tears
+    On,
+    // presents straight away, may tear
+    Off,
+    // doesn't tear or block, newer frames replace queued ones
+    Mailbox,
+}
+
+// key names are winit's `VirtualKeyCode` variants, e.g. "W", "Space" or
+// "LShift"
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Keys {
+    pub forward: String,
+    pub back: String,
+    pub left: String,
+    pub right: String,
+    // also jumps while walking
+    pub up: String,
+    pub down: String,
+    // switches between flying and walking
+    pub walk: String,
+    pub quit: String,
+}
+
+impl Keys {
+    pub fn bindings(&self) -> [(&'static str, &str); 8] {
+        [
+            ("forward", &self.forward),
+            ("back", &self.back),
+            ("left", &self.left),
+            ("right", &self.right),
+            ("up", &self.up),
+            ("down", &self.down),
+            ("walk", &self.walk),
+            ("quit", &self.quit),
+        ]
+    }
+
+    fn binding_mut(&mut self, action: &str) -> Option<&mut String> {
+        match action {
+            "forward" => Some(&mut self.forward),
+            "back" => Some(&mut self.back),
+            "left" => Some(&mut self.left),
+            "right" => Some(&mut self.right),
+            "up" => Some(&mut self.up),
+            "down" => Some(&mut self.down),
+            "walk" => Some(&mut self.walk),
+            "quit" => Some(&mut self.quit),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Keys {
+    fn default() -> Keys {
+        Keys {
+            forward: "W".to_string(),
+            back: "S".to_string(),
+            left: "A".to_string(),
+            right: "D".to_string(),
+            up: "Space".to_string(),
+            down: "LShift".to_string(),
+            walk: "F".to_string(),
+            quit: "Escape".to_string(),
+        }
+    }
+}
+
+// anything missing from the file keeps its default
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    // vertical, in degrees
+    pub fov: f32,
+    // in chunks
+    pub render_distance: i32,
+    pub vsync: Vsync,
+    // the best scoring device when not set
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub gpu: Option<usize>,
+    pub width: u32,
+    pub height: u32,
+    pub fullscreen: bool,
+    // for development, needs the Vulkan SDK
+    pub validation: bool,
+    pub keys: Keys,
+}
+
+impl Default for Settings {
+    fn default() -> Settings {
+        Settings {
+            fov: 90.0,
+            render_distance: 8,
+            vsync: Vsync::On,
+            gpu: None,
+            width: 1024,
+            height: 768,
+            fullscreen: false,
+            validation: false,
+            keys: Keys::default(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    Usage(String),
+    Invalid(String),
+}
+
+impl fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SettingsError::Io(ref path, ref err) => {
+                write!(f, "failed to read {}: {}", path.display(), err)
+            }
+            SettingsError::Parse(ref path, ref err) => write!(f, "{}: {}", path.display(), err),
+            SettingsError::Usage(ref message) => write!(f, "{}\n\n{}", message, USAGE),
+            SettingsError::Invalid(ref message) => write!(f, "invalid settings: {}", message),
+        }
+    }
+}
+
+impl error::Error for SettingsError {
+    fn description(&self) -> &str {
+        match *self {
+            SettingsError::Io(_, ref err) => err.description(),
+            SettingsError::Parse(..) => "failed to parse settings",
+            SettingsError::Usage(ref message) => message,
+            SettingsError::Invalid(ref message) => message,
+        }
+    }
+}
+
+// what the command line asked for
+pub struct Options {
+    pub settings: Settings,
+    pub print_config: bool,
+    pub list_devices: bool,
+}
+
+impl Settings {
+    // a missing file gives the defaults unless `required`
+    pub fn load<P: AsRef<Path>>(path: P, required: bool) -> Result<Settings, SettingsError> {
+        let path = path.as_ref();
+        let text = match fs::read_to_string(path) {
+            Ok(text) => text,
+            Err(ref err) if err.kind() == io::ErrorKind::NotFound && !required => {
+                return Ok(Settings::default());
+            }
+            Err(err) => return Err(SettingsError::Io(path.to_path_buf(), err)),
+        };
+        toml::from_str(&text).map_err(|err| SettingsError::Parse(path.to_path_buf(), err))
+    }
+
+    // reads the settings file and applies the flags in `args` (without the
+    // program name) on top of it
+    pub fn from_args(args: &[String]) -> Result<Options, SettingsError> {
+        // the file has to be read first so that flags can override it no
+        // matter where `--config` appears
+        let mut config = None;
+        let mut rest = args.iter();
+        while let Some(arg) = rest.next() {
+            if arg == "--config" {
+                config = Some(flag_value(arg, rest.next())?.to_string());
+            }
+        }
+        let mut settings = match config {
+            Some(ref path) => Settings::load(path, true)?,
+            None => Settings::load(DEFAULT_PATH, false)?,
+        };
+
+        let mut print_config = false;
+        let mut list_devices = false;
+        let mut rest = args.iter();
+        while let Some(arg) = rest.next() {
+            match arg.as_str() {
+                "--config" => {
+                    rest.next();
+                }
+                "--print-config" => print_config = true,
+                "--list-devices" => list_devices = true,
+                "--fullscreen" => settings.fullscreen = true,
+                "--windowed" => settings.fullscreen = false,
+                "--validation" => settings.validation = true,
+                "--fov" => settings.fov = parse_flag(arg, rest.next())?,
+                "--render-distance" => settings.render_distance = parse_flag(arg, rest.next())?,
+                "--gpu" => settings.gpu = Some(parse_flag(arg, rest.next())?),
+                "--width" => settings.width = parse_flag(arg, rest.next())?,
+                "--height" => settings.height = parse_flag(arg, rest.next())?,
+                "--vsync" => {
+                    settings.vsync = match flag_value(arg, rest.next())? {
+                        "on" => Vsync::On,
+                        "off" => Vsync::Off,
+                        "mailbox" => Vsync::Mailbox,
+                        other => {
+                            return Err(SettingsError::Usage(format!(
+                                "--vsync expects on, off or mailbox, not {:?}",
+                                other
+                            )));
+                        }
+                    }
+                }
+                "--bind" => {
+                    let value = flag_value(arg, rest.next())?;
+                    let mut parts = value.splitn(2, '=');
+                    let action = parts.next().unwrap_or("");
+                    let key = match parts.next() {
+                        Some(key) => key,
+                        None => {
+                            return Err(SettingsError::Usage(format!(
+                                "--bind expects <action>=<key>, not {:?}",
+                                value
+                            )));
+                        }
+                    };
+                    match settings.keys.binding_mut(action) {
+                        Some(binding) => *binding = key.to_string(),
+                        None => {
+                            return Err(SettingsError::Usage(format!(
+                                "--bind: there is no {:?} action",
+                                action
+                            )));
+                        }
+                    }
+                }
+                other => {
+                    return Err(SettingsError::Usage(format!("unknown option {:?}", other)));
+                }
+            }
+        }
+
+        settings.validate()?;
+        Ok(Options {
+            settings: settings,
+            print_config: print_config,
+            list_devices: list_devices,
+        })
+    }
+
+    // key names are checked when they're turned into key codes, see
+    // `camera::KeyBindings`
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        if !(self.fov >= 10.0 && self.fov <= 170.0) {
+            return Err(invalid(format!(
+                "fov must be between 10 and 170 degrees, not {}",
+                self.fov
+            )));
+        }
+        if self.render_distance < 1 || self.render_distance > 32 {
+            return Err(invalid(format!(
+                "render_distance must be between 1 and 32 chunks, not {}",
+                self.render_distance
+            )));
+        }
+        if self.width == 0 || self.height == 0 || self.width > 16384 || self.height > 16384 {
+            return Err(invalid(format!(
+                "the window size must be between 1x1 and 16384x16384, not {}x{}",
+                self.width, self.height
+            )));
+        }
+
+        let bindings = self.keys.bindings();
+        for (i, &(action, key)) in bindings.iter().enumerate() {
+            if let Some(&(other, _)) = bindings[..i].iter().find(|&&(_, k)| k == key) {
+                return Err(invalid(format!(
+                    "{:?} is bound to both {} and {}",
+                    key, other, action
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    pub fn to_toml(&self) -> String {
+        toml::to_string(self).expect("failed to serialize settings")
+    }
+
+    // far enough to see the corners of the furthest loaded chunks
+    pub fn far_plane(&self) -> f32 {
+        (self.render_distance + 1) as f32 * CHUNK_SIZE as f32 * 3.0f32.sqrt()
+    }
+
+    pub fn near_plane(&self) -> f32 {
+        NEAR_PLANE
+    }
+}
+
+fn invalid(message: String) -> SettingsError {
+    SettingsError::Invalid(message)
+}
+
+fn flag_value<'a>(flag: &str, value: Option<&'a String>) -> Result<&'a str, SettingsError> {
+    value
+        .map(|value| value.as_str())
+        .ok_or_else(|| SettingsError::Usage(format!("{} expects a value", flag)))
+}
+
+fn parse_flag<T: ::std::str::FromStr>(
+    flag: &str,
+    value: Option<&String>,
+) -> Result<T, SettingsError> {
+    let value = flag_value(flag, value)?;
+    value
+        .parse()
+        .map_err(|_| SettingsError::Usage(format!("{}: can't understand {:?}", flag, value)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+
+    fn args(args: &[&str]) -> Vec<String> {
+        // keeps a settings.toml in the working directory out of the tests
+        let mut all = vec!["--config".to_string(), missing_file()];
+        all.extend(args.iter().map(|arg| arg.to_string()));
+        all
+    }
+
+    fn missing_file() -> String {
+        env::temp_dir()
+            .join("rscraft-no-such-settings.toml")
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn write_file(name: &str, text: &str) -> String {
+        let path = env::temp_dir().join(format!("rscraft-settings-{}.toml", name));
+        fs::write(&path, text).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn parse(args: &[&str]) -> Result<Options, SettingsError> {
+        let path = write_file("empty", "");
+        let mut all = vec!["--config".to_string(), path];
+        all.extend(args.iter().map(|arg| arg.to_string()));
+        Settings::from_args(&all)
+    }
+
+    #[test]
+    fn missing_files_are_only_fine_when_not_asked_for() {
+        match Settings::from_args(&args(&[])) {
+            Err(SettingsError::Io(..)) => {}
+            other => panic!("expected an io error, got {:?}", other.map(|o| o.settings)),
+        }
+        assert_eq!(
+            Settings::load(missing_file(), false).unwrap(),
+            Settings::default()
+        );
+    }
+
+    #[test]
+    fn files_only_override_what_they_mention() {
+        let path = write_file(
+            "partial",
+            "fov = 70.0\nvsync = \"mailbox\"\n\n[keys]\nforward = \"Up\"\n",
+        );
+        let settings = Settings::load(path, true).unwrap();
+        assert_eq!(settings.fov, 70.0);
+        assert_eq!(settings.vsync, Vsync::Mailbox);
+        assert_eq!(settings.keys.forward, "Up");
+        assert_eq!(settings.keys.back, "S");
+        assert_eq!(settings.render_distance, 8);
+    }
+
+    #[test]
+    fn unknown_fields_are_rejected() {
+        let path = write_file("typo", "render_distanse = 4\n");
+        match Settings::load(path, true) {
+            Err(SettingsError::Parse(..)) => {}
+            other => panic!("expected a parse error, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn flags_override_the_file() {
+        let path = write_file("flags", "fov = 70.0\nwidth = 640\n");
+        let options = Settings::from_args(&[
+            "--fov".to_string(),
+            "100".to_string(),
+            "--config".to_string(),
+            path,
+            "--gpu".to_string(),
+            "1".to_string(),
+            "--vsync".to_string(),
+            "off".to_string(),
+            "--fullscreen".to_string(),
+            "--validation".to_string(),
+            "--bind".to_string(),
+            "walk=G".to_string(),
+        ])
+        .unwrap();
+        let settings = options.settings;
+        assert_eq!(settings.fov, 100.0);
+        assert_eq!(settings.width, 640);
+        assert_eq!(settings.gpu, Some(1));
+        assert_eq!(settings.vsync, Vsync::Off);
+        assert!(settings.fullscreen);
+        assert!(settings.validation);
+        assert_eq!(settings.keys.walk, "G");
+        assert!(!options.print_config);
+        assert!(!options.list_devices);
+    }
+
+    #[test]
+    fn bad_flags() {
+        for bad in &[
+            &["--fov"][..],
+            &["--fov", "wide"],
+            &["--vsync", "sometimes"],
+            &["--bind", "forward"],
+            &["--bind", "fly=G"],
+            &["--frobnicate"],
+        ] {
+            match parse(bad) {
+                Err(SettingsError::Usage(_)) => {}
+                other => panic!("{:?}: expected a usage error, got {:?}", bad, other.is_ok()),
+            }
+        }
+    }
+
+    #[test]
+    fn out_of_range_values_are_rejected() {
+        for bad in &[
+            &["--fov", "0"][..],
+            &["--fov", "180"],
+            &["--fov", "NaN"],
+            &["--render-distance", "0"],
+            &["--width", "0"],
+            &["--bind", "walk=W"],
+        ] {
+            match parse(bad) {
+                Err(SettingsError::Invalid(_)) => {}
+                other => panic!(
+                    "{:?}: expected it to be invalid, got {:?}",
+                    bad,
+                    other.is_ok()
+                ),
+            }
+        }
+        assert!(parse(&["--fov", "60", "--render-distance", "16"]).is_ok());
+    }
+
+    #[test]
+    fn printed_settings_read_back_the_same() {
+        let options = parse(&["--print-config", "--gpu", "2", "--bind", "up=J"]).unwrap();
+        assert!(options.print_config);
+        assert!(parse(&["--list-devices"]).unwrap().list_devices);
+        let path = write_file("printed", &options.settings.to_toml());
+        assert_eq!(Settings::load(path, true).unwrap(), options.settings);
+
+        let defaults = Settings::default().to_toml();
+        assert!(!defaults.contains("gpu"));
+        let path = write_file("defaults", &defaults);
+        assert_eq!(Settings::load(path, true).unwrap(), Settings::default());
+    }
+
+    #[test]
+    fn far_plane_covers_the_render_distance() {
+        let settings = Settings::default();
+        let reach = (settings.render_distance * CHUNK_SIZE as i32) as f32;
+        assert!(settings.far_plane() > reach * 3.0f32.sqrt());
+    }
+}
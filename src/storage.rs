@@ -0,0 +1,860 @@
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+
+use std::collections::HashMap;
+use std::error;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
+use std::mem;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::mpsc;
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::thread;
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use arena::RangeAllocator;
+use chunk::Chunk;
+use chunk::ChunkPos;
+
+// region files hold a cube of REGION_SIZE^3 chunks
+pub const REGION_BITS: i32 = 3;
+pub const REGION_SIZE: usize = 1 << REGION_BITS;
+pub const REGION_VOLUME: usize = REGION_SIZE * REGION_SIZE * REGION_SIZE;
+
+// bump this whenever the region layout or the chunk encoding changes, files
+// with a newer version are refused rather than misread
+pub const FORMAT_VERSION: u32 = 1;
+
+const MAGIC: &'static [u8; 4] = b"RSCR";
+const SECTOR_SIZE: u64 = 4096;
+// magic, version and then a (first sector, sector count) pair per chunk
+const HEADER_SIZE: u64 = 8 + REGION_VOLUME as u64 * 8;
+const HEADER_SECTORS: u32 = ((HEADER_SIZE + SECTOR_SIZE - 1) / SECTOR_SIZE) as u32;
+
+const COMPRESSION_ZLIB: u8 = 1;
+
+const FLUSH_INTERVAL_SECS: u64 = 5;
+
+#[derive(Debug)]
+pub enum StorageError {
+    Io(io::Error),
+    UnsupportedVersion { path: PathBuf, version: u32 },
+    Corrupt { path: PathBuf, message: String },
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StorageError::Io(ref err) => write!(f, "failed to access world storage: {}", err),
+            StorageError::UnsupportedVersion { ref path, version } => write!(
+                f,
+                "{}: unsupported region format version {} (expected at most {})",
+                path.display(),
+                version,
+                FORMAT_VERSION
+            ),
+            StorageError::Corrupt {
+                ref path,
+                ref message,
+            } => write!(f, "{}: {}", path.display(), message),
+        }
+    }
+}
+
+impl error::Error for StorageError {
+    fn description(&self) -> &str {
+        match *self {
+            StorageError::Io(ref err) => err.description(),
+            StorageError::UnsupportedVersion { .. } => "unsupported region format version",
+            StorageError::Corrupt { ref message, .. } => message,
+        }
+    }
+}
+
+impl StorageError {
+    // errors that come from what's in one region rather than from the
+    // filesystem, chunks in other regions can still be written
+    fn is_permanent(&self) -> bool {
+        match *self {
+            StorageError::Io(..) => false,
+            StorageError::UnsupportedVersion { .. } | StorageError::Corrupt { .. } => true,
+        }
+    }
+}
+
+impl From<io::Error> for StorageError {
+    fn from(err: io::Error) -> StorageError {
+        StorageError::Io(err)
+    }
+}
+
+fn region_of(pos: ChunkPos) -> [i32; 3] {
+    [
+        pos.x >> REGION_BITS,
+        pos.y >> REGION_BITS,
+        pos.z >> REGION_BITS,
+    ]
+}
+
+fn index_in_region(pos: ChunkPos) -> usize {
+    let local = |v: i32| (v & (REGION_SIZE as i32 - 1)) as usize;
+    (local(pos.y) * REGION_SIZE + local(pos.z)) * REGION_SIZE + local(pos.x)
+}
+
+fn read_u32(bytes: &[u8]) -> u32 {
+    (0..4).fold(0, |value, i| value | (bytes[i] as u32) << (i * 8))
+}
+
+fn write_u32(bytes: &mut Vec<u8>, value: u32) {
+    for i in 0..4 {
+        bytes.push((value >> (i * 8)) as u8);
+    }
+}
+
+// the file starts with a header holding the location of every chunk in the
+// region, chunks are stored in whole sectors as a length, a compression type
+// and the compressed chunk encoding. chunks are never written over, a new
+// version goes into free sectors and the old ones are only freed once the
+// header points at the new ones, so a crash halfway leaves the old chunk.
+struct RegionFile {
+    path: PathBuf,
+    file: File,
+    table: Vec<(u32, u32)>,
+    // sectors before `end_sector` that no chunk is using
+    free: RangeAllocator,
+    end_sector: u32,
+}
+
+impl RegionFile {
+    fn create(path: PathBuf) -> Result<RegionFile, StorageError> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(&path)?;
+
+        let mut header = Vec::with_capacity((HEADER_SECTORS as u64 * SECTOR_SIZE) as usize);
+        header.extend_from_slice(MAGIC);
+        write_u32(&mut header, FORMAT_VERSION);
+        header.resize((HEADER_SECTORS as u64 * SECTOR_SIZE) as usize, 0);
+        file.write_all(&header)?;
+
+        Ok(RegionFile {
+            path: path,
+            file: file,
+            table: vec![(0, 0); REGION_VOLUME],
+            free: RangeAllocator::new(0),
+            end_sector: HEADER_SECTORS,
+        })
+    }
+
+    fn open(path: PathBuf) -> Result<RegionFile, StorageError> {
+        let mut file = OpenOptions::new().read(true).write(true).open(&path)?;
+
+        let mut header = vec![0; HEADER_SIZE as usize];
+        if let Err(err) = file.read_exact(&mut header) {
+            if err.kind() == io::ErrorKind::UnexpectedEof {
+                return Err(StorageError::Corrupt {
+                    path: path,
+                    message: "truncated region header".to_string(),
+                });
+            }
+            return Err(err.into());
+        }
+        if &header[0..4] != MAGIC {
+            return Err(StorageError::Corrupt {
+                path: path,
+                message: "not a region file".to_string(),
+            });
+        }
+        let version = read_u32(&header[4..8]);
+        if version > FORMAT_VERSION {
+            return Err(StorageError::UnsupportedVersion {
+                path: path,
+                version: version,
+            });
+        }
+
+        let table = (0..REGION_VOLUME)
+            .map(|i| {
+                let entry = &header[8 + i * 8..];
+                (read_u32(&entry[0..4]), read_u32(&entry[4..8]))
+            })
+            .collect::<Vec<_>>();
+        let length = file.metadata()?.len();
+        let end_sector = ((length + SECTOR_SIZE - 1) / SECTOR_SIZE) as u32;
+        let end_sector = ::std::cmp::max(end_sector, HEADER_SECTORS);
+
+        Ok(RegionFile {
+            path: path,
+            file: file,
+            free: free_sectors(&table, end_sector),
+            table: table,
+            end_sector: end_sector,
+        })
+    }
+
+    fn corrupt(&self, message: String) -> StorageError {
+        StorageError::Corrupt {
+            path: self.path.clone(),
+            message: message,
+        }
+    }
+
+    fn read(&mut self, index: usize) -> Result<Option<Chunk>, StorageError> {
+        let (sector, count) = self.table[index];
+        if sector == 0 {
+            return Ok(None);
+        }
+        // the entry decides how much gets allocated, so it has to point at
+        // sectors that actually exist
+        let sectors = self.file.metadata()?.len() / SECTOR_SIZE;
+        if sector < HEADER_SECTORS || count == 0 || sector as u64 + count as u64 > sectors {
+            return Err(self.corrupt(format!(
+                "chunk {} is at sectors {}..{}, the file has {}",
+                index,
+                sector,
+                sector as u64 + count as u64,
+                sectors
+            )));
+        }
+
+        let mut data = vec![0; (count as u64 * SECTOR_SIZE) as usize];
+        self.file
+            .seek(SeekFrom::Start(sector as u64 * SECTOR_SIZE))?;
+        if self.file.read_exact(&mut data).is_err() {
+            return Err(self.corrupt(format!("chunk {} runs past the end of the file", index)));
+        }
+
+        let length = read_u32(&data[0..4]) as usize;
+        if length == 0 || 4 + length > data.len() {
+            return Err(self.corrupt(format!("chunk {} has a bad length {}", index, length)));
+        }
+        let compression = data[4];
+        let compressed = &data[5..4 + length];
+
+        let mut encoded = Vec::new();
+        match compression {
+            COMPRESSION_ZLIB => {
+                if let Err(err) = ZlibDecoder::new(compressed).read_to_end(&mut encoded) {
+                    return Err(self.corrupt(format!("chunk {}: {}", index, err)));
+                }
+            }
+            other => {
+                return Err(self.corrupt(format!(
+                    "chunk {} uses unknown compression {}",
+                    index, other
+                )))
+            }
+        }
+
+        match Chunk::decode(&encoded) {
+            Ok(chunk) => Ok(Some(chunk)),
+            Err(message) => Err(self.corrupt(format!("chunk {}: {}", index, message))),
+        }
+    }
+
+    fn write(&mut self, index: usize, chunk: &Chunk) -> Result<(), StorageError> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&chunk.encode())?;
+        let compressed = encoder.finish()?;
+
+        let mut data = Vec::with_capacity(5 + compressed.len());
+        write_u32(&mut data, compressed.len() as u32 + 1);
+        data.push(COMPRESSION_ZLIB);
+        data.extend_from_slice(&compressed);
+        let needed = ((data.len() as u64 + SECTOR_SIZE - 1) / SECTOR_SIZE) as u32;
+        data.resize((needed as u64 * SECTOR_SIZE) as usize, 0);
+
+        let sector = match self.free.alloc(needed as usize) {
+            Some(range) => range.start as u32,
+            None => {
+                let sector = self.end_sector;
+                self.end_sector += needed;
+                sector
+            }
+        };
+        // on failure the header may or may not point at the new sectors
+        // already, so they stay unused until the region is opened again
+        self.write_at(index, sector, &data)?;
+
+        // only now nothing points at the old sectors anymore
+        let (old_sector, old_count) = mem::replace(&mut self.table[index], (sector, needed));
+        if is_in_file(old_sector, old_count, self.end_sector) {
+            self.free
+                .free(old_sector as usize..(old_sector + old_count) as usize);
+        }
+        Ok(())
+    }
+
+    // the data is synced before the table entry is written and the entry
+    // before its old sectors can be handed out again
+    fn write_at(&mut self, index: usize, sector: u32, data: &[u8]) -> Result<(), StorageError> {
+        self.file
+            .seek(SeekFrom::Start(sector as u64 * SECTOR_SIZE))?;
+        self.file.write_all(data)?;
+        self.file.sync_data()?;
+
+        let mut entry = Vec::with_capacity(8);
+        write_u32(&mut entry, sector);
+        write_u32(&mut entry, (data.len() as u64 / SECTOR_SIZE) as u32);
+        self.file.seek(SeekFrom::Start(8 + index as u64 * 8))?;
+        self.file.write_all(&entry)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+// whether an entry points at whole sectors between the header and the end
+// of the file, damaged entries don't own any sectors
+fn is_in_file(sector: u32, count: u32, end_sector: u32) -> bool {
+    sector >= HEADER_SECTORS && count > 0 && sector as u64 + count as u64 <= end_sector as u64
+}
+
+// the sectors after the header that none of the entries use
+fn free_sectors(table: &[(u32, u32)], end_sector: u32) -> RangeAllocator {
+    let mut used = table
+        .iter()
+        .cloned()
+        .filter(|&(sector, count)| is_in_file(sector, count, end_sector))
+        .collect::<Vec<_>>();
+    used.sort();
+
+    let mut free = RangeAllocator::new(0);
+    let mut next = HEADER_SECTORS;
+    for (sector, count) in used {
+        if sector > next {
+            free.free(next as usize..sector as usize);
+        }
+        next = ::std::cmp::max(next, sector + count);
+    }
+    if end_sector > next {
+        free.free(next as usize..end_sector as usize);
+    }
+    free
+}
+
+// moves a region that can't be read aside for someone to look at and starts
+// a new one in its place. regions from a newer version are never replaced,
+// their chunks can't be saved until the game is updated.
+fn replace_corrupt(path: PathBuf, message: &str) -> Result<RegionFile, StorageError> {
+    let mut aside = path.with_extension("rgn.corrupt");
+    let mut i = 1;
+    while aside.exists() {
+        aside = path.with_extension(format!("rgn.corrupt.{}", i));
+        i += 1;
+    }
+    error!(
+        "{}: {}, moving it to {} and starting over",
+        path.display(),
+        message,
+        aside.display()
+    );
+    fs::rename(&path, &aside)?;
+    RegionFile::create(path)
+}
+
+struct Shared {
+    dir: PathBuf,
+    // saved chunks that haven't made it to disk yet
+    pending: Mutex<HashMap<ChunkPos, Chunk>>,
+    regions: Mutex<HashMap<[i32; 3], RegionFile>>,
+}
+
+impl Shared {
+    fn region_path(&self, region: [i32; 3]) -> PathBuf {
+        self.dir
+            .join(format!("r.{}.{}.{}.rgn", region[0], region[1], region[2]))
+    }
+
+    // writes out every pending chunk. the regions lock is held while the
+    // pending map is taken so a concurrent load either still finds the chunk
+    // in the map or waits for it to hit the file. chunks that fail to be
+    // written stay pending, after an io error the rest aren't tried until
+    // the next flush.
+    fn write_pending(&self) -> Result<(), StorageError> {
+        let mut regions = self.regions.lock().unwrap();
+        let pending = mem::replace(&mut *self.pending.lock().unwrap(), HashMap::new());
+
+        let mut result = Ok(());
+        let mut retry = false;
+        let mut unwritten = Vec::new();
+        for (pos, chunk) in pending {
+            if retry {
+                unwritten.push((pos, chunk));
+                continue;
+            }
+            if let Err(err) = self.write_chunk(&mut regions, pos, &chunk) {
+                retry = !err.is_permanent();
+                unwritten.push((pos, chunk));
+                if result.is_ok() {
+                    result = Err(err);
+                }
+            }
+        }
+
+        // keep what failed for the next attempt, unless it got saved again
+        // in the meantime
+        let mut pending = self.pending.lock().unwrap();
+        for (pos, chunk) in unwritten {
+            pending.entry(pos).or_insert(chunk);
+        }
+        result
+    }
+
+    fn write_chunk(
+        &self,
+        regions: &mut HashMap<[i32; 3], RegionFile>,
+        pos: ChunkPos,
+        chunk: &Chunk,
+    ) -> Result<(), StorageError> {
+        let region = region_of(pos);
+        if !regions.contains_key(&region) {
+            let path = self.region_path(region);
+            let file = if path.exists() {
+                match RegionFile::open(path.clone()) {
+                    Err(StorageError::Corrupt { message, .. }) => replace_corrupt(path, &message)?,
+                    file => file?,
+                }
+            } else {
+                RegionFile::create(path)?
+            };
+            regions.insert(region, file);
+        }
+        regions
+            .get_mut(&region)
+            .unwrap()
+            .write(index_in_region(pos), chunk)
+    }
+
+    fn read_chunk(&self, pos: ChunkPos) -> Result<Option<Chunk>, StorageError> {
+        let mut regions = self.regions.lock().unwrap();
+        let region = region_of(pos);
+        if !regions.contains_key(&region) {
+            let path = self.region_path(region);
+            if !path.exists() {
+                return Ok(None);
+            }
+            regions.insert(region, RegionFile::open(path)?);
+        }
+        regions.get_mut(&region).unwrap().read(index_in_region(pos))
+    }
+}
+
+// chunk persistence for a world directory. saves only go into memory, a
+// background thread writes them out every few seconds and once more when the
+// storage is dropped. it can be shared with the job pool, which loads chunks
+// on its worker threads.
+pub struct WorldStorage {
+    shared: Arc<Shared>,
+    shutdown: Mutex<mpsc::Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl WorldStorage {
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<WorldStorage, StorageError> {
+        fs::create_dir_all(dir.as_ref())?;
+
+        let shared = Arc::new(Shared {
+            dir: dir.as_ref().to_path_buf(),
+            pending: Mutex::new(HashMap::new()),
+            regions: Mutex::new(HashMap::new()),
+        });
+
+        let (shutdown, receiver) = mpsc::channel();
+        let thread = {
+            let shared = shared.clone();
+            thread::spawn(move || loop {
+                let stop = match receiver.recv_timeout(Duration::from_secs(FLUSH_INTERVAL_SECS)) {
+                    Err(RecvTimeoutError::Timeout) => false,
+                    _ => true,
+                };
+                if let Err(err) = shared.write_pending() {
+                    error!("failed to save chunks: {}", err);
+                }
+                if stop {
+                    break;
+                }
+            })
+        };
+
+        Ok(WorldStorage {
+            shared: shared,
+            shutdown: Mutex::new(shutdown),
+            thread: Some(thread),
+        })
+    }
+
+    // the most recently saved version of the chunk, or None if it was never
+    // saved
+    pub fn load_chunk(&self, pos: ChunkPos) -> Result<Option<Chunk>, StorageError> {
+        if let Some(chunk) = self.shared.pending.lock().unwrap().get(&pos) {
+            return Ok(Some(chunk.clone()));
+        }
+        self.shared.read_chunk(pos)
+    }
+
+    pub fn save_chunk(&self, pos: ChunkPos, chunk: &Chunk) {
+        self.shared
+            .pending
+            .lock()
+            .unwrap()
+            .insert(pos, chunk.clone());
+    }
+
+    // writes out all saved chunks right away instead of waiting for the
+    // background thread
+    pub fn flush(&self) -> Result<(), StorageError> {
+        self.shared.write_pending()
+    }
+}
+
+impl Drop for WorldStorage {
+    fn drop(&mut self) {
+        let _ = self.shutdown.lock().unwrap().send(());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use block;
+    use block::Block;
+    use chunk::CHUNK_SIZE;
+    use noise;
+
+    use std::env;
+    use std::process;
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rscraft-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // a chunk with a different mix of blocks for every seed
+    fn noisy_chunk(seed: u64, kinds: u64) -> Chunk {
+        let mut chunk = Chunk::new();
+        for y in 0..CHUNK_SIZE {
+            for z in 0..CHUNK_SIZE {
+                for x in 0..CHUNK_SIZE {
+                    let i = ((y * CHUNK_SIZE + z) * CHUNK_SIZE + x) as u64;
+                    let id = noise::mix(seed ^ i) % kinds;
+                    chunk.set(x, y, z, Block(id as u16));
+                }
+            }
+        }
+        chunk
+    }
+
+    #[test]
+    fn encoding_round_trips() {
+        let chunks = vec![
+            Chunk::new(),
+            Chunk::filled(block::STONE),
+            noisy_chunk(1, 2),
+            noisy_chunk(2, 5),
+            noisy_chunk(3, 300),
+        ];
+        for chunk in chunks {
+            let decoded = Chunk::decode(&chunk.encode()).unwrap();
+            assert_eq!(decoded.content_hash(), chunk.content_hash());
+            assert_eq!(decoded.bits_per_block(), chunk.bits_per_block());
+        }
+    }
+
+    #[test]
+    fn decoding_rejects_bad_data() {
+        let encoded = noisy_chunk(4, 5).encode();
+        assert!(Chunk::decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(Chunk::decode(&[]).is_err());
+        assert!(Chunk::decode(&[0, 0]).is_err());
+
+        // five palette entries but indices up to seven
+        let mut bad = encoded.clone();
+        for byte in bad[2 + 5 * 2..].iter_mut() {
+            *byte = 0xff;
+        }
+        assert!(Chunk::decode(&bad).is_err());
+    }
+
+    #[test]
+    fn chunks_survive_reopening() {
+        let dir = temp_dir("reopen");
+        // spread over several regions, including negative ones
+        let positions = vec![
+            ChunkPos::new(0, 0, 0),
+            ChunkPos::new(7, 7, 7),
+            ChunkPos::new(8, 0, 0),
+            ChunkPos::new(-1, -1, -1),
+            ChunkPos::new(-9, 3, 20),
+        ];
+        {
+            let storage = WorldStorage::open(&dir).unwrap();
+            for (i, &pos) in positions.iter().enumerate() {
+                storage.save_chunk(pos, &noisy_chunk(i as u64, 4 + i as u64));
+            }
+        }
+
+        let storage = WorldStorage::open(&dir).unwrap();
+        for (i, &pos) in positions.iter().enumerate() {
+            let chunk = storage.load_chunk(pos).unwrap().unwrap();
+            assert_eq!(
+                chunk.content_hash(),
+                noisy_chunk(i as u64, 4 + i as u64).content_hash()
+            );
+        }
+        assert!(storage
+            .load_chunk(ChunkPos::new(1, 0, 0))
+            .unwrap()
+            .is_none());
+        assert!(storage
+            .load_chunk(ChunkPos::new(100, 100, 100))
+            .unwrap()
+            .is_none());
+
+        drop(storage);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn saved_chunks_load_before_flushing() {
+        let dir = temp_dir("pending");
+        let storage = WorldStorage::open(&dir).unwrap();
+        let pos = ChunkPos::new(3, -2, 1);
+        let chunk = noisy_chunk(9, 3);
+
+        storage.save_chunk(pos, &chunk);
+        let loaded = storage.load_chunk(pos).unwrap().unwrap();
+        assert_eq!(loaded.content_hash(), chunk.content_hash());
+
+        storage.flush().unwrap();
+        let loaded = storage.load_chunk(pos).unwrap().unwrap();
+        assert_eq!(loaded.content_hash(), chunk.content_hash());
+
+        drop(storage);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn overwriting_grows_and_shrinks_chunks() {
+        let dir = temp_dir("overwrite");
+        let a = ChunkPos::new(0, 0, 0);
+        let b = ChunkPos::new(1, 0, 0);
+        let storage = WorldStorage::open(&dir).unwrap();
+
+        storage.save_chunk(a, &Chunk::filled(block::STONE));
+        storage.save_chunk(b, &Chunk::filled(block::DIRT));
+        storage.flush().unwrap();
+
+        // much larger than its sectors, has to move
+        let large = noisy_chunk(5, 300);
+        storage.save_chunk(a, &large);
+        storage.flush().unwrap();
+        // and back down again, fits where it is
+        storage.save_chunk(b, &Chunk::filled(block::SAND));
+        storage.flush().unwrap();
+        drop(storage);
+
+        let storage = WorldStorage::open(&dir).unwrap();
+        assert_eq!(
+            storage.load_chunk(a).unwrap().unwrap().content_hash(),
+            large.content_hash()
+        );
+        assert_eq!(
+            storage.load_chunk(b).unwrap().unwrap().content_hash(),
+            Chunk::filled(block::SAND).content_hash()
+        );
+
+        drop(storage);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn newer_versions_are_refused() {
+        let dir = temp_dir("version");
+        {
+            let storage = WorldStorage::open(&dir).unwrap();
+            storage.save_chunk(ChunkPos::new(0, 0, 0), &Chunk::filled(block::STONE));
+        }
+
+        let path = dir.join("r.0.0.0.rgn");
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[4] = FORMAT_VERSION as u8 + 1;
+        fs::write(&path, &bytes).unwrap();
+
+        let storage = WorldStorage::open(&dir).unwrap();
+        match storage.load_chunk(ChunkPos::new(0, 0, 0)) {
+            Err(StorageError::UnsupportedVersion { version, .. }) => {
+                assert_eq!(version, FORMAT_VERSION + 1)
+            }
+            _ => panic!("expected an unsupported version error"),
+        }
+
+        drop(storage);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_regions_are_reported() {
+        let dir = temp_dir("corrupt");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("r.0.0.0.rgn"), b"not a region").unwrap();
+
+        let storage = WorldStorage::open(&dir).unwrap();
+        match storage.load_chunk(ChunkPos::new(0, 0, 0)) {
+            Err(StorageError::Corrupt { .. }) => {}
+            _ => panic!("expected a corrupt region error"),
+        }
+
+        drop(storage);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn damaged_header_entries_are_reported() {
+        let dir = temp_dir("entries");
+        {
+            let storage = WorldStorage::open(&dir).unwrap();
+            storage.save_chunk(ChunkPos::new(0, 0, 0), &Chunk::filled(block::STONE));
+        }
+        let path = dir.join("r.0.0.0.rgn");
+        let good = fs::read(&path).unwrap();
+
+        // far too many sectors, inside the header and no sectors at all
+        let entries = vec![(HEADER_SECTORS, 0xffff_ffff), (1, 1), (HEADER_SECTORS, 0)];
+        for (sector, count) in entries {
+            let mut bytes = good.clone();
+            let mut entry = Vec::new();
+            write_u32(&mut entry, sector);
+            write_u32(&mut entry, count);
+            bytes[8..16].copy_from_slice(&entry);
+            fs::write(&path, &bytes).unwrap();
+
+            let storage = WorldStorage::open(&dir).unwrap();
+            match storage.load_chunk(ChunkPos::new(0, 0, 0)) {
+                Err(StorageError::Corrupt { .. }) => {}
+                _ => panic!("expected a corrupt region error for {:?}", (sector, count)),
+            }
+        }
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn corrupt_regions_are_moved_aside() {
+        let dir = temp_dir("unusable");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("r.0.0.0.rgn"), b"not a region").unwrap();
+
+        let storage = WorldStorage::open(&dir).unwrap();
+        storage.save_chunk(ChunkPos::new(0, 0, 0), &Chunk::filled(block::STONE));
+        storage.flush().unwrap();
+        drop(storage);
+
+        assert_eq!(
+            fs::read(dir.join("r.0.0.0.rgn.corrupt")).unwrap(),
+            b"not a region"
+        );
+        let storage = WorldStorage::open(&dir).unwrap();
+        assert_eq!(
+            storage
+                .load_chunk(ChunkPos::new(0, 0, 0))
+                .unwrap()
+                .unwrap()
+                .content_hash(),
+            Chunk::filled(block::STONE).content_hash()
+        );
+
+        drop(storage);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn chunks_of_newer_regions_stay_pending() {
+        let dir = temp_dir("newer");
+        {
+            let storage = WorldStorage::open(&dir).unwrap();
+            storage.save_chunk(ChunkPos::new(0, 0, 0), &Chunk::filled(block::STONE));
+        }
+        let path = dir.join("r.0.0.0.rgn");
+        let mut bytes = fs::read(&path).unwrap();
+        bytes[4] = FORMAT_VERSION as u8 + 1;
+        fs::write(&path, &bytes).unwrap();
+
+        let storage = WorldStorage::open(&dir).unwrap();
+        let chunk = noisy_chunk(6, 3);
+        storage.save_chunk(ChunkPos::new(1, 0, 0), &chunk);
+        storage.save_chunk(ChunkPos::new(8, 0, 0), &Chunk::filled(block::DIRT));
+        match storage.flush() {
+            Err(StorageError::UnsupportedVersion { .. }) => {}
+            _ => panic!("expected an unsupported version error"),
+        }
+        // the chunk in the good region made it, the other one is kept
+        // without touching the file
+        assert!(storage
+            .load_chunk(ChunkPos::new(8, 0, 0))
+            .unwrap()
+            .is_some());
+        assert_eq!(
+            storage
+                .load_chunk(ChunkPos::new(1, 0, 0))
+                .unwrap()
+                .unwrap()
+                .content_hash(),
+            chunk.content_hash()
+        );
+        assert!(storage.flush().is_err());
+        assert_eq!(fs::read(&path).unwrap(), bytes);
+
+        drop(storage);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn rewritten_chunks_reuse_freed_sectors() {
+        let dir = temp_dir("sectors");
+        let pos = ChunkPos::new(0, 0, 0);
+        let path = dir.join("r.0.0.0.rgn");
+        let sectors = |path: &Path| fs::metadata(path).unwrap().len() / SECTOR_SIZE;
+        let storage = WorldStorage::open(&dir).unwrap();
+
+        // the second version can't go where the first one still is, the
+        // third one takes the sectors the first one freed
+        for kind in 1..4 {
+            storage.save_chunk(pos, &Chunk::filled(Block(kind)));
+            storage.flush().unwrap();
+        }
+        assert_eq!(sectors(&path), HEADER_SECTORS as u64 + 2);
+        drop(storage);
+
+        // free sectors are found again after reopening
+        let storage = WorldStorage::open(&dir).unwrap();
+        storage.save_chunk(pos, &Chunk::filled(Block(4)));
+        storage.flush().unwrap();
+        assert_eq!(sectors(&path), HEADER_SECTORS as u64 + 2);
+        assert_eq!(
+            storage.load_chunk(pos).unwrap().unwrap().content_hash(),
+            Chunk::filled(Block(4)).content_hash()
+        );
+
+        drop(storage);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
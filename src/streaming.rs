@@ -0,0 +1,479 @@
+use std::collections::HashSet;
+use std::mem;
+use std::sync::Arc;
+
+use block::Block;
+use block::BlockRegistry;
+use chunk;
+use chunk::ChunkPos;
+use jobs::JobPool;
+use jobs::JobResult;
+use light::Lighting;
+use mesher::ChunkMesh;
+use storage::WorldStorage;
+use world::World;
+
+// chunks are only unloaded once they're this many chunks past the load
+// radius, so moving back and forth across the edge doesn't keep loading and
+// unloading the same chunks
+pub const HYSTERESIS: i32 = 1;
+// cap on chunks being loaded or generated at once, so the job pool's queue
+// doesn't fill up with chunks the camera might have moved away from before
+// they're done
+const MAX_LOADING: usize = 64;
+
+// what changed in the world during an update, for the renderer
+#[derive(Default)]
+pub struct Changes {
+    pub meshes: Vec<(ChunkPos, ChunkMesh)>,
+    pub unloaded: Vec<ChunkPos>,
+}
+
+// keeps the chunks around the camera loaded. chunks within `radius` chunks
+// horizontally and `vertical_radius` vertically are loaded from disk or
+// generated on the job pool, chunks that end up past those by more than
+// HYSTERESIS are saved if they were edited and dropped, so no more than
+// `capacity` chunks are ever loaded. chunks that were only generated aren't
+// saved, generating them again gives the same result.
+pub struct ChunkManager {
+    world: World,
+    lighting: Lighting,
+    blocks: Arc<BlockRegistry>,
+    storage: Arc<WorldStorage>,
+    pool: JobPool,
+    radius: i32,
+    vertical_radius: i32,
+    center: Option<ChunkPos>,
+    // chunks edited since they were loaded or generated
+    dirty: HashSet<ChunkPos>,
+    loading: HashSet<ChunkPos>,
+    // chunks that couldn't be read, they're left alone rather than being
+    // generated over
+    failed: HashSet<ChunkPos>,
+    // chunks to mesh at the end of the update, collected so that chunks
+    // arriving together don't mesh their shared neighbours once each
+    remesh: HashSet<ChunkPos>,
+    meshes: Vec<(ChunkPos, ChunkMesh)>,
+}
+
+impl ChunkManager {
+    pub fn new(
+        storage: WorldStorage,
+        pool: JobPool,
+        blocks: Arc<BlockRegistry>,
+        radius: i32,
+        vertical_radius: i32,
+    ) -> ChunkManager {
+        ChunkManager {
+            world: World::new(),
+            lighting: Lighting::new(),
+            blocks: blocks,
+            storage: Arc::new(storage),
+            pool: pool,
+            radius: radius,
+            vertical_radius: vertical_radius,
+            center: None,
+            dirty: HashSet::new(),
+            loading: HashSet::new(),
+            failed: HashSet::new(),
+            remesh: HashSet::new(),
+            meshes: Vec::new(),
+        }
+    }
+
+    pub fn world(&self) -> &World {
+        &self.world
+    }
+
+    // the most chunks that can be loaded at once
+    pub fn capacity(&self) -> usize {
+        let width = 2 * (self.radius + HYSTERESIS) + 1;
+        let height = 2 * (self.vertical_radius + HYSTERESIS) + 1;
+        (width * width * height) as usize
+    }
+
+    fn within(&self, pos: ChunkPos, center: ChunkPos, slack: i32) -> bool {
+        in_area(
+            pos,
+            center,
+            self.radius + slack,
+            self.vertical_radius + slack,
+        )
+    }
+
+    // moves the loaded area to be around `center` and collects whatever the
+    // workers finished since the last update
+    pub fn update(&mut self, center: ChunkPos) -> Changes {
+        let mut unloaded = Vec::new();
+        if self.center != Some(center) {
+            self.center = Some(center);
+            self.pool
+                .set_view(center, self.radius.max(self.vertical_radius) + HYSTERESIS);
+            unloaded = self.unload_outside(center);
+        }
+
+        for result in self.pool.poll() {
+            match result {
+                JobResult::Loaded(pos, chunk) | JobResult::Generated(pos, chunk) => {
+                    if self.loading.remove(&pos) && self.within(pos, center, HYSTERESIS) {
+                        self.world.insert_chunk(pos, chunk);
+                        self.chunk_added(pos);
+                    }
+                }
+                JobResult::LoadFailed(pos, err) => {
+                    if self.loading.remove(&pos) {
+                        error!("couldn't load chunk {:?}: {}", pos, err);
+                        self.failed.insert(pos);
+                    }
+                }
+                JobResult::Meshed(pos, data) => {
+                    if self.world.chunk(pos).is_some() {
+                        self.meshes.push((pos, data));
+                    }
+                }
+            }
+        }
+
+        self.load_missing(center);
+
+        for pos in mem::replace(&mut self.remesh, HashSet::new()) {
+            if self.world.chunk(pos).is_some() {
+                self.pool.mesh(&self.world, &self.lighting, pos);
+            }
+        }
+
+        Changes {
+            meshes: mem::replace(&mut self.meshes, Vec::new()),
+            unloaded: unloaded,
+        }
+    }
+
+    // true when everything in range is loaded and nothing is left to do
+    pub fn is_idle(&self) -> bool {
+        let center = match self.center {
+            Some(center) => center,
+            None => return true,
+        };
+        self.loading.is_empty()
+            && self.remesh.is_empty()
+            && self.pool.pending() == 0
+            && self.missing(center).is_empty()
+    }
+
+    fn unload_outside(&mut self, center: ChunkPos) -> Vec<ChunkPos> {
+        let leaving: Vec<_> = self
+            .world
+            .chunks()
+            .map(|(&pos, _)| pos)
+            .filter(|&pos| !self.within(pos, center, HYSTERESIS))
+            .collect();
+        for &pos in &leaving {
+            if let Some(chunk) = self.world.remove_chunk(pos) {
+                if self.dirty.remove(&pos) {
+                    self.storage.save_chunk(pos, &chunk);
+                }
+            }
+            self.lighting.remove_chunk(pos);
+        }
+
+        // the pool drops the jobs too, they're asked for again once they're
+        // back in range
+        let (radius, vertical_radius) =
+            (self.radius + HYSTERESIS, self.vertical_radius + HYSTERESIS);
+        let keep = |pos: &ChunkPos| in_area(*pos, center, radius, vertical_radius);
+        self.loading.retain(&keep);
+        self.failed.retain(&keep);
+        leaving
+    }
+
+    // chunks in range that aren't loaded or on their way, closest first
+    fn missing(&self, center: ChunkPos) -> Vec<ChunkPos> {
+        let mut missing = Vec::new();
+        for dy in -self.vertical_radius..self.vertical_radius + 1 {
+            for dz in -self.radius..self.radius + 1 {
+                for dx in -self.radius..self.radius + 1 {
+                    let pos = center.offset(dx, dy, dz);
+                    if self.world.chunk(pos).is_none()
+                        && !self.loading.contains(&pos)
+                        && !self.failed.contains(&pos)
+                    {
+                        missing.push(pos);
+                    }
+                }
+            }
+        }
+        missing.sort_by_key(|pos| {
+            let (dx, dy, dz) = (pos.x - center.x, pos.y - center.y, pos.z - center.z);
+            dx * dx + dy * dy + dz * dz
+        });
+        missing
+    }
+
+    fn load_missing(&mut self, center: ChunkPos) {
+        for pos in self.missing(center) {
+            if self.loading.len() == MAX_LOADING {
+                break;
+            }
+            self.loading.insert(pos);
+            self.pool.load(&self.storage, pos);
+        }
+    }
+
+    // lights a new chunk and remeshes it along with the chunks around it,
+    // which lose the faces along their shared borders
+    fn chunk_added(&mut self, pos: ChunkPos) {
+        let changed = self.lighting.add_chunk(&self.world, &self.blocks, pos);
+        self.remesh.extend(changed);
+        for dz in -1..2 {
+            for dy in -1..2 {
+                for dx in -1..2 {
+                    self.remesh.insert(pos.offset(dx, dy, dz));
+                }
+            }
+        }
+    }
+
+    // changes one block and relights around it, the meshes follow with the
+    // next update. blocks in chunks that aren't loaded can't be changed,
+    // returns false for those.
+    pub fn set_block(&mut self, x: i32, y: i32, z: i32, block: Block) -> bool {
+        let pos = ChunkPos::containing(x, y, z);
+        if self.world.chunk(pos).is_none() {
+            return false;
+        }
+        self.world.set_block(x, y, z, block);
+        self.dirty.insert(pos);
+
+        let changed = self.lighting.update(&self.world, &self.blocks, x, y, z);
+        self.remesh.extend(changed);
+        self.remesh.extend(chunk::chunks_touching(x, y, z));
+        true
+    }
+
+    // writes every changed chunk to storage
+    pub fn save_all(&mut self) {
+        for pos in self.dirty.drain() {
+            if let Some(chunk) = self.world.chunk(pos) {
+                self.storage.save_chunk(pos, chunk);
+            }
+        }
+    }
+}
+
+fn in_area(pos: ChunkPos, center: ChunkPos, radius: i32, vertical_radius: i32) -> bool {
+    (pos.x - center.x).abs() <= radius
+        && (pos.z - center.z).abs() <= radius
+        && (pos.y - center.y).abs() <= vertical_radius
+}
+
+impl Drop for ChunkManager {
+    fn drop(&mut self) {
+        self.save_all();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use atlas::Atlas;
+    use block;
+    use chunk::Chunk;
+    use std::env;
+    use std::fs;
+    use std::path::PathBuf;
+    use std::process;
+    use std::thread;
+    use std::time::Duration;
+    use std::time::Instant;
+    use worldgen::WorldGenerator;
+
+    // solid stone below y = 0, so that lighting and meshing stay cheap and
+    // the tests are about what gets loaded where
+    struct Bedrock;
+
+    impl WorldGenerator for Bedrock {
+        fn generate(&self, pos: ChunkPos) -> Chunk {
+            if pos.y < 0 {
+                Chunk::filled(block::STONE)
+            } else {
+                Chunk::new()
+            }
+        }
+    }
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("rscraft-{}-{}", name, process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn manager(dir: &PathBuf, radius: i32) -> ChunkManager {
+        let storage = WorldStorage::open(dir).unwrap();
+        let blocks = Arc::new(block::test_registry());
+        let pool = JobPool::new(4, Arc::new(Bedrock), Arc::new(Atlas::new()), blocks.clone());
+        ChunkManager::new(storage, pool, blocks, radius, 1)
+    }
+
+    // updates until everything around `center` is loaded, returning the
+    // chunks that were unloaded on the way
+    fn settle(manager: &mut ChunkManager, center: ChunkPos) -> Vec<ChunkPos> {
+        let start = Instant::now();
+        let mut unloaded = Vec::new();
+        loop {
+            let changes = manager.update(center);
+            unloaded.extend(changes.unloaded);
+            assert!(manager.world().len() <= manager.capacity());
+            if manager.is_idle() {
+                return unloaded;
+            }
+            assert!(start.elapsed() < Duration::from_secs(60), "never settled");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    fn assert_loaded_around(manager: &ChunkManager, center: ChunkPos) {
+        for (&pos, _) in manager.world().chunks() {
+            assert!(
+                manager.within(pos, center, HYSTERESIS),
+                "{:?} is loaded around {:?}",
+                pos,
+                center
+            );
+        }
+        for dy in -manager.vertical_radius..manager.vertical_radius + 1 {
+            for dz in -manager.radius..manager.radius + 1 {
+                for dx in -manager.radius..manager.radius + 1 {
+                    let pos = center.offset(dx, dy, dz);
+                    assert!(manager.world().chunk(pos).is_some(), "{:?} missing", pos);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn follows_the_camera() {
+        let dir = temp_dir("streaming-follow");
+        let mut manager = manager(&dir, 1);
+
+        let mut center = ChunkPos::new(0, -2, 0);
+        settle(&mut manager, center);
+        assert_loaded_around(&manager, center);
+
+        // fly in a square, updating every frame without waiting for the
+        // workers, then let it catch up at each corner
+        for &(dx, dz) in [(1, 0), (0, 1), (-1, 0), (0, -1)].iter() {
+            for _ in 0..3 {
+                center = center.offset(dx, 0, dz);
+                for _ in 0..3 {
+                    manager.update(center);
+                    assert!(manager.world().len() <= manager.capacity());
+                }
+            }
+            settle(&mut manager, center);
+            assert_loaded_around(&manager, center);
+        }
+
+        drop(manager);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn small_moves_do_not_unload_anything() {
+        let dir = temp_dir("streaming-hysteresis");
+        let mut manager = manager(&dir, 1);
+
+        let a = ChunkPos::new(0, -2, 0);
+        let b = ChunkPos::new(1, -2, 0);
+        settle(&mut manager, a);
+        settle(&mut manager, b);
+        for _ in 0..5 {
+            assert!(settle(&mut manager, a).is_empty());
+            assert!(settle(&mut manager, b).is_empty());
+        }
+
+        // a second step the same way is past the slack
+        let c = ChunkPos::new(2, -2, 0);
+        let unloaded = settle(&mut manager, c);
+        assert_eq!(unloaded.len(), 3 * 3);
+        assert!(unloaded.iter().all(|pos| pos.x == -1));
+
+        drop(manager);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn changes_survive_unloading() {
+        let dir = temp_dir("streaming-save");
+        let mut manager = manager(&dir, 1);
+
+        let home = ChunkPos::new(0, -2, 0);
+        settle(&mut manager, home);
+        assert!(manager.set_block(5, -50, 7, block::TORCH));
+        assert!(!manager.set_block(500, -50, 7, block::TORCH));
+
+        // far enough away that home is unloaded, then back again
+        let away = ChunkPos::new(3, -2, 0);
+        let unloaded = settle(&mut manager, away);
+        assert!(unloaded.contains(&home));
+        assert!(manager.world().chunk(home).is_none());
+
+        settle(&mut manager, home);
+        assert_eq!(manager.world().get_block(5, -50, 7), block::TORCH);
+
+        // and across restarts
+        manager.set_block(5, -49, 7, block::AIR);
+        drop(manager);
+        let mut manager = self::manager(&dir, 1);
+        settle(&mut manager, home);
+        assert_eq!(manager.world().get_block(5, -50, 7), block::TORCH);
+        assert_eq!(manager.world().get_block(5, -49, 7), block::AIR);
+
+        drop(manager);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn untouched_chunks_are_not_saved() {
+        let dir = temp_dir("streaming-untouched");
+        let mut manager = manager(&dir, 1);
+
+        let home = ChunkPos::new(0, -2, 0);
+        settle(&mut manager, home);
+        let unloaded = settle(&mut manager, ChunkPos::new(3, -2, 0));
+        assert!(unloaded.contains(&home));
+        assert!(manager.storage.load_chunk(home).unwrap().is_none());
+
+        drop(manager);
+        let regions = fs::read_dir(&dir).unwrap().count();
+        assert_eq!(regions, 0, "generated chunks were written out");
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn meshes_arrive_for_loaded_chunks() {
+        let dir = temp_dir("streaming-meshes");
+        let mut manager = manager(&dir, 1);
+        let center = ChunkPos::new(0, -2, 0);
+
+        let start = Instant::now();
+        let mut meshed = HashSet::new();
+        loop {
+            let changes = manager.update(center);
+            for (pos, _) in changes.meshes {
+                assert!(manager.world().chunk(pos).is_some());
+                meshed.insert(pos);
+            }
+            if manager.is_idle() {
+                break;
+            }
+            assert!(start.elapsed() < Duration::from_secs(60));
+            thread::sleep(Duration::from_millis(1));
+        }
+        let loaded: HashSet<_> = manager.world().chunks().map(|(&pos, _)| pos).collect();
+        assert_eq!(meshed, loaded);
+
+        drop(manager);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
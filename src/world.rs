@@ -0,0 +1,139 @@
+use std::collections::hash_map;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use block;
+use block::Block;
+use chunk;
+use chunk::Chunk;
+use chunk::ChunkPos;
+
+// chunks are shared with snapshots handed to worker threads, and only copied
+// when they're written to while a snapshot still holds on to them
+pub struct World {
+    chunks: HashMap<ChunkPos, Arc<Chunk>>,
+}
+
+impl World {
+    pub fn new() -> World {
+        World {
+            chunks: HashMap::new(),
+        }
+    }
+
+    pub fn chunk(&self, pos: ChunkPos) -> Option<&Chunk> {
+        self.chunks.get(&pos).map(|chunk| &**chunk)
+    }
+
+    pub fn chunk_mut(&mut self, pos: ChunkPos) -> Option<&mut Chunk> {
+        self.chunks.get_mut(&pos).map(Arc::make_mut)
+    }
+
+    pub fn insert_chunk(&mut self, pos: ChunkPos, chunk: Chunk) -> Option<Chunk> {
+        self.chunks.insert(pos, Arc::new(chunk)).map(unwrap_chunk)
+    }
+
+    pub fn remove_chunk(&mut self, pos: ChunkPos) -> Option<Chunk> {
+        self.chunks.remove(&pos).map(unwrap_chunk)
+    }
+
+    pub fn chunks(&self) -> hash_map::Iter<ChunkPos, Arc<Chunk>> {
+        self.chunks.iter()
+    }
+
+    // a world holding only the chunk at `center` and the ones around it,
+    // which is everything meshing that chunk looks at
+    pub fn region(&self, center: ChunkPos) -> World {
+        let mut region = World::new();
+        for dz in -1..2 {
+            for dy in -1..2 {
+                for dx in -1..2 {
+                    let pos = center.offset(dx, dy, dz);
+                    if let Some(chunk) = self.chunks.get(&pos) {
+                        region.chunks.insert(pos, chunk.clone());
+                    }
+                }
+            }
+        }
+        region
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    // blocks in chunks that aren't loaded read as air
+    pub fn get_block(&self, x: i32, y: i32, z: i32) -> Block {
+        match self.chunks.get(&ChunkPos::containing(x, y, z)) {
+            Some(chunk) => chunk.get(chunk::local(x), chunk::local(y), chunk::local(z)),
+            None => block::AIR,
+        }
+    }
+
+    // creates the chunk if it isn't loaded yet, returns the previous block
+    pub fn set_block(&mut self, x: i32, y: i32, z: i32, block: Block) -> Block {
+        let pos = ChunkPos::containing(x, y, z);
+        if block.is_air() && !self.chunks.contains_key(&pos) {
+            return block::AIR;
+        }
+        let chunk = self
+            .chunks
+            .entry(pos)
+            .or_insert_with(|| Arc::new(Chunk::new()));
+        Arc::make_mut(chunk).set(chunk::local(x), chunk::local(y), chunk::local(z), block)
+    }
+}
+
+fn unwrap_chunk(chunk: Arc<Chunk>) -> Chunk {
+    Arc::try_unwrap(chunk).unwrap_or_else(|chunk| (*chunk).clone())
+}
+
+impl Default for World {
+    fn default() -> World {
+        World::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn blocks_land_in_the_right_chunk() {
+        let mut world = World::new();
+        world.set_block(-1, -1, -1, block::STONE);
+        world.set_block(0, 0, 0, block::DIRT);
+        world.set_block(-33, 32, 5, block::GRASS);
+
+        assert_eq!(world.len(), 3);
+        assert_eq!(world.get_block(-1, -1, -1), block::STONE);
+        assert_eq!(world.get_block(0, 0, 0), block::DIRT);
+        assert_eq!(world.get_block(-33, 32, 5), block::GRASS);
+        let chunk = world.chunk(ChunkPos::new(-1, -1, -1)).unwrap();
+        assert_eq!(chunk.get(31, 31, 31), block::STONE);
+        let chunk = world.chunk(ChunkPos::new(-2, 1, 0)).unwrap();
+        assert_eq!(chunk.get(31, 0, 5), block::GRASS);
+    }
+
+    #[test]
+    fn missing_chunks_read_as_air() {
+        let mut world = World::new();
+        assert_eq!(world.get_block(-100, 5, 7), block::AIR);
+        // clearing a block doesn't create a chunk
+        assert_eq!(world.set_block(-100, 5, 7, block::AIR), block::AIR);
+        assert_eq!(world.len(), 0);
+        assert_eq!(world.set_block(-100, 5, 7, block::STONE), block::AIR);
+        assert_eq!(world.set_block(-100, 5, 7, block::AIR), block::STONE);
+        assert_eq!(world.len(), 1);
+    }
+
+    #[test]
+    fn regions_share_chunks_until_written() {
+        let mut world = World::new();
+        world.set_block(0, 0, 0, block::STONE);
+        let region = world.region(ChunkPos::new(0, 0, 0));
+        world.set_block(0, 0, 0, block::DIRT);
+        assert_eq!(region.get_block(0, 0, 0), block::STONE);
+        assert_eq!(world.get_block(0, 0, 0), block::DIRT);
+    }
+}